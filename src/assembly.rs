@@ -0,0 +1,285 @@
+// kasm as a library: assemble source that's already in memory, without
+// reading or writing any files
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::errors::LocatedError;
+use crate::image::Segment;
+use crate::instructions::generate_instruction_set;
+use crate::instructions::Cpu;
+use crate::pass1::pass1_with_line;
+use crate::pass1::Address;
+use crate::pass1::LabelTable;
+use crate::pass1::Program;
+use crate::pass2::generate_with_line;
+use crate::pass2::ListingEntry;
+use crate::pass2::Output;
+use crate::scanner::Line;
+use crate::sources::expand;
+use crate::sources::FileSystem;
+use crate::sources::SourceFile;
+use crate::sources::SourceProvider;
+
+pub struct Options {
+    pub cpu: Cpu,         // The instructions available until a .cpu
+    pub fill: Option<u8>, // Overrides .fillbyte for the gaps
+    pub object: bool,     // Leave imported labels and checksums for kld
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            cpu: Cpu::W65C02,
+            fill: None,
+            object: false,
+        }
+    }
+}
+
+pub struct Assembly {
+    pub program: Program, // What pass1 made of the source, labels and all
+    pub output: Output,   // The image, and the bytes each line produced
+    pub fill: u8,         // The byte in the gaps, from the options or .fillbyte
+}
+
+impl Assembly {
+    pub fn symbols(&self) -> &LabelTable {
+        &self.program.symbol_table
+    }
+
+    // The image without its gaps. An object's imported labels are still holes.
+    pub fn segments(&self) -> Vec<Segment> {
+        self.output.image.segments()
+    }
+
+    // Every line that produced bytes, with its address, in address order
+    pub fn lines(&self) -> &[ListingEntry] {
+        &self.output.listing
+    }
+
+    // Lines of the source itself, not of the files it includes
+    pub fn address_of_line(&self, line: Line) -> Option<Address> {
+        self.lines()
+            .iter()
+            .find(|entry| entry.file == 0 && entry.line == line)
+            .map(|entry| entry.address)
+    }
+}
+
+pub struct Diagnostic {
+    pub code: ErrorCode,
+    pub message: String,
+    pub file: Option<String>, // The included file it's in, None for the source itself
+    pub line: Option<Line>,   // None when it isn't down to a single line
+    pub include_line: Option<Line>, // The source's .include that led to the file
+}
+
+// What stopped the source from assembling. kasm gives up at the first
+// error for now, so there's only ever one.
+pub struct Diagnostics {
+    pub errors: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    fn new(((code, message), location): LocatedError, files: &[SourceFile]) -> Diagnostics {
+        let (file, line) = match location {
+            Some((file, line)) => (file, Some(line)),
+            None => (0, None),
+        };
+
+        // Follow the includes back to the source
+        let mut include_line = None;
+        let mut at = file;
+        while let Some((from, line)) = files.get(at).and_then(|source| source.included_at) {
+            include_line = Some(line);
+            at = from;
+        }
+
+        Diagnostics {
+            errors: vec![Diagnostic {
+                code,
+                message,
+                file: files
+                    .get(file)
+                    .filter(|_| file > 0)
+                    .map(|source| source.name.to_string()),
+                line,
+                include_line,
+            }],
+        }
+    }
+
+    // The first error, for the command line
+    pub fn into_error(self) -> Error {
+        match self.errors.into_iter().next() {
+            Some(Diagnostic {
+                code,
+                message,
+                file: Some(file),
+                ..
+            }) => error(code, format!("{}: {}", file, message)),
+            Some(diagnostic) => error(diagnostic.code, diagnostic.message),
+            None => error(ErrorCode::NoError, String::new()),
+        }
+    }
+}
+
+// Includes are read from the filesystem
+pub fn assemble(source: &str, options: &Options) -> Result<Assembly, Diagnostics> {
+    assemble_with(source, None, options, &FileSystem)
+}
+
+// Includes are read from the provider, next to name when the source has one
+pub fn assemble_with(
+    source: &str,
+    name: Option<&str>,
+    options: &Options,
+    provider: &dyn SourceProvider,
+) -> Result<Assembly, Diagnostics> {
+    let unlocated = |err: Error| Diagnostics::new((err, None), &[]);
+
+    let mut files = vec![];
+    let scanned =
+        expand(source, name, provider, &mut files).map_err(|err| Diagnostics::new(err, &files))?;
+    let mut program =
+        pass1_with_line(scanned, options.cpu).map_err(|err| Diagnostics::new(err, &files))?;
+    program.files = files;
+    let mut output = generate_with_line(&generate_instruction_set(), &program)
+        .map_err(|err| Diagnostics::new(err, &program.files))?;
+
+    // The options win over .fillbyte
+    let fill = options.fill.or(output.fill).unwrap_or(0x00);
+
+    // Checksums cover the final image, which only kld knows about
+    if options.object {
+        if !output.checksums.is_empty() {
+            return Err(unlocated(error(
+                ErrorCode::MalformedChecksum,
+                "Checksums can't be used in object files".to_string(),
+            )));
+        }
+    } else {
+        output.finish(fill).map_err(unlocated)?;
+        output.resolved().map_err(unlocated)?;
+    }
+
+    Ok(Assembly {
+        program,
+        output,
+        fill,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::assemble;
+    use super::assemble_with;
+    use super::Options;
+
+    #[test]
+    fn test_assemble() {
+        let source = ".org $e000\nreset:\n    lda #$01\n    sta $0200\n.fillbyte $ea\n";
+        let assembly = if let Ok(assembly) = assemble(source, &Options::default()) {
+            assembly
+        } else {
+            panic!("Did not expect the source to fail to assemble");
+        };
+
+        assert_eq!(assembly.fill, 0xea);
+        assert_eq!(assembly.symbols()["reset"].address, 0xe000);
+        assert_eq!(assembly.address_of_line(3), Some(0xe000));
+        assert_eq!(assembly.address_of_line(4), Some(0xe002));
+        assert_eq!(assembly.address_of_line(1), None);
+
+        let segments = assembly.segments();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].address, 0xe000);
+        assert_eq!(segments[0].data, vec![0xa9, 0x01, 0x8d, 0x00, 0x02]);
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        for (source, line) in &[
+            ("nop\nlda missing\n", Some(2)),
+            ("nop\n.bogus\n", Some(2)),
+            (".test \"open\"\n", Some(1)),
+            (".export missing\n", None),
+        ] {
+            match assemble(source, &Options::default()) {
+                Ok(_) => panic!("Did not expect {} to assemble", source),
+                Err(diagnostics) => {
+                    assert_eq!(diagnostics.errors.len(), 1);
+                    assert_eq!(diagnostics.errors[0].line, *line);
+                }
+            }
+        }
+
+        let options = Options {
+            object: true,
+            ..Options::default()
+        };
+        if assemble(".checksum sum8, $1000, $1001\n", &options).is_ok() {
+            panic!("Did not expect a checksum in an object to work");
+        }
+
+        // Without a checksum, an import in an image is only unresolved
+        match assemble(".import far\n.org $1000\njsr far\n", &Options::default()) {
+            Ok(_) => panic!("Did not expect an unresolved import to assemble"),
+            Err(diagnostics) => assert!(diagnostics.errors[0]
+                .message
+                .contains("can only be resolved by the linker")),
+        }
+    }
+
+    #[test]
+    fn test_assemble_with() {
+        let files: HashMap<String, Vec<u8>> = [
+            ("src/io.s".to_string(), b"putc:\n    rts\n".to_vec()),
+            ("src/font.bin".to_string(), vec![0x18, 0x24]),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let source = ".org $8000\n    jsr putc\n.include \"io.s\"\n.incbin \"font.bin\"\n";
+        let options = Options::default();
+        match assemble_with(source, Some("src/main.s"), &options, &files) {
+            Ok(assembly) => {
+                assert_eq!(assembly.symbols()["putc"].address, 0x8003);
+                assert_eq!(assembly.symbols()["putc"].file, 1);
+                assert_eq!(assembly.symbols()["putc"].line, 1);
+                assert_eq!(
+                    assembly.segments()[0].data,
+                    vec![0x20, 0x03, 0x80, 0x60, 0x18, 0x24]
+                );
+            }
+            Err(diagnostics) => panic!(
+                "Did not expect the includes to fail: {}",
+                diagnostics.errors[0].message
+            ),
+        }
+
+        // Nothing is found next to a source without a name
+        if assemble_with(source, None, &options, &files).is_ok() {
+            panic!("Did not expect io.s to be found");
+        }
+
+        // An error in an include is on its own line there, reached from the .include
+        let files: HashMap<String, Vec<u8>> = [
+            ("src/io.s".to_string(), b"putc:\n    bogus\n".to_vec()),
+            ("src/font.bin".to_string(), vec![0x18, 0x24]),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        match assemble_with(source, Some("src/main.s"), &options, &files) {
+            Ok(_) => panic!("Did not expect io.s to assemble"),
+            Err(diagnostics) => {
+                assert_eq!(diagnostics.errors[0].file, Some("src/io.s".to_string()));
+                assert_eq!(diagnostics.errors[0].line, Some(2));
+                assert_eq!(diagnostics.errors[0].include_line, Some(3));
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use std::io;
+use std::process;
+
+use kasm::lsp::serve;
+
+// Editors start this and talk to it over stdin and stdout
+fn main() {
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    if let Err(err) = serve(&mut stdin.lock(), &mut stdout.lock()) {
+        eprintln!("{}", err.1);
+        process::exit(err.0 as i32);
+    }
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::process;
+
+use kasm::disassembler::run;
+use kasm::disassembler::usage;
+use kasm::disassembler::DisassembleConfig;
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let config = match DisassembleConfig::new(&mut args) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err.1);
+            usage(&args[0]);
+            process::exit(err.0 as i32);
+        }
+    };
+
+    match run(&config) {
+        Ok(_) => {}
+        Err(err) => {
+            println!("{}", err.1);
+            process::exit(err.0 as i32);
+        }
+    }
+}
@@ -0,0 +1,26 @@
+use std::env;
+use std::process;
+
+use kasm::linker::run;
+use kasm::linker::usage;
+use kasm::linker::LinkConfig;
+
+fn main() {
+    let mut args: Vec<String> = env::args().collect();
+    let config = match LinkConfig::new(&mut args) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("{}", err.1);
+            usage(&args[0]);
+            process::exit(err.0 as i32);
+        }
+    };
+
+    match run(&config) {
+        Ok(_) => {}
+        Err(err) => {
+            println!("{}", err.1);
+            process::exit(err.0 as i32);
+        }
+    }
+}
@@ -1,22 +1,68 @@
 use std::env;
 use std::process;
 
+use kasm::formatter;
+use kasm::linter;
 use kasm::run;
+use kasm::runner;
+use kasm::tester;
 use kasm::usage;
 use kasm::Config;
 
 fn main() {
     let mut args: Vec<String> = env::args().collect();
-    let config = match Config::new(&mut args) {
-        Ok(config) => config,
-        Err(err) => {
-            println!("{}", err.1);
-            usage(&args[0]);
-            process::exit(err.0 as i32);
-        }
+
+    // kasm run and kasm test simulate the program instead of writing an image,
+    // kasm fmt rewrites the source and kasm lint looks it over for likely bugs.
+    // They all need a file after them, so kasm test on its own assembles test.
+    let command = match args.len() {
+        0..=2 => None,
+        _ => args.get(1).map(String::as_str),
+    };
+    let result = match command {
+        Some("run") => match runner::RunConfig::new(&mut args) {
+            Ok(config) => runner::run(&config),
+            Err(err) => {
+                println!("{}", err.1);
+                runner::usage(&args[0]);
+                process::exit(err.0 as i32);
+            }
+        },
+        Some("test") => match tester::TestConfig::new(&mut args) {
+            Ok(config) => tester::run(&config),
+            Err(err) => {
+                println!("{}", err.1);
+                tester::usage(&args[0]);
+                process::exit(err.0 as i32);
+            }
+        },
+        Some("fmt") => match formatter::FormatConfig::new(&mut args) {
+            Ok(config) => formatter::run(&config),
+            Err(err) => {
+                println!("{}", err.1);
+                formatter::usage(&args[0]);
+                process::exit(err.0 as i32);
+            }
+        },
+        Some("lint") => match linter::LintConfig::new(&mut args) {
+            Ok(config) => linter::run(&config),
+            Err(err) => {
+                println!("{}", err.1);
+                linter::usage(&args[0]);
+                process::exit(err.0 as i32);
+            }
+        },
+        _ => match Config::new(&mut args) {
+            Ok(config) => run(&config),
+            Err(err) => {
+                println!("{}", err.1);
+                usage(&args[0]);
+                process::exit(err.0 as i32);
+            }
+        },
     };
 
-    match run(&config) {
+    match result {
         Ok(_) => {}
         Err(err) => {
             println!("{}", err.1);
@@ -0,0 +1,168 @@
+// Checksums over a range of the image, for ROMs that verify themselves
+use crate::pass1::Address;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ChecksumKind {
+    Sum8,  // The low byte of the sum of every byte
+    Xor8,  // Every byte exclusive or'd together
+    Crc16, // CRC-16/XMODEM, polynomial $1021 starting from $0000
+    Crc32, // The CRC-32 used by zip, gzip and friends
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Checksum {
+    pub kind: ChecksumKind,
+    pub start: Address,
+    pub end: Address,
+    pub adjust: bool, // Pick the byte that makes the whole range sum to zero
+}
+
+pub fn str_to_checksum_kind(kind: &str) -> Option<ChecksumKind> {
+    match kind {
+        "sum8" => Some(ChecksumKind::Sum8),
+        "xor8" => Some(ChecksumKind::Xor8),
+        "crc16" => Some(ChecksumKind::Crc16),
+        "crc32" => Some(ChecksumKind::Crc32),
+        _ => None,
+    }
+}
+
+// The number of bytes the checksum takes up in the image
+pub fn checksum_width(kind: ChecksumKind) -> usize {
+    match kind {
+        ChecksumKind::Sum8 | ChecksumKind::Xor8 => 1,
+        ChecksumKind::Crc16 => 2,
+        ChecksumKind::Crc32 => 4,
+    }
+}
+
+// The bytes to store, little endian like everything else on the 6502
+pub fn checksum(checksum: &Checksum, bytes: &[u8]) -> Vec<u8> {
+    match checksum.kind {
+        ChecksumKind::Sum8 => {
+            let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            if checksum.adjust {
+                vec![sum.wrapping_neg()]
+            } else {
+                vec![sum]
+            }
+        }
+        // A byte equal to the xor of the rest already zeroes the range
+        ChecksumKind::Xor8 => vec![bytes.iter().fold(0u8, |xor, byte| xor ^ byte)],
+        ChecksumKind::Crc16 => crc16(bytes).to_le_bytes().to_vec(),
+        ChecksumKind::Crc32 => crc32(bytes).to_le_bytes().to_vec(),
+    }
+}
+
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc: u16 = 0x0000;
+    for byte in bytes {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::checksum;
+    use super::Checksum;
+    use super::ChecksumKind;
+    use crate::runner::assemble;
+
+    // The standard check input for CRCs
+    const CHECK: &[u8] = b"123456789";
+
+    fn check(kind: ChecksumKind, adjust: bool, bytes: &[u8]) -> Vec<u8> {
+        let checksum_entry = Checksum {
+            kind,
+            start: 0,
+            end: 0,
+            adjust,
+        };
+        checksum(&checksum_entry, bytes)
+    }
+
+    #[test]
+    fn test_sum8() {
+        assert_eq!(check(ChecksumKind::Sum8, false, CHECK), vec![0xdd]);
+    }
+
+    #[test]
+    fn test_sum8_adjust() {
+        let sum = check(ChecksumKind::Sum8, true, CHECK);
+        assert_eq!(sum, vec![0x23]);
+        let total = CHECK
+            .iter()
+            .chain(&sum)
+            .fold(0u8, |total, byte| total.wrapping_add(*byte));
+        assert_eq!(total, 0x00);
+    }
+
+    #[test]
+    fn test_xor8() {
+        assert_eq!(check(ChecksumKind::Xor8, false, CHECK), vec![0x31]);
+    }
+
+    #[test]
+    fn test_xor8_adjust() {
+        let xor = check(ChecksumKind::Xor8, true, CHECK);
+        assert_eq!(xor, vec![0x31]);
+        let total = CHECK
+            .iter()
+            .chain(&xor)
+            .fold(0u8, |total, byte| total ^ byte);
+        assert_eq!(total, 0x00);
+    }
+
+    #[test]
+    fn test_crc16() {
+        // CRC-16/XMODEM's check value is $31c3
+        assert_eq!(check(ChecksumKind::Crc16, false, CHECK), vec![0xc3, 0x31]);
+    }
+
+    #[test]
+    fn test_crc32() {
+        // CRC-32's check value is $cbf43926
+        assert_eq!(
+            check(ChecksumKind::Crc32, false, CHECK),
+            vec![0x26, 0x39, 0xf4, 0xcb]
+        );
+    }
+
+    #[test]
+    fn test_checksum_covers_itself_and_gaps() {
+        // $1002-$1003 and $1006-$1007 are gaps, and the checksum is at $1004
+        let source = ".fillbyte $ff\n.org $1000\n.byte $01, $02\n.org $1004\n.checksum sum8, $1000, $1007, adjust\n.byte $03\n";
+        let segments = match assemble(source, None) {
+            Ok((_, segments)) => segments,
+            Err(err) => panic!("Did not expect the checksum to error: {}", err.1),
+        };
+        // $01 + $02 + 4 * $ff + $00 + $03 is $402, so $fe brings it to zero
+        match segments.iter().find(|segment| segment.address == 0x1004) {
+            Some(segment) => assert_eq!(segment.data, vec![0xfe, 0x03]),
+            None => panic!("Did not expect the checksum to be missing"),
+        }
+    }
+}
@@ -0,0 +1,646 @@
+// kdasm turns an image back into source that kasm assembles to the same bytes
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::files::read_binary;
+use crate::files::read_source;
+use crate::files::write_file;
+use crate::ihex::from_ihex;
+use crate::image::Segment;
+use crate::instructions::address_mode_length;
+use crate::instructions::generate_instruction_set;
+use crate::instructions::generate_opcode_table;
+use crate::instructions::mnemonic_to_str;
+use crate::instructions::AddressMode;
+use crate::instructions::InstructionKey;
+use crate::instructions::Mnemonic;
+use crate::instructions::OpcodeMap;
+use crate::parse_address;
+use crate::pass1::VECTORS;
+
+// The 65C02 only sees 64K, and its addresses wrap around within it, unlike
+// the assembler's Address which also covers the 65816's 16M
+pub type Address16 = u16;
+
+const SOURCE_DEFAULT: &str = "a.s";
+const BYTES_PER_LINE: usize = 8;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum InputFormat {
+    Binary,   // A raw image, placed at the load address
+    IntelHex, // Carries its own addresses
+}
+
+pub struct DisassembleConfig {
+    image_file: String,
+    out_file: String,
+    format: InputFormat,
+    load: Address16,
+    entries: Vec<Address16>, // Where to start tracing, the vectors if empty
+    hints_file: Option<String>,
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} [-o <outfile>] [--format bin|ihex] [--load <address>]\n       [-e <address> ...] [--hints <hints>] <image>",
+        cmd
+    );
+}
+
+impl DisassembleConfig {
+    pub fn new(args: &mut Vec<String>) -> Result<DisassembleConfig, (ErrorCode, &'static str)> {
+        let mut args = args.drain(1..); // Remove first arg
+        let mut image_file = None;
+        let mut out_file = String::from(SOURCE_DEFAULT);
+        let mut format = InputFormat::Binary;
+        let mut load = 0x0000;
+        let mut entries = Vec::new();
+        let mut hints_file = None;
+
+        while let Some(val) = args.next() {
+            if val == "-o" {
+                match args.next() {
+                    Some(file) => out_file = file,
+                    None => return Err((ErrorCode::Usage, "No output filename provided")),
+                }
+            } else if val == "--format" {
+                format = match args.next().as_deref() {
+                    Some("bin") => InputFormat::Binary,
+                    Some("ihex") => InputFormat::IntelHex,
+                    _ => return Err((ErrorCode::Usage, "Unknown input format")),
+                };
+            } else if val == "--load" {
+                match args.next().as_deref().and_then(parse_short_address) {
+                    Some(address) => load = address,
+                    None => return Err((ErrorCode::Usage, "Invalid load address")),
+                }
+            } else if val == "-e" {
+                match args.next().as_deref().and_then(parse_short_address) {
+                    Some(address) => entries.push(address),
+                    None => return Err((ErrorCode::Usage, "Invalid entry point")),
+                }
+            } else if val == "--hints" {
+                match args.next() {
+                    Some(file) => hints_file = Some(file),
+                    None => return Err((ErrorCode::Usage, "No hints filename provided")),
+                }
+            } else if image_file.is_none() {
+                image_file = Some(val);
+            } else {
+                return Err((ErrorCode::Usage, "Only one image can be disassembled"));
+            }
+        }
+
+        let image_file = match image_file {
+            Some(file) => file,
+            None => return Err((ErrorCode::Usage, "Missing arguments")),
+        };
+
+        if image_file == out_file {
+            return Err((
+                ErrorCode::OverwriteSource,
+                "You really don't want to overwrite your image",
+            ));
+        }
+
+        Ok(DisassembleConfig {
+            image_file,
+            out_file,
+            format,
+            load,
+            entries,
+            hints_file,
+        })
+    }
+}
+
+fn parse_short_address(value: &str) -> Option<Address16> {
+    parse_address(value)
+        .filter(|address| *address <= 0xffff)
+        .map(|address| address as Address16)
+}
+
+pub fn run(config: &DisassembleConfig) -> Result<(), Error> {
+    let segments = match config.format {
+        InputFormat::Binary => {
+            let data = read_binary(&config.image_file)?;
+            if config.load as usize + data.len() > 0x10000 {
+                return Err(error(
+                    ErrorCode::OutsideWindow,
+                    format!(
+                        "{} bytes loaded at ${:04x} runs past $ffff",
+                        data.len(),
+                        config.load
+                    ),
+                ));
+            }
+            vec![Segment {
+                address: config.load as u32,
+                data,
+            }]
+        }
+        InputFormat::IntelHex => from_ihex(&read_source(&config.image_file)?)?,
+    };
+
+    let hints = match &config.hints_file {
+        Some(file) => match parse_hints(&read_source(file)?) {
+            Ok(hints) => hints,
+            Err((code, msg)) => return Err((code, format!("{}: {}", file, msg))),
+        },
+        None => Hints::default(),
+    };
+
+    let source = disassemble(&segments, &config.entries, &hints);
+    write_file(&config.out_file, source.as_bytes())
+}
+
+// What we know that the image can't tell us
+#[derive(Default)]
+pub struct Hints {
+    pub labels: HashMap<Address16, String>,
+    pub data: Vec<(Address16, Address16)>, // Inclusive ranges never traced as code
+    pub entries: Vec<Address16>,
+}
+
+// One hint per line:
+//   label $e000 reset
+//   data $f000 $f0ff
+//   entry $e100
+pub fn parse_hints(text: &str) -> Result<Hints, Error> {
+    let mut hints = Hints::default();
+
+    for (index, raw_line) in text.lines().enumerate() {
+        let line = raw_line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+        let addresses: Vec<Option<Address16>> = parts
+            .iter()
+            .skip(1)
+            .map(|part| parse_short_address(part))
+            .collect();
+        match (parts[0], addresses.as_slice(), parts.len()) {
+            ("label", [Some(address), _], 3) if is_label(parts[2]) => {
+                hints.labels.insert(*address, parts[2].to_string());
+            }
+            ("data", [Some(start), Some(end)], 3) if start <= end => {
+                hints.data.push((*start, *end));
+            }
+            ("entry", [Some(address)], 2) => hints.entries.push(*address),
+            _ => {
+                return Err(error(
+                    ErrorCode::MalformedHints,
+                    format!("Unknown hint at line {}: {}", index + 1, line),
+                ))
+            }
+        }
+    }
+
+    Ok(hints)
+}
+
+// The same names kasm accepts as operands
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+pub struct Instruction {
+    pub key: InstructionKey,
+    pub bytes: Vec<u8>, // Including the opcode
+}
+
+impl Instruction {
+    pub fn word(&self) -> Address16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    // Where a branch goes, relative to the end of the instruction
+    pub fn branch_target(&self, address: usize) -> Address16 {
+        let offset = *self.bytes.last().unwrap() as i8 as isize;
+        ((address + self.bytes.len()) as isize + offset) as Address16
+    }
+
+    // Every address the operand points at
+    fn target(&self, address: usize) -> Option<Address16> {
+        match self.key.address_mode {
+            AddressMode::Relative | AddressMode::ZeropageRelative => {
+                Some(self.branch_target(address))
+            }
+            AddressMode::Absolute
+            | AddressMode::AbsoluteX
+            | AddressMode::AbsoluteY
+            | AddressMode::Indirect
+            | AddressMode::AbsoluteIndexedIndirect => Some(self.word()),
+            _ => None,
+        }
+    }
+
+    // Where execution can carry on from here, other than the next instruction
+    fn jumps_to(&self, address: usize) -> Option<Address16> {
+        match (self.key.mnemonic, self.key.address_mode) {
+            (_, AddressMode::Relative) | (_, AddressMode::ZeropageRelative) => {
+                Some(self.branch_target(address))
+            }
+            (Mnemonic::JMP, AddressMode::Absolute) | (Mnemonic::JSR, AddressMode::Absolute) => {
+                Some(self.word())
+            }
+            _ => None,
+        }
+    }
+
+    // Whether the next instruction is never reached from this one
+    fn ends_flow(&self) -> bool {
+        matches!(
+            self.key.mnemonic,
+            Mnemonic::BRA
+                | Mnemonic::BRK
+                | Mnemonic::JMP
+                | Mnemonic::RTI
+                | Mnemonic::RTS
+                | Mnemonic::STP
+        )
+    }
+}
+
+struct Disassembly<'a> {
+    memory: BTreeMap<usize, u8>,
+    opcodes: OpcodeMap,
+    hints: &'a Hints,
+    code: BTreeMap<usize, Instruction>,
+    owned: HashSet<usize>, // Every byte that's part of an instruction
+}
+
+impl Disassembly<'_> {
+    fn is_data(&self, address: usize) -> bool {
+        self.hints
+            .data
+            .iter()
+            .any(|(start, end)| address >= *start as usize && address <= *end as usize)
+    }
+
+    fn decode(&self, address: usize) -> Option<Instruction> {
+        let key = *self.opcodes.get(self.memory.get(&address)?)?;
+        let length = address_mode_length(key.address_mode) as usize;
+        let mut bytes = Vec::new();
+        for at in address..address + length {
+            if self.owned.contains(&at) || self.is_data(at) {
+                return None;
+            }
+            bytes.push(*self.memory.get(&at)?);
+        }
+        Some(Instruction { key, bytes })
+    }
+
+    // Recursive traversal, following every path execution can take
+    fn trace(&mut self, entries: Vec<Address16>) {
+        let mut pending: Vec<usize> = entries.iter().map(|entry| *entry as usize).collect();
+
+        while let Some(mut address) = pending.pop() {
+            while !self.code.contains_key(&address) {
+                let instruction = match self.decode(address) {
+                    Some(instruction) => instruction,
+                    None => break,
+                };
+                if let Some(target) = instruction.jumps_to(address) {
+                    pending.push(target as usize);
+                }
+                let length = instruction.bytes.len();
+                let ends_flow = instruction.ends_flow();
+                self.owned.extend(address..address + length);
+                self.code.insert(address, instruction);
+                if ends_flow {
+                    break;
+                }
+                address += length;
+            }
+        }
+    }
+
+    // Labels can only go in front of an instruction or a data byte
+    fn placeable(&self, address: usize) -> bool {
+        self.memory.contains_key(&address)
+            && (self.code.contains_key(&address) || !self.owned.contains(&address))
+    }
+
+    fn vector(&self, address: usize) -> Option<Address16> {
+        Some(u16::from_le_bytes([
+            *self.memory.get(&address)?,
+            *self.memory.get(&(address + 1))?,
+        ]))
+    }
+
+    // Whether $fffa-$ffff is all data that can go in a .vectors
+    fn has_vectors(&self, labels: &BTreeMap<Address16, String>) -> bool {
+        let vectors = VECTORS as usize;
+        (vectors..=0xffff).all(|at| self.memory.contains_key(&at) && !self.owned.contains(&at))
+            && !labels.keys().any(|at| *at as usize > vectors)
+    }
+}
+
+pub fn disassemble(segments: &[Segment], entries: &[Address16], hints: &Hints) -> String {
+    let mut disassembly = Disassembly {
+        memory: BTreeMap::new(),
+        opcodes: generate_opcode_table(&generate_instruction_set()),
+        hints,
+        code: BTreeMap::new(),
+        owned: HashSet::new(),
+    };
+    for segment in segments {
+        for (offset, byte) in segment.data.iter().enumerate() {
+            disassembly
+                .memory
+                .insert(segment.address as usize + offset, *byte);
+        }
+    }
+
+    // Without any entry points, start from wherever the vectors point
+    let mut starts: Vec<Address16> = entries.iter().chain(&hints.entries).copied().collect();
+    if starts.is_empty() {
+        for vector in (VECTORS as usize..=0xffff).step_by(2) {
+            if let Some(target) = disassembly.vector(vector) {
+                starts.push(target);
+            }
+        }
+    }
+    disassembly.trace(starts);
+
+    // Everything the code and vectors point at gets a label where one fits
+    let mut targets: Vec<Address16> = disassembly
+        .code
+        .iter()
+        .filter_map(|(address, instruction)| instruction.target(*address))
+        .collect();
+    for vector in (VECTORS as usize..=0xffff).step_by(2) {
+        if let Some(target) = disassembly.vector(vector) {
+            targets.push(target);
+        }
+    }
+    let mut labels: BTreeMap<Address16, String> = BTreeMap::new();
+    for target in targets {
+        if disassembly.placeable(target as usize) {
+            labels.insert(target, format!("l_{:04x}", target));
+        }
+    }
+    for (address, name) in &hints.labels {
+        labels.insert(*address, name.to_string());
+    }
+
+    let operand = |address: Address16| match labels.get(&address) {
+        Some(name) => name.to_string(),
+        None => format!("${:04x}", address),
+    };
+
+    let mut source = String::from("; Disassembled by kdasm\n");
+
+    // Named addresses outside of the image, like I/O, become .equ's
+    let mut equs = false;
+    for (address, name) in &labels {
+        if !disassembly.placeable(*address as usize) {
+            source.push_str(&format!(".equ {} = ${:04x}\n", name, address));
+            equs = true;
+        }
+    }
+    if equs {
+        source.push('\n');
+    }
+
+    let has_vectors = disassembly.has_vectors(&labels);
+    let mut address = match disassembly.memory.keys().next() {
+        Some(address) => *address,
+        None => return source,
+    };
+    let last = *disassembly.memory.keys().next_back().unwrap();
+    let mut data: Vec<u8> = Vec::new();
+    let mut org = true;
+
+    while address <= last {
+        let byte = match disassembly.memory.get(&address) {
+            Some(byte) => *byte,
+            None => {
+                flush(&mut source, &mut data);
+                org = true;
+                address += 1;
+                continue;
+            }
+        };
+        if org {
+            source.push_str(&format!("\n.org ${:04x}\n", address));
+            org = false;
+        }
+
+        let instruction = disassembly.code.get(&address);
+        let label = labels
+            .get(&(address as Address16))
+            .filter(|_| disassembly.placeable(address));
+        if label.is_some() || instruction.is_some() || data.len() == BYTES_PER_LINE {
+            flush(&mut source, &mut data);
+        }
+        if let Some(name) = label {
+            source.push_str(&format!("{}:\n", name));
+        }
+
+        if has_vectors && address == VECTORS as usize {
+            flush(&mut source, &mut data);
+            let vector = |at: usize| operand(disassembly.vector(at).unwrap());
+            source.push_str(&format!(
+                "    .vectors nmi={}, reset={}, irq={}\n",
+                vector(address),
+                vector(address + 2),
+                vector(address + 4)
+            ));
+            address += 6;
+            continue;
+        }
+
+        match instruction {
+            Some(instruction) => {
+                source.push_str(&format!(
+                    "    {}\n",
+                    format_instruction(instruction, address, &operand)
+                ));
+                address += instruction.bytes.len();
+            }
+            None => {
+                data.push(byte);
+                address += 1;
+            }
+        }
+    }
+    flush(&mut source, &mut data);
+
+    source
+}
+
+fn flush(source: &mut String, data: &mut Vec<u8>) {
+    if data.is_empty() {
+        return;
+    }
+    let bytes: Vec<String> = data.iter().map(|byte| format!("${:02x}", byte)).collect();
+    source.push_str(&format!("    .byte {}\n", bytes.join(", ")));
+    data.clear();
+}
+
+pub fn format_instruction(
+    instruction: &Instruction,
+    address: usize,
+    operand: &dyn Fn(Address16) -> String,
+) -> String {
+    let mnemonic = mnemonic_to_str(instruction.key.mnemonic);
+    let bytes = &instruction.bytes;
+    let text = match instruction.key.address_mode {
+        AddressMode::Implied => return mnemonic,
+        AddressMode::Immediate => format!("#${:02x}", bytes[1]),
+        AddressMode::Zeropage => format!("${:02x}", bytes[1]),
+        AddressMode::ZeropageX => format!("${:02x},x", bytes[1]),
+        AddressMode::ZeropageY => format!("${:02x},y", bytes[1]),
+        AddressMode::Absolute => operand(instruction.word()),
+        AddressMode::AbsoluteX => format!("{},x", operand(instruction.word())),
+        AddressMode::AbsoluteY => format!("{},y", operand(instruction.word())),
+        AddressMode::Indirect => format!("({})", operand(instruction.word())),
+        AddressMode::IndirectX => format!("(${:02x},x)", bytes[1]),
+        AddressMode::IndirectY => format!("(${:02x}),y", bytes[1]),
+        AddressMode::ZeropageIndirect => format!("(${:02x})", bytes[1]),
+        AddressMode::AbsoluteIndexedIndirect => format!("({},x)", operand(instruction.word())),
+        AddressMode::Relative => operand(instruction.branch_target(address)),
+        // Only the 65816 has these, and neither kdasm nor kasm run decode them
+        AddressMode::AbsoluteLong => format!("${:02x}{:02x}{:02x}", bytes[3], bytes[2], bytes[1]),
+        AddressMode::AbsoluteLongX => {
+            format!("${:02x}{:02x}{:02x},x", bytes[3], bytes[2], bytes[1])
+        }
+        AddressMode::DirectIndirectLong => format!("[${:02x}]", bytes[1]),
+        AddressMode::DirectIndirectLongY => format!("[${:02x}],y", bytes[1]),
+        AddressMode::StackRelative => format!("${:02x},s", bytes[1]),
+        AddressMode::StackRelativeIndirectY => format!("(${:02x},s),y", bytes[1]),
+        AddressMode::BlockMove => format!("${:02x},${:02x}", bytes[2], bytes[1]),
+        AddressMode::RelativeLong => {
+            let offset = i16::from_le_bytes([bytes[1], bytes[2]]);
+            operand(
+                (address as Address16)
+                    .wrapping_add(3)
+                    .wrapping_add(offset as Address16),
+            )
+        }
+        AddressMode::AbsoluteIndirectLong => format!("[{}]", operand(instruction.word())),
+        AddressMode::ZeropageRelative => format!(
+            "${:02x},{}",
+            bytes[1],
+            operand(instruction.branch_target(address))
+        ),
+    };
+    format!("{} {}", mnemonic, text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble;
+    use super::parse_hints;
+    use super::DisassembleConfig;
+    use super::Hints;
+    use crate::image::Segment;
+    use crate::instructions::generate_instruction_set;
+    use crate::pass1::pass1;
+    use crate::pass2::generate;
+    use crate::scanner::scanner;
+
+    fn reassemble(source: &str) -> Vec<Segment> {
+        let program = match pass1(scanner(source)) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}\n{}", err.1, source),
+        };
+        match generate(&generate_instruction_set(), &program).and_then(|o| o.segments()) {
+            Ok(segments) => segments,
+            Err(err) => panic!("Did not expect pass2 to fail: {}\n{}", err.1, source),
+        }
+    }
+
+    fn rom() -> Vec<Segment> {
+        vec![
+            Segment {
+                address: 0xe000,
+                data: vec![
+                    0xa2, 0xff, // ldx #$ff
+                    0x9a, // txs
+                    0x20, 0x0d, 0xe0, // jsr $e00d
+                    0xd0, 0xfb, // bne $e003
+                    0xad, 0x00, 0x60, // lda $6000
+                    0x80, 0xfe, // bra $e00b
+                    0xbd, 0x13, 0xe0, // lda $e013,x
+                    0x7c, 0x13, 0xe0, // jmp ($e013,x)
+                    0x01, 0x02, 0x03, // data
+                    0x40, // rti
+                    0x0f, 0x12, 0xfd, // bbr0 $12,$e017
+                    0x60, // rts
+                ],
+            },
+            Segment {
+                address: 0xfffa,
+                data: vec![0x16, 0xe0, 0x00, 0xe0, 0x17, 0xe0],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let segments = rom();
+        let source = disassemble(&segments, &[], &Hints::default());
+        assert!(source.contains("l_e00d:\n    lda l_e013,x\n"));
+        assert!(source.contains("l_e013:\n    .byte $01, $02, $03\n"));
+        assert!(source.contains(".vectors nmi=l_e016, reset=l_e000, irq=l_e017"));
+        assert!(source.contains("    bbr0 $12,l_e017\n"));
+
+        let reassembled = reassemble(&source);
+        assert_eq!(reassembled.len(), segments.len());
+        for (read, original) in reassembled.iter().zip(&segments) {
+            assert_eq!(read.address, original.address);
+            assert_eq!(read.data, original.data);
+        }
+    }
+
+    #[test]
+    fn test_hints() {
+        let hints = match parse_hints("label $e000 reset ; comment\nlabel $6000 via\ndata $e00d $e012\n") {
+            Ok(hints) => hints,
+            Err(err) => panic!("Did not expect the hints to error: {}", err.1),
+        };
+        let segments = rom();
+        let source = disassemble(&segments, &[], &hints);
+        assert!(source.contains(".equ via = $6000\n"));
+        assert!(source.contains("reset:\n    ldx #$ff\n"));
+        assert!(source.contains("    lda via\n"));
+        assert!(source.contains("l_e00d:\n    .byte $bd, $13, $e0, $7c, $13, $e0, $01, $02\n"));
+        assert_eq!(reassemble(&source)[0].data, segments[0].data);
+
+        for hint in &["label $e000", "label $e000 Reset", "data $e010 $e000", "code $e000"] {
+            if parse_hints(hint).is_ok() {
+                panic!("Did not expect {} to work", hint);
+            }
+        }
+    }
+
+    #[test]
+    fn test_disassemble_config() {
+        if let Ok(c) = DisassembleConfig::new(&mut vec![
+            "kdasm".to_string(),
+            "--load".to_string(),
+            "$8000".to_string(),
+            "-e".to_string(),
+            "$8010".to_string(),
+            "rom.bin".to_string(),
+        ]) {
+            assert_eq!(c.image_file, "rom.bin");
+            assert_eq!(c.out_file, "a.s");
+            assert_eq!(c.load, 0x8000);
+            assert_eq!(c.entries, vec![0x8010]);
+        } else {
+            panic!("Did not expect creating a new DisassembleConfig to error");
+        };
+    }
+}
@@ -1,6 +1,9 @@
 // Stuff for hanlding errors encountered in the assembler
 #![macro_use]
 
+use crate::scanner::FileId;
+use crate::scanner::Line;
+
 #[macro_export]
 macro_rules! verbose {
     ($fmt:expr) => (print!(concat!($fmt)));
@@ -39,10 +42,39 @@ pub enum ErrorCode {
     AddressExpected,
     HexExpected,
     MalformedEqu,
+    UnresolvedImport,
+    UndefinedExport,
+    MalformedObject,
+    DuplicateSymbol,
+    MissingSymbol,
+    Overlap,
+    OutsideWindow,
+    CounterOverflow,
+    MalformedVectors,
+    MalformedChecksum,
+    BranchOutOfRange,
+    MalformedHex,
+    MalformedHints,
+    CycleLimit,
+    MalformedExpression,
+    AssertionFailed,
+    UserError,
+    MalformedTest,
+    TestFailed,
+    CycleBudget,
+    UnsupportedInstruction,
+    MalformedInclude,
+    MalformedMessage,
+    Unformatted,
+    FormatMismatch,
+    LintWarnings,
 }
 
 pub type ErrorMsg = String;
 
+// An error and the file and line it was found on, when it's down to one line
+pub type LocatedError = (Error, Option<(FileId, Line)>);
+
 pub fn error(code: ErrorCode, msg: String) -> Error {
     (code, msg)
 }
@@ -0,0 +1,265 @@
+// Expressions for the directives that check things about the program.
+// Numbers are $hex, %binary or decimal, * is the address of the line, and
+// the operators and their precedence are the same as C's.
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+
+pub type Number = i64;
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Number(Number),
+    Label(String),
+    Operator(&'static str),
+    Open,
+    Close,
+}
+
+// Longest first, so << isn't read as <
+const OPERATORS: [&str; 19] = [
+    "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "&", "|", "^", "<",
+    ">", "!",
+];
+
+fn malformed(expression: &str, problem: &str) -> Error {
+    error(
+        ErrorCode::MalformedExpression,
+        format!("{} in expression: {}", problem, expression),
+    )
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut rest = expression.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        let (token, length) = if ch == '(' {
+            (Token::Open, 1)
+        } else if ch == ')' {
+            (Token::Close, 1)
+        } else if ch == '~' {
+            (Token::Operator("~"), 1)
+        } else if ch == '$' || ch == '%' && !follows_operand(&tokens) || ch.is_ascii_digit() {
+            let (radix, digits) = match ch {
+                '$' => (16, &rest[1..]),
+                '%' => (2, &rest[1..]),
+                _ => (10, rest),
+            };
+            let length = digits
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(digits.len());
+            let number = match Number::from_str_radix(&digits[..length], radix) {
+                Ok(number) => number,
+                Err(_) => return Err(malformed(expression, "Bad number")),
+            };
+            (Token::Number(number), rest.len() - digits.len() + length)
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let length = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            (Token::Label(rest[..length].to_string()), length)
+        } else {
+            match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+                Some(op) => (Token::Operator(op), op.len()),
+                None => return Err(malformed(expression, "Unexpected character")),
+            }
+        };
+        tokens.push(token);
+        rest = rest[length..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+// % is modulo after something with a value, binary anywhere else
+fn follows_operand(tokens: &[Token]) -> bool {
+    matches!(
+        tokens.last(),
+        Some(Token::Number(_)) | Some(Token::Label(_)) | Some(Token::Close)
+    )
+}
+
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "|" => Some(3),
+        "^" => Some(4),
+        "&" => Some(5),
+        "==" | "!=" => Some(6),
+        "<" | "<=" | ">" | ">=" => Some(7),
+        "<<" | ">>" => Some(8),
+        "+" | "-" => Some(9),
+        "*" | "/" | "%" => Some(10),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    expression: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+    here: Number,
+    lookup: &'a dyn Fn(&str) -> Option<Number>,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    // Precedence climbing, everything binds to the left
+    fn binary(&mut self, minimum: u8) -> Result<Number, Error> {
+        let mut left = self.unary()?;
+        while let Some(Token::Operator(op)) = self.peek() {
+            let op = *op;
+            let level = match precedence(op) {
+                Some(level) if level >= minimum => level,
+                _ => break,
+            };
+            self.position += 1;
+            let right = self.binary(level + 1)?;
+            left = self.apply(op, left, right)?;
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Number, Error> {
+        match self.next() {
+            Some(Token::Operator("-")) => Ok(self.unary()?.wrapping_neg()),
+            Some(Token::Operator("!")) => Ok((self.unary()? == 0) as Number),
+            Some(Token::Operator("~")) => Ok(!self.unary()?),
+            // A * where a value belongs is the current address
+            Some(Token::Operator("*")) => Ok(self.here),
+            Some(Token::Number(number)) => Ok(number),
+            Some(Token::Label(label)) => match (self.lookup)(&label) {
+                Some(value) => Ok(value),
+                None => Err(error(
+                    ErrorCode::UnknownLabel,
+                    format!("Unknown label: {}", label),
+                )),
+            },
+            Some(Token::Open) => {
+                let value = self.binary(1)?;
+                match self.next() {
+                    Some(Token::Close) => Ok(value),
+                    _ => Err(malformed(self.expression, "Missing )")),
+                }
+            }
+            _ => Err(malformed(self.expression, "Expected a value")),
+        }
+    }
+
+    fn apply(&self, op: &str, left: Number, right: Number) -> Result<Number, Error> {
+        let overflow = || malformed(self.expression, "Division overflow");
+        let value = match op {
+            "||" => (left != 0 || right != 0) as Number,
+            "&&" => (left != 0 && right != 0) as Number,
+            "|" => left | right,
+            "^" => left ^ right,
+            "&" => left & right,
+            "==" => (left == right) as Number,
+            "!=" => (left != right) as Number,
+            "<" => (left < right) as Number,
+            "<=" => (left <= right) as Number,
+            ">" => (left > right) as Number,
+            ">=" => (left >= right) as Number,
+            "<<" => left.checked_shl(right as u32).unwrap_or(0),
+            ">>" => left.checked_shr(right as u32).unwrap_or(0),
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => {
+                return Err(malformed(self.expression, "Division by zero"));
+            }
+            // The smallest number divided by -1 is too big to fit
+            "/" => left.checked_div(right).ok_or_else(overflow)?,
+            _ => left.checked_rem(right).ok_or_else(overflow)?,
+        };
+        Ok(value)
+    }
+}
+
+// Work out the value of an expression, with labels found by lookup
+pub fn evaluate(
+    expression: &str,
+    here: Number,
+    lookup: &dyn Fn(&str) -> Option<Number>,
+) -> Result<Number, Error> {
+    let mut parser = Parser {
+        expression,
+        tokens: tokenize(expression)?,
+        position: 0,
+        here,
+        lookup,
+    };
+    let value = parser.binary(1)?;
+    if parser.peek().is_some() {
+        return Err(malformed(expression, "Unexpected text after the end"));
+    }
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::evaluate;
+    use super::Number;
+
+    fn eval(expression: &str) -> Option<Number> {
+        let lookup = |label: &str| match label {
+            "table" => Some(0x12f8),
+            "table_end" => Some(0x1300),
+            _ => None,
+        };
+        evaluate(expression, 0x8000, &lookup).ok()
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(eval("$ff"), Some(255));
+        assert_eq!(eval("%1010"), Some(10));
+        assert_eq!(eval("42"), Some(42));
+        assert_eq!(eval("*"), Some(0x8000));
+        assert_eq!(eval("$xyz"), None);
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(eval("1 + 2 * 3"), Some(7));
+        assert_eq!(eval("(1 + 2) * 3"), Some(9));
+        assert_eq!(eval("10 - 4 - 3"), Some(3));
+        assert_eq!(eval("1 << 4 | 1"), Some(17));
+        assert_eq!(eval("-2 + 5"), Some(3));
+        assert_eq!(eval("!0 && ~0 == -1"), Some(1));
+        assert_eq!(eval("7 % 4"), Some(3));
+        assert_eq!(eval("* - $10"), Some(0x7ff0));
+        assert_eq!(eval("* * 2"), Some(0x10000));
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(eval("(table_end - table) / 2 == 4"), Some(1));
+        assert_eq!(eval("table >> 8 == (table_end - 1) >> 8"), Some(1));
+        assert_eq!(eval("table_end < $c000"), Some(1));
+        assert_eq!(eval("missing + 1"), None);
+    }
+
+    #[test]
+    fn test_malformed() {
+        assert_eq!(eval(""), None);
+        assert_eq!(eval("(1 + 2"), None);
+        assert_eq!(eval("1 +"), None);
+        assert_eq!(eval("1 2"), None);
+        assert_eq!(eval("1 / 0"), None);
+        assert_eq!(eval("(1 << 63) / -1"), None);
+        assert_eq!(eval("(1 << 63) % -1"), None);
+        assert_eq!(eval("1 # 2"), None);
+    }
+}
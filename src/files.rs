@@ -6,9 +6,37 @@ use std::string::String;
 use crate::errors::error;
 use crate::errors::Error;
 use crate::errors::ErrorCode;
-use crate::pass2::MachineCode;
+use crate::ihex::to_ihex;
+use crate::image::flatten;
+use crate::image::Segment;
+use crate::pass1::Address;
+use crate::pass1::VECTORS;
+use crate::srec::to_srec;
 use crate::Config;
 
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Binary,   // A raw image, padded out from $0000
+    IntelHex, // Only the addresses we have code or data for
+    SRecord,  // Motorola S-records, also skipping the gaps
+}
+
+// Where the output begins, anything below it is left out
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Start {
+    Address(Address),
+    Lowest, // Wherever the first byte of code or data is
+}
+
+pub fn str_to_output_format(format: &str) -> Option<OutputFormat> {
+    match format {
+        "bin" => Some(OutputFormat::Binary),
+        "ihex" => Some(OutputFormat::IntelHex),
+        "srec" => Some(OutputFormat::SRecord),
+        _ => None,
+    }
+}
+
 pub fn read_source(file: &str) -> Result<String, Error> {
     let path = Path::new(file);
     let display = path.display();
@@ -16,7 +44,7 @@ pub fn read_source(file: &str) -> Result<String, Error> {
         Err(err) => {
             return Err(error(
                 ErrorCode::FileOpen,
-                format!("Couldn't open {}: {}", display, err.to_string()),
+                format!("Couldn't open {}: {}", display, err),
             ))
         }
         Ok(f) => f,
@@ -26,52 +54,301 @@ pub fn read_source(file: &str) -> Result<String, Error> {
     if let Err(why) = f.read_to_string(&mut raw_source) {
         return Err(error(
             ErrorCode::FileRead,
-            format!("Couldn't read {}: {}", display, why.to_string()),
+            format!("Couldn't read {}: {}", display, why),
         ));
     };
 
     Ok(raw_source)
 }
 
-pub fn write_out(config: &Config, output: MachineCode) -> Result<(), Error> {
-    let path = Path::new(&config.out_file);
+pub fn read_binary(file: &str) -> Result<Vec<u8>, Error> {
+    let path = Path::new(file);
     let display = path.display();
-    let mut f = match File::create(&path) {
-        Err(why) => {
+    let mut f = match File::open(path) {
+        Err(err) => {
             return Err(error(
-                ErrorCode::FileCreate,
-                format!("Couldn't create {}: {}", display, why.to_string()),
+                ErrorCode::FileOpen,
+                format!("Couldn't open {}: {}", display, err),
             ))
         }
         Ok(f) => f,
     };
 
+    let mut bytes = Vec::new();
+    if let Err(why) = f.read_to_end(&mut bytes) {
+        return Err(error(
+            ErrorCode::FileRead,
+            format!("Couldn't read {}: {}", display, why),
+        ));
+    };
+
+    Ok(bytes)
+}
+
+pub fn write_out(config: &Config, segments: Vec<Segment>, fill: u8) -> Result<(), Error> {
+    let start = match config.start {
+        Start::Address(address) => address,
+        Start::Lowest => segments.first().map_or(0, |segment| segment.address),
+    };
+    // Only a 65816 program can get past 64K, and then it can use all 16M
+    let beyond = segments
+        .iter()
+        .any(|segment| segment.address as usize + segment.data.len() > 0x10000);
+    let end = config.end.unwrap_or(if beyond { 0xffffff } else { 0xffff });
+
+    // Never quietly drop or write bytes that fall outside of the window
+    for segment in &segments {
+        let last = segment.address as usize + segment.data.len() - 1;
+        if segment.address < start || last > end as usize {
+            return Err(error(
+                ErrorCode::OutsideWindow,
+                format!(
+                    "${:04x}-${:04x} is outside of the output window ${:04x}-${:04x}",
+                    segment.address, last, start, end
+                ),
+            ));
+        }
+    }
+
+    if missing_reset_vector(&segments, start, config.end) {
+        warning!("Warning! No reset vector at $fffc");
+    }
+
+    match config.format {
+        OutputFormat::Binary => write_binary(config, segments, start, fill),
+        OutputFormat::IntelHex => write_file(&config.out_file, to_ihex(&segments).as_bytes()),
+        OutputFormat::SRecord => write_file(&config.out_file, to_srec(&segments).as_bytes()),
+    }
+}
+
+fn write_binary(
+    config: &Config,
+    segments: Vec<Segment>,
+    start: Address,
+    fill: u8,
+) -> Result<(), Error> {
     let mut final_output: Vec<u8> = Vec::new();
 
     // Create out initial padding
-    let mut padding = vec![0x00; config.padding as usize];
+    let mut padding = vec![fill; config.padding as usize];
     final_output.append(&mut padding);
 
     // Write out code
-    for code in output {
-        final_output.push(code);
+    let mut code = flatten(segments, start, fill);
+    if let Some(end) = config.end {
+        code.resize(end as usize - start as usize + 1, fill);
     }
+    final_output.append(&mut code);
 
     // Pad remainder of file to size required
     if config.size > 0 && final_output.len() > config.size as usize {
         warning!("Warning! Final output is larger than desired size");
     }
-    for _ in final_output.len()..config.size as usize {
-        final_output.push(0x00);
+    final_output.resize(final_output.len().max(config.size as usize), fill);
+
+    // Boards with several ROM chips get a file for each chip
+    let chips = split_rom(&final_output, config.split, config.interleave, fill);
+    if chips.len() == 1 {
+        return write_file(&config.out_file, &chips[0].1);
+    }
+    for (number, chip) in chips {
+        write_file(&numbered_file(&config.out_file, &number), &chip)?;
     }
 
+    Ok(())
+}
+
+// A ROM that reaches the top of memory is no use without a reset vector,
+// whatever format it's written in
+fn missing_reset_vector(segments: &[Segment], start: Address, end: Option<Address>) -> bool {
+    let reset = VECTORS + 2;
+    let top = segments.last().map_or(0, |segment| {
+        segment.address as usize + segment.data.len() - 1
+    });
+    let full = end.map_or(top, |end| end as usize) == 0xffff;
+    full && start <= reset && !covers(segments, reset, 2)
+}
+
+// Whether every byte from address onwards is in one of the segments
+fn covers(segments: &[Segment], address: Address, size: usize) -> bool {
+    (address as usize..address as usize + size).all(|address| {
+        segments.iter().any(|segment| {
+            address >= segment.address as usize
+                && address < segment.address as usize + segment.data.len()
+        })
+    })
+}
+
+// Cut the image into banks of a fixed size, and spread the bytes of each
+// bank across interleaved chips, e.g. even and odd bytes for a pair of
+// 8 bit ROMs on a 16 bit bus. Every chip is padded out to the same size.
+fn split_rom(
+    rom: &[u8],
+    split: Option<u32>,
+    interleave: Option<usize>,
+    fill: u8,
+) -> Vec<(String, Vec<u8>)> {
+    let banks: Vec<Vec<u8>> = match split {
+        Some(size) => rom
+            .chunks(size as usize)
+            .map(|chunk| {
+                let mut bank = chunk.to_vec();
+                bank.resize(size as usize, fill);
+                bank
+            })
+            .collect(),
+        None => vec![rom.to_vec()],
+    };
+
+    let mut chips = Vec::new();
+    for (bank_number, bank) in banks.iter().enumerate() {
+        let bank_name = match split {
+            Some(_) => vec![bank_number.to_string()],
+            None => vec![],
+        };
+        match interleave {
+            Some(ways) => {
+                let size = bank.len().div_ceil(ways);
+                for lane in 0..ways {
+                    let mut chip: Vec<u8> = bank.iter().skip(lane).step_by(ways).copied().collect();
+                    chip.resize(size, fill);
+                    let mut name = bank_name.clone();
+                    name.push(lane.to_string());
+                    chips.push((name.join("."), chip));
+                }
+            }
+            None => chips.push((bank_name.join("."), bank.to_vec())),
+        }
+    }
+
+    chips
+}
+
+// rom.bin becomes rom.0.bin, rom.1.bin and so on
+fn numbered_file(file: &str, number: &str) -> String {
+    let path = Path::new(file);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => path
+            .with_file_name(format!(
+                "{}.{}.{}",
+                stem.to_string_lossy(),
+                number,
+                extension.to_string_lossy()
+            ))
+            .to_string_lossy()
+            .to_string(),
+        _ => format!("{}.{}", file, number),
+    }
+}
+
+pub fn write_file(file: &str, contents: &[u8]) -> Result<(), Error> {
+    let path = Path::new(file);
+    let display = path.display();
+    let mut f = match File::create(path) {
+        Err(why) => {
+            return Err(error(
+                ErrorCode::FileCreate,
+                format!("Couldn't create {}: {}", display, why),
+            ))
+        }
+        Ok(f) => f,
+    };
+
     // Write the final output to the output file
-    if let Err(why) = f.write_all(&final_output) {
+    if let Err(why) = f.write_all(contents) {
         return Err(error(
             ErrorCode::FileWrite,
-            format!("Couldn't write {}: {}", display, why.to_string()),
+            format!("Couldn't write {}: {}", display, why),
         ));
     };
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::covers;
+    use super::missing_reset_vector;
+    use super::numbered_file;
+    use super::split_rom;
+    use crate::image::Segment;
+
+    #[test]
+    fn test_split() {
+        let chips = split_rom(&[1, 2, 3, 4, 5], Some(2), None, 0xff);
+        assert_eq!(
+            chips,
+            vec![
+                ("0".to_string(), vec![1, 2]),
+                ("1".to_string(), vec![3, 4]),
+                ("2".to_string(), vec![5, 0xff]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interleave() {
+        let chips = split_rom(&[1, 2, 3, 4, 5], None, Some(2), 0xff);
+        assert_eq!(
+            chips,
+            vec![
+                ("0".to_string(), vec![1, 3, 5]),
+                ("1".to_string(), vec![2, 4, 0xff]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_and_interleave() {
+        let chips = split_rom(&[1, 2, 3, 4, 5, 6, 7, 8], Some(4), Some(2), 0xff);
+        assert_eq!(chips[0], ("0.0".to_string(), vec![1, 3]));
+        assert_eq!(chips[1], ("0.1".to_string(), vec![2, 4]));
+        assert_eq!(chips[3], ("1.1".to_string(), vec![6, 8]));
+    }
+
+    #[test]
+    fn test_covers() {
+        let segments = vec![
+            Segment {
+                address: 0xfffa,
+                data: vec![0x00, 0x10],
+            },
+            Segment {
+                address: 0xfffc,
+                data: vec![0x00, 0x10, 0x00, 0x10],
+            },
+        ];
+        assert!(covers(&segments, 0xfffa, 6));
+        assert!(!covers(&segments[..1], 0xfffc, 2));
+    }
+
+    #[test]
+    fn test_missing_reset_vector() {
+        let segments = vec![Segment {
+            address: 0xfff0,
+            data: vec![0x00; 16],
+        }];
+        assert!(!missing_reset_vector(&segments, 0xfff0, None));
+
+        // Reaching $ffff without one, whether by code or --end, warns
+        let segments = vec![
+            Segment {
+                address: 0xe000,
+                data: vec![0xea],
+            },
+            Segment {
+                address: 0xfffe,
+                data: vec![0x00, 0xe0],
+            },
+        ];
+        assert!(missing_reset_vector(&segments, 0xe000, None));
+        assert!(missing_reset_vector(&segments[..1], 0xe000, Some(0xffff)));
+        assert!(!missing_reset_vector(&segments[..1], 0xe000, None));
+    }
+
+    #[test]
+    fn test_numbered_file() {
+        assert_eq!(numbered_file("rom.bin", "1"), "rom.1.bin");
+        assert_eq!(numbered_file("out/rom", "0.1"), "out/rom.0.1");
+    }
+}
@@ -0,0 +1,366 @@
+// kasm fmt: rewrite source into one layout, so diffs are only ever about code
+use crate::assembly::assemble_with;
+use crate::assembly::Options;
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::files::read_source;
+use crate::files::write_file;
+use crate::sources::FileSystem;
+
+const INDENT: &str = "    "; // Where instructions and test steps start
+const COMMENT_COLUMN: usize = 24; // Where trailing comments start, unless the code is longer
+
+pub struct FormatConfig {
+    source_files: Vec<String>,
+    check: bool, // Only say which files would change
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} fmt [--check] <sourcefile> [<sourcefile> ...]",
+        cmd
+    );
+}
+
+impl FormatConfig {
+    pub fn new(args: &mut Vec<String>) -> Result<FormatConfig, (ErrorCode, &'static str)> {
+        let mut source_files = vec![];
+        let mut check = false;
+
+        for val in args.drain(2..) {
+            // Remove the command and "fmt"
+            if val == "--check" {
+                check = true;
+            } else {
+                source_files.push(val);
+            }
+        }
+
+        if source_files.is_empty() {
+            return Err((ErrorCode::Usage, "Missing arguments"));
+        }
+        Ok(FormatConfig {
+            source_files,
+            check,
+        })
+    }
+}
+
+pub fn run(config: &FormatConfig) -> Result<(), Error> {
+    let mut unformatted = 0;
+    for file in &config.source_files {
+        let source = read_source(file)?;
+        let formatted = format(&source);
+        if formatted == source {
+            continue;
+        }
+        check_bytes(file, &source, &formatted)?;
+
+        if config.check {
+            println!("Would reformat {}", file);
+            unformatted += 1;
+        } else {
+            write_file(file, formatted.as_bytes())?;
+        }
+    }
+
+    if unformatted > 0 {
+        return Err(error(
+            ErrorCode::Unformatted,
+            format!(
+                "{} of {} files need formatting",
+                unformatted,
+                config.source_files.len()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// The formatter should never change what a file assembles to, but if it
+// ever does, better to say so than to write the file. A file that doesn't
+// assemble can't be checked, so it's left alone too.
+fn check_bytes(file: &str, source: &str, formatted: &str) -> Result<(), Error> {
+    let assemble = |source: &str| {
+        assemble_with(source, Some(file), &Options::default(), &FileSystem).map(|assembly| {
+            assembly
+                .segments()
+                .into_iter()
+                .map(|segment| (segment.address, segment.data))
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let before = match assemble(source) {
+        Ok(before) => before,
+        Err(diagnostics) => {
+            let (code, message) = diagnostics.into_error();
+            return Err(error(
+                code,
+                format!(
+                    "{} doesn't assemble, so it was left alone: {}",
+                    file, message
+                ),
+            ));
+        }
+    };
+    if assemble(formatted).ok().as_ref() != Some(&before) {
+        return Err(error(
+            ErrorCode::FormatMismatch,
+            format!(
+                "Formatting {} would change what it assembles to, so it was left alone",
+                file
+            ),
+        ));
+    }
+    Ok(())
+}
+
+// A line split into its code and its comment, which starts at the ;
+struct FormatLine<'a> {
+    code: &'a str,
+    comment: Option<&'a str>,
+    indented: bool, // Whether the comment on a line of its own was indented
+}
+
+// Labels go in column 0, and so do directives except a test's steps.
+// Instructions are indented with their operands in a column, and the
+// trailing comments in each run of lines without a blank one line up.
+// Hex is lowercase, but strings and comments are left exactly as they are.
+pub fn format(source: &str) -> String {
+    let lines: Vec<FormatLine> = source
+        .split('\n')
+        .map(|line| {
+            let (code, comment) = split_comment(line);
+            FormatLine {
+                code: code.trim(),
+                comment: comment.map(str::trim_end),
+                indented: line.starts_with(char::is_whitespace),
+            }
+        })
+        .collect();
+
+    // Operands line up after the longest mnemonic, which is usually three letters
+    let width = lines
+        .iter()
+        .filter(|line| is_instruction(line.code))
+        .map(|line| line.code.split_whitespace().next().unwrap_or("").len())
+        .max()
+        .unwrap_or(3);
+
+    let code: Vec<String> = lines
+        .iter()
+        .map(|line| format_code(line.code, width))
+        .collect();
+
+    let mut formatted = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let comment = match line.comment {
+            Some(comment) => comment,
+            None => {
+                formatted.push(code[i].trim_end().to_string());
+                continue;
+            }
+        };
+        if line.code.is_empty() {
+            let indent = if line.indented { INDENT } else { "" };
+            formatted.push(format!("{}{}", indent, comment));
+            continue;
+        }
+
+        // The longest code with a comment, up and down to the nearest blank lines
+        let blank = |j: &usize| lines[*j].code.is_empty() && lines[*j].comment.is_none();
+        let first = (0..i).rev().find(blank).map_or(0, |j| j + 1);
+        let last = (i..lines.len()).find(blank).unwrap_or(lines.len());
+        let column = (first..last)
+            .filter(|j| !lines[*j].code.is_empty() && lines[*j].comment.is_some())
+            .map(|j| code[j].len() + 1)
+            .max()
+            .unwrap_or(0)
+            .max(COMMENT_COLUMN);
+        formatted.push(format!("{:width$}{}", code[i], comment, width = column));
+    }
+
+    // One newline at the end, and no blank lines before it
+    while formatted.last().is_some_and(String::is_empty) {
+        formatted.pop();
+    }
+    if formatted.is_empty() {
+        return String::new();
+    }
+    formatted.join("\n") + "\n"
+}
+
+// A mnemonic and maybe an operand, but not a label with code after it
+fn is_instruction(code: &str) -> bool {
+    let mnemonic = code.split_whitespace().next().unwrap_or("");
+    code.starts_with(|ch: char| ch.is_ascii_alphabetic()) && !mnemonic.ends_with(':')
+}
+
+fn format_code(code: &str, width: usize) -> String {
+    // Labels stay in column 0, even with something after them
+    let first = code.split_whitespace().next().unwrap_or("");
+    if code.is_empty() || first.ends_with(':') {
+        return code.to_string();
+    }
+
+    if let Some(directive) = code.strip_prefix('.') {
+        let (name, value) = match directive.split_once(char::is_whitespace) {
+            Some((name, value)) => (name, normalise(value.trim())),
+            None => (directive, String::new()),
+        };
+        let indent = match name {
+            "given" | "call" | "expect" => INDENT,
+            _ => "",
+        };
+        return format!("{}.{} {}", indent, name, value)
+            .trim_end()
+            .to_string();
+    }
+
+    if is_instruction(code) {
+        let (mnemonic, operand) = code.split_once(char::is_whitespace).unwrap_or((code, ""));
+        // Spaces in a string are part of it, so only operands without one are tidied
+        let operand = if operand.contains(&['"', '\''][..]) {
+            operand.trim().to_string()
+        } else {
+            operand.split_whitespace().collect::<Vec<_>>().join(" ")
+        };
+        return format!(
+            "{}{:width$} {}",
+            INDENT,
+            mnemonic.to_lowercase(),
+            normalise(&operand),
+            width = width
+        )
+        .trim_end()
+        .to_string();
+    }
+
+    // Not something kasm knows, so it's left for the assembler to complain about
+    format!("{}{}", INDENT, code)
+}
+
+// The code and the comment, split at the first ; that isn't in a string
+fn split_comment(line: &str) -> (&str, Option<&str>) {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return (&line[..i], Some(&line[i..])),
+            _ => {}
+        }
+    }
+    (line, None)
+}
+
+// Lowercase hex, leaving strings alone
+fn normalise(code: &str) -> String {
+    let mut normalised = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut hex = false;
+    for ch in code.chars() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ => {}
+        }
+        hex = match ch {
+            '$' => !in_string,
+            _ => hex && ch.is_ascii_alphanumeric(),
+        };
+        normalised.push(if hex { ch.to_ascii_lowercase() } else { ch });
+    }
+    normalised
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_bytes;
+    use super::format;
+    use crate::assembly::assemble;
+    use crate::assembly::Options;
+
+    const MESSY: &str = "; A program\n\n  .org $E000\nreset:\n\tLDA #$2B ; Load $2B\n  sta  $A45F   ; Store it\n\tBBR0 $12,reset\n     ; Indented\n\nmessage:\n.ascii \"Semi; $FF colons\" ; \"quoted\" ; comment\n\n\n.byte $0A,$FF\n.test \"t\"\n.call reset\n.expect a=$2B\n.endtest\n\n";
+
+    #[test]
+    fn test_format() {
+        assert_eq!(
+            format(MESSY),
+            "; A program\n\
+             \n\
+             .org $e000\n\
+             reset:\n    \
+             lda  #$2b           ; Load $2B\n    \
+             sta  $a45f          ; Store it\n    \
+             bbr0 $12,reset\n    \
+             ; Indented\n\
+             \n\
+             message:\n\
+             .ascii \"Semi; $FF colons\" ; \"quoted\" ; comment\n\
+             \n\
+             \n\
+             .byte $0a,$ff\n\
+             .test \"t\"\n    \
+             .call reset\n    \
+             .expect a=$2b\n\
+             .endtest\n"
+        );
+
+        // Formatting again changes nothing
+        assert_eq!(format(&format(MESSY)), format(MESSY));
+        assert_eq!(format("\n\n"), "");
+    }
+
+    #[test]
+    fn test_strings_and_labels() {
+        // Spaces in strings stay put, and a label with code after it isn't a mnemonic
+        assert_eq!(
+            format("  LDA  #\"a  b\"\n  RESET: JMP RESET\n"),
+            "    lda #\"a  b\"\nRESET: JMP RESET\n"
+        );
+    }
+
+    #[test]
+    fn test_check_bytes() {
+        if check_bytes("bad.s", "  nop\n  bogus\n", "    nop\n    bogus\n").is_ok() {
+            panic!("Did not expect a file that doesn't assemble to be rewritten");
+        }
+        if check_bytes("good.s", "  NOP\n", "    nop\n").is_err() {
+            panic!("Did not expect formatting to change the bytes");
+        }
+    }
+
+    #[test]
+    fn test_mixed_case() {
+        // Capitals in operands assemble, so files written that way can be tidied
+        let source = "  .org $E000\nreset:\n\tLDA #$2B\n  STA  $A45F,X\n\tBNE reset\n";
+        assert_eq!(
+            format(source),
+            ".org $e000\nreset:\n    lda #$2b\n    sta $a45f,X\n    bne reset\n"
+        );
+        if let Err(err) = check_bytes("mixed.s", source, &format(source)) {
+            panic!("Did not expect formatting to be refused: {}", err.1);
+        }
+    }
+
+    #[test]
+    fn test_same_bytes() {
+        let bytes = |source: &str| match assemble(source, &Options::default()) {
+            Ok(assembly) => assembly
+                .segments()
+                .into_iter()
+                .map(|segment| (segment.address, segment.data))
+                .collect::<Vec<_>>(),
+            Err(_) => panic!("Did not expect the source to fail to assemble:\n{}", source),
+        };
+        let source = ".org $e000\nreset:\n\tLDA #$2b ; Load\n  STA  $a45f\n\tbne reset\n.byte $0A,$FF\n  .vectors reset=reset\n";
+        assert_eq!(bytes(&format(source)), bytes(source));
+    }
+}
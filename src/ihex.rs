@@ -0,0 +1,218 @@
+// Intel HEX, as used by most EEPROM programmers
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::image::Image;
+use crate::image::Segment;
+use crate::pass1::Address;
+
+const BYTES_PER_RECORD: usize = 16;
+
+const DATA: u8 = 0x00;
+const END_OF_FILE: u8 = 0x01;
+const EXTENDED_SEGMENT_ADDRESS: u8 = 0x02;
+const START_SEGMENT_ADDRESS: u8 = 0x03;
+const EXTENDED_LINEAR_ADDRESS: u8 = 0x04;
+const START_LINEAR_ADDRESS: u8 = 0x05;
+
+fn record(record_type: u8, address: u16, data: &[u8]) -> String {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(record_type);
+    bytes.extend_from_slice(data);
+
+    // The checksum is the two's complement of the sum of every other byte
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(sum.wrapping_neg());
+
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(":{}\n", hex.concat())
+}
+
+// Only the segments are written, the gaps between them are left out
+pub fn to_ihex(segments: &[Segment]) -> String {
+    let mut ihex = String::new();
+    let mut upper = 0; // The upper 16 bits of the address currently in effect
+
+    for segment in segments {
+        let mut address = segment.address;
+        let mut data = &segment.data[..];
+
+        while !data.is_empty() {
+            if address >> 16 != upper {
+                upper = address >> 16;
+                ihex.push_str(&record(
+                    EXTENDED_LINEAR_ADDRESS,
+                    0,
+                    &(upper as u16).to_be_bytes(),
+                ));
+            }
+
+            // Records can't wrap around a 64K boundary
+            let room = 0x10000 - (address & 0xffff) as usize;
+            let size = data.len().min(BYTES_PER_RECORD).min(room);
+            ihex.push_str(&record(DATA, address as u16, &data[..size]));
+
+            address += size as u32;
+            data = &data[size..];
+        }
+    }
+
+    ihex.push_str(&record(END_OF_FILE, 0, &[]));
+    ihex
+}
+
+// Read the records back into segments. Only the 64K a 6502 can see is
+// supported, so data can't land above it, and start addresses are ignored.
+pub fn from_ihex(ihex: &str) -> Result<Vec<Segment>, Error> {
+    let mut image = Image::new();
+    let mut base = 0; // From the last extended address record
+
+    for (index, raw_line) in ihex.lines().enumerate() {
+        let line = index + 1;
+        let text = raw_line.trim();
+        if text.is_empty() {
+            continue;
+        }
+
+        let bytes = match text.strip_prefix(':').map(hex_to_bytes) {
+            Some(Some(bytes)) if bytes.len() >= 5 && bytes.len() == bytes[0] as usize + 5 => bytes,
+            _ => return Err(malformed(line, "isn't a valid record")),
+        };
+        if bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte)) != 0 {
+            return Err(malformed(line, "has a bad checksum"));
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]);
+        let data = &bytes[4..bytes.len() - 1];
+        match bytes[3] {
+            DATA => {
+                for (offset, byte) in data.iter().enumerate() {
+                    let at = base + address as usize + offset;
+                    if at > 0xffff {
+                        return Err(malformed(line, "is above 64K"));
+                    }
+                    if image.insert(at as Address, *byte, 0, line as u16).is_err() {
+                        return Err(malformed(line, "overlaps an earlier record"));
+                    }
+                }
+            }
+            END_OF_FILE => break,
+            EXTENDED_SEGMENT_ADDRESS | EXTENDED_LINEAR_ADDRESS if data.len() == 2 => {
+                let value = u16::from_be_bytes([data[0], data[1]]) as usize;
+                base = match bytes[3] {
+                    EXTENDED_SEGMENT_ADDRESS => value << 4,
+                    _ => value << 16,
+                };
+            }
+            START_SEGMENT_ADDRESS | START_LINEAR_ADDRESS => {}
+            _ => return Err(malformed(line, "isn't a record type kasm knows")),
+        }
+    }
+
+    Ok(image.segments())
+}
+
+fn hex_to_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|at| u8::from_str_radix(hex.get(at..at + 2)?, 16).ok())
+        .collect()
+}
+
+fn malformed(line: usize, problem: &str) -> Error {
+    error(
+        ErrorCode::MalformedHex,
+        format!("Intel HEX record at line {} {}", line, problem),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::from_ihex;
+    use super::record;
+    use super::to_ihex;
+    use crate::image::Segment;
+
+    #[test]
+    fn test_record_checksum() {
+        assert_eq!(
+            record(0x00, 0x0030, &[0x02, 0x33, 0x7a]),
+            ":0300300002337A1E\n"
+        );
+        assert_eq!(record(0x01, 0x0000, &[]), ":00000001FF\n");
+    }
+
+    #[test]
+    fn test_to_ihex() {
+        let segments = vec![
+            Segment {
+                address: 0x1000,
+                data: (0..18).collect(),
+            },
+            Segment {
+                address: 0xfffc,
+                data: vec![0x00, 0x10],
+            },
+        ];
+        assert_eq!(
+            to_ihex(&segments),
+            ":10100000000102030405060708090A0B0C0D0E0F68\n\
+             :021010001011BD\n\
+             :02FFFC000010F3\n\
+             :00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn test_from_ihex() {
+        let segments = vec![
+            Segment {
+                address: 0x1000,
+                data: (0..18).collect(),
+            },
+            Segment {
+                address: 0xfffc,
+                data: vec![0x00, 0x10],
+            },
+        ];
+        if let Ok(read) = from_ihex(&to_ihex(&segments)) {
+            assert_eq!(read.len(), 2);
+            assert_eq!(read[0].address, 0x1000);
+            assert_eq!(read[0].data, segments[0].data);
+            assert_eq!(read[1].address, 0xfffc);
+            assert_eq!(read[1].data, segments[1].data);
+        } else {
+            panic!("Did not expect reading back Intel HEX to error");
+        };
+        if from_ihex(":0300300002337A1F\n").is_ok() {
+            panic!("Did not expect a bad checksum to work");
+        }
+        let above = record(0x04, 0, &[0x00, 0x01]) + &record(0x00, 0, &[0xea]);
+        if from_ihex(&above).is_ok() {
+            panic!("Did not expect an address above 64K to work");
+        }
+        let segment = record(0x02, 0, &[0x10, 0x00]) + &record(0x00, 0xfff, &[0xea]);
+        if from_ihex(&segment).is_ok() {
+            panic!("Did not expect a segment address above 64K to work");
+        }
+
+        // Start addresses don't matter, and moving the base is fine if data stays low
+        let ihex = record(0x02, 0, &[0x01, 0x00])
+            + &record(0x00, 0x10, &[0xea])
+            + &record(0x03, 0, &[0x00, 0x00, 0x10, 0x00])
+            + &record(0x05, 0, &[0x00, 0x01, 0x00, 0x00])
+            + &record(0x01, 0, &[]);
+        match from_ihex(&ihex) {
+            Ok(read) => {
+                assert_eq!(read.len(), 1);
+                assert_eq!(read[0].address, 0x1010);
+                assert_eq!(read[0].data, vec![0xea]);
+            }
+            Err(_) => panic!("Did not expect start address records to error"),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+// A sparse memory image, built up a byte at a time in whatever order
+// the source happens to emit them
+use std::collections::BTreeMap;
+
+use crate::pass1::Address;
+use crate::scanner::FileId;
+use crate::scanner::Line;
+
+pub type MachineCode = Vec<u8>;
+
+// A run of contiguous bytes, usually everything following an .org
+pub struct Segment {
+    pub address: Address,
+    pub data: MachineCode,
+}
+
+#[derive(Default)]
+pub struct Image {
+    bytes: BTreeMap<Address, (u8, FileId, Line)>, // Each byte, and the line that emitted it
+}
+
+impl Image {
+    pub fn new() -> Image {
+        Image {
+            bytes: BTreeMap::new(),
+        }
+    }
+
+    // If the address is already taken we hand back the line that took it
+    pub fn insert(
+        &mut self,
+        address: Address,
+        byte: u8,
+        file: FileId,
+        line: Line,
+    ) -> Result<(), (FileId, Line)> {
+        if let Some((_, file, line)) = self.bytes.get(&address) {
+            return Err((*file, *line));
+        }
+        self.bytes.insert(address, (byte, file, line));
+        Ok(())
+    }
+
+    // Overwrite a byte that's already been placed, keeping its line
+    pub fn patch(&mut self, address: Address, byte: u8) {
+        if let Some(entry) = self.bytes.get_mut(&address) {
+            entry.0 = byte;
+        }
+    }
+
+    pub fn get(&self, address: Address) -> Option<u8> {
+        self.bytes.get(&address).map(|(byte, _, _)| *byte)
+    }
+
+    // Collect the bytes into runs of contiguous addresses
+    pub fn segments(&self) -> Vec<Segment> {
+        let mut segments: Vec<Segment> = Vec::new();
+
+        for (address, (byte, _, _)) in &self.bytes {
+            match segments.last_mut() {
+                Some(segment)
+                    if segment.address as usize + segment.data.len() == *address as usize =>
+                {
+                    segment.data.push(*byte)
+                }
+                _ => segments.push(Segment {
+                    address: *address,
+                    data: vec![*byte],
+                }),
+            }
+        }
+
+        segments
+    }
+}
+
+// Lay the segments out in a single image beginning at the start address,
+// nothing below the start address is included and gaps are filled in
+pub fn flatten(segments: Vec<Segment>, start: Address, fill: u8) -> MachineCode {
+    let mut output = MachineCode::new();
+
+    for segment in segments {
+        let skip = (start as usize).saturating_sub(segment.address as usize);
+        let offset = (segment.address as usize).saturating_sub(start as usize);
+        if output.len() < offset {
+            output.resize(offset, fill);
+        }
+        output.extend(segment.data.iter().skip(skip));
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::flatten;
+    use super::Image;
+
+    #[test]
+    fn test_segments() {
+        let mut image = Image::new();
+        for (address, byte) in &[(0x1001, 0x02), (0x2000, 0x03), (0x1000, 0x01)] {
+            if image.insert(*address, *byte, 0, 1).is_err() {
+                panic!("Did not expect ${:04x} to be taken", address);
+            }
+        }
+        let segments = image.segments();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].address, 0x1000);
+        assert_eq!(segments[0].data, vec![0x01, 0x02]);
+        assert_eq!(segments[1].address, 0x2000);
+        assert_eq!(segments[1].data, vec![0x03]);
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut image = Image::new();
+        for (address, byte) in &[(0x7fff, 0x01), (0x8001, 0x02)] {
+            if image.insert(*address, *byte, 0, 1).is_err() {
+                panic!("Did not expect ${:04x} to be taken", address);
+            }
+        }
+        assert_eq!(flatten(image.segments(), 0x8000, 0x00), vec![0x00, 0x02]);
+        assert_eq!(
+            flatten(image.segments(), 0x7ffe, 0xff),
+            vec![0xff, 0x01, 0xff, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_overlap() {
+        let mut image = Image::new();
+        assert_eq!(image.insert(0x1000, 0x01, 0, 3), Ok(()));
+        assert_eq!(image.insert(0x1000, 0x02, 1, 7), Err((0, 3)));
+        assert_eq!(image.get(0x1000), Some(0x01));
+    }
+}
@@ -18,12 +18,27 @@ pub enum AddressMode {
     Zeropage,
     ZeropageX,
     ZeropageY,
+    ZeropageIndirect,        // ($12) on the 65C02
+    AbsoluteIndexedIndirect, // ($1234,x), only for jmp
+    ZeropageRelative,        // $12,label for bbr and bbs
+    AbsoluteLong,            // $123456 or long:label on the 65816
+    AbsoluteLongX,           // $123456,x
+    DirectIndirectLong,      // [$12]
+    DirectIndirectLongY,     // [$12],y
+    StackRelative,           // $12,s
+    StackRelativeIndirectY,  // ($12,s),y
+    BlockMove,               // $01,$02 for mvn and mvp, the source bank then the destination
+    RelativeLong,            // label for brl and per, a 16 bit offset
+    AbsoluteIndirectLong,    // [$1234], only for jml
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub enum Mnemonic {
     ADC,
+    ALR,
+    ANC,
     AND,
+    ARR,
     ASL,
     BBR0,
     BBR1,
@@ -50,6 +65,7 @@ pub enum Mnemonic {
     BPL,
     BRA,
     BRK,
+    BRL,
     BVC,
     BVS,
     CLC,
@@ -57,8 +73,10 @@ pub enum Mnemonic {
     CLI,
     CLV,
     CMP,
+    COP,
     CPX,
     CPY,
+    DCP,
     DEC,
     DEX,
     DEY,
@@ -66,22 +84,38 @@ pub enum Mnemonic {
     INC,
     INX,
     INY,
+    ISC,
+    JML,
     JMP,
+    JSL,
     JSR,
+    LAX,
     LDA,
     LDX,
     LDY,
     LSR,
+    MVN,
+    MVP,
     NOP,
     ORA,
+    PEA,
+    PEI,
+    PER,
     PHA,
+    PHB,
+    PHD,
+    PHK,
     PHP,
     PHX,
     PHY,
     PLA,
+    PLB,
+    PLD,
     PLP,
     PLX,
     PLY,
+    REP,
+    RLA,
     RMB0,
     RMB1,
     RMB2,
@@ -92,12 +126,18 @@ pub enum Mnemonic {
     RMB7,
     ROL,
     ROR,
+    RRA,
     RTI,
+    RTL,
     RTS,
+    SAX,
     SBC,
+    SBX,
     SEC,
     SED,
     SEI,
+    SEP,
+    SLO,
     SMB0,
     SMB1,
     SMB2,
@@ -106,6 +146,7 @@ pub enum Mnemonic {
     SMB5,
     SMB6,
     SMB7,
+    SRE,
     STA,
     STP,
     STX,
@@ -113,23 +154,33 @@ pub enum Mnemonic {
     STZ,
     TAX,
     TAY,
+    TCD,
+    TCS,
+    TDC,
     TRB,
     TSB,
+    TSC,
     TSX,
     TXA,
     TXS,
+    TXY,
     TYA,
+    TYX,
     WAI,
+    WDM,
+    XBA,
+    XCE,
 }
 
-#[derive(PartialEq, Eq, Hash)]
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
 pub struct InstructionKey {
     pub mnemonic: Mnemonic,
     pub address_mode: AddressMode,
 }
 
-type Opcode = u8;
+pub type Opcode = u8;
 pub type InstructionMap = HashMap<InstructionKey, Opcode>;
+pub type OpcodeMap = HashMap<Opcode, InstructionKey>;
 
 // returns the length in bytes of instruction for the given the AddressMode
 pub fn address_mode_length(address_mode: AddressMode) -> u16 {
@@ -146,9 +197,252 @@ pub fn address_mode_length(address_mode: AddressMode) -> u16 {
         AddressMode::Zeropage => 2,
         AddressMode::ZeropageX => 2,
         AddressMode::ZeropageY => 2,
+        AddressMode::ZeropageIndirect => 2,
+        AddressMode::AbsoluteIndexedIndirect => 3,
+        AddressMode::ZeropageRelative => 3,
+        AddressMode::AbsoluteLong => 4,
+        AddressMode::AbsoluteLongX => 4,
+        AddressMode::DirectIndirectLong => 2,
+        AddressMode::DirectIndirectLongY => 2,
+        AddressMode::StackRelative => 2,
+        AddressMode::StackRelativeIndirectY => 2,
+        AddressMode::BlockMove => 3,
+        AddressMode::RelativeLong => 3,
+        AddressMode::AbsoluteIndirectLong => 3,
+    }
+}
+
+// How long an instruction takes on a 65C02, or a 65816 with 8 bit registers
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Cycles {
+    pub base: u8,
+    pub page_cross: bool, // One more when indexing, or bra's branch, crosses a page
+    pub branch: bool,     // One more when taken, and another crossing a page
+}
+
+impl Cycles {
+    // Branches not taken and no pages crossed
+    pub fn min(&self) -> u32 {
+        self.base as u32
+    }
+
+    // Taken branches that cross a page, and indexing that crosses one
+    pub fn max(&self) -> u32 {
+        self.min() + self.page_cross as u32 + 2 * self.branch as u32
+    }
+}
+
+pub fn instruction_cycles(key: InstructionKey) -> Cycles {
+    use AddressMode::*;
+    use Mnemonic::*;
+
+    let reads = matches!(
+        key.mnemonic,
+        ADC | AND | BIT | CMP | CPX | CPY | EOR | LDA | LDX | LDY | ORA | SBC | LAX
+    );
+    let shifts = matches!(key.mnemonic, ASL | LSR | ROL | ROR);
+    let combined = matches!(key.mnemonic, DCP | ISC | RLA | RRA | SLO | SRE);
+    let modifies = shifts
+        || combined
+        || is_bit_op(key.mnemonic)
+        || matches!(key.mnemonic, INC | DEC | TRB | TSB);
+
+    let base = match (key.mnemonic, key.address_mode) {
+        (BRK, _) | (COP, _) | (MVN, _) | (MVP, _) => 7,
+        (JSL, _) | (JSR, AbsoluteIndexedIndirect) => 8,
+        (JSR, _) | (RTS, _) | (RTI, _) | (RTL, _) | (PEI, _) | (PER, _) => 6,
+        (JMP, Absolute) | (BRA, _) => 3, // bra is always taken
+        (JMP, _) => 6,
+        (JML, AbsoluteLong) | (BRL, _) | (PHD, _) | (PLB, _) => 4,
+        (JML, _) => 6,
+        (PEA, _) | (PLD, _) => 5,
+        (PHA, _) | (PHP, _) | (PHX, _) | (PHY, _) | (WAI, _) | (STP, _) => 3,
+        (PHB, _) | (PHK, _) | (REP, _) | (SEP, _) | (XBA, _) => 3,
+        (PLA, _) | (PLP, _) | (PLX, _) | (PLY, _) => 4,
+        (_, ZeropageRelative) => 5,
+        (_, Relative) | (_, Implied) | (_, Immediate) => 2,
+        // The undocumented read-modify-writes don't get the indexed shortcuts
+        (_, AbsoluteY) if combined => 7,
+        (_, IndirectX) | (_, IndirectY) if combined => 8,
+        (_, Zeropage) if modifies => 5,
+        (_, Zeropage) => 3,
+        (_, ZeropageX) | (_, ZeropageY) if modifies => 6,
+        (_, ZeropageX) | (_, ZeropageY) => 4,
+        (_, Absolute) if modifies => 6,
+        (_, Absolute) => 4,
+        (_, AbsoluteX) if shifts => 6,
+        (_, AbsoluteX) if modifies => 7,
+        (_, AbsoluteX) | (_, AbsoluteY) if reads => 4,
+        (_, AbsoluteX) | (_, AbsoluteY) => 5,
+        (_, IndirectX) => 6,
+        (_, IndirectY) if reads => 5,
+        (_, IndirectY) => 6,
+        (_, ZeropageIndirect) => 5,
+        (_, Indirect) | (_, AbsoluteIndexedIndirect) => 6,
+        (_, StackRelative) => 4,
+        (_, StackRelativeIndirectY) => 7,
+        (_, AbsoluteLong) | (_, AbsoluteLongX) => 5,
+        (_, DirectIndirectLong) | (_, DirectIndirectLongY) | (_, AbsoluteIndirectLong) => 6,
+        (_, BlockMove) | (_, RelativeLong) => 7, // Only mvn, mvp, brl and per, above
+    };
+
+    Cycles {
+        base,
+        page_cross: (reads && matches!(key.address_mode, AbsoluteX | AbsoluteY | IndirectY))
+            || (shifts && key.address_mode == AbsoluteX)
+            || key.mnemonic == BRA,
+        branch: key.mnemonic != BRA && matches!(key.address_mode, Relative | ZeropageRelative),
+    }
+}
+
+// rmb and smb, which read, modify and write a zeropage byte
+fn is_bit_op(mnemonic: Mnemonic) -> bool {
+    let name = mnemonic_to_str(mnemonic);
+    name.starts_with("rmb") || name.starts_with("smb")
+}
+
+// The instruction set the other way around, for the disassembler. That and
+// the simulator are 65C02s, and both the 65816 and the NMOS undocumented
+// instructions reuse its opcodes.
+pub fn generate_opcode_table(instruction_set: &InstructionMap) -> OpcodeMap {
+    instruction_set
+        .iter()
+        .filter(|(key, _)| available(**key, Cpu::W65C02))
+        .map(|(key, opcode)| (*opcode, *key))
+        .collect()
+}
+
+// Each CPU has every instruction the one before it has, see available
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Cpu {
+    Mos6502,   // The original NMOS part
+    Mos6502X,  // The NMOS part's stable undocumented opcodes too, and nothing later
+    Cmos65C02, // Adds stz, bra, phx and friends, and (zp) addressing
+    R65C02,    // Rockwell's, adds the bit instructions rmb, smb, bbr and bbs
+    W65C02,    // WDC's, adds wai and stp
+    W65816,    // 16 bits and 24 bit addresses, but without the bit instructions
+}
+
+pub fn str_to_cpu(cpu: &str) -> Option<Cpu> {
+    match cpu {
+        "6502" => Some(Cpu::Mos6502),
+        "6502x" => Some(Cpu::Mos6502X),
+        "65c02" => Some(Cpu::Cmos65C02),
+        "r65c02" => Some(Cpu::R65C02),
+        "w65c02" => Some(Cpu::W65C02),
+        "65816" => Some(Cpu::W65816),
+        _ => None,
+    }
+}
+
+pub fn cpu_to_str(cpu: Cpu) -> &'static str {
+    match cpu {
+        Cpu::Mos6502 => "6502",
+        Cpu::Mos6502X => "6502x",
+        Cpu::Cmos65C02 => "65c02",
+        Cpu::R65C02 => "r65c02",
+        Cpu::W65C02 => "w65c02",
+        Cpu::W65816 => "65816",
+    }
+}
+
+// The first CPU to have an instruction
+pub fn minimum_cpu(key: InstructionKey) -> Cpu {
+    use AddressMode::*;
+    use Mnemonic::*;
+
+    match (key.mnemonic, key.address_mode) {
+        (BRL, _) | (COP, _) | (JML, _) | (JSL, _) | (MVN, _) | (MVP, _) | (PEA, _) | (PEI, _) => {
+            Cpu::W65816
+        }
+        (PER, _) | (PHB, _) | (PHD, _) | (PHK, _) | (PLB, _) | (PLD, _) | (REP, _) | (RTL, _) => {
+            Cpu::W65816
+        }
+        (SEP, _) | (TCD, _) | (TCS, _) | (TDC, _) | (TSC, _) | (TXY, _) | (TYX, _) | (WDM, _) => {
+            Cpu::W65816
+        }
+        (XBA, _) | (XCE, _) | (JSR, AbsoluteIndexedIndirect) => Cpu::W65816,
+        (_, AbsoluteLong) | (_, AbsoluteLongX) | (_, DirectIndirectLong) => Cpu::W65816,
+        (_, DirectIndirectLongY) | (_, StackRelative) | (_, StackRelativeIndirectY) => Cpu::W65816,
+        (WAI, _) | (STP, _) => Cpu::W65C02,
+        _ if is_undocumented(key.mnemonic) => Cpu::Mos6502X,
+        _ if is_bit_op(key.mnemonic) || is_bit_branch(key.mnemonic) => Cpu::R65C02,
+        (BRA, _) | (PHX, _) | (PHY, _) | (PLX, _) | (PLY, _) | (STZ, _) | (TRB, _) | (TSB, _) => {
+            Cpu::Cmos65C02
+        }
+        (INC, Implied) | (DEC, Implied) => Cpu::Cmos65C02,
+        (BIT, Immediate) | (BIT, ZeropageX) | (BIT, AbsoluteX) => Cpu::Cmos65C02,
+        (_, ZeropageIndirect) | (_, AbsoluteIndexedIndirect) => Cpu::Cmos65C02,
+        _ => Cpu::Mos6502,
     }
 }
 
+// Whether a CPU has an instruction. The 65816 has everything the W65C02
+// has except the bit instructions, which it uses the opcodes of. The
+// undocumented instructions are only for a 6502x, the CMOS parts made them
+// nops or used their opcodes.
+pub fn available(key: InstructionKey, cpu: Cpu) -> bool {
+    match (minimum_cpu(key), cpu) {
+        (Cpu::Mos6502X, cpu) => cpu == Cpu::Mos6502X,
+        (needs, Cpu::Mos6502X) => needs == Cpu::Mos6502,
+        (Cpu::R65C02, Cpu::W65816) => false,
+        (needs, cpu) => needs <= cpu,
+    }
+}
+
+// The NMOS 6502's undocumented instructions that behave the same on every part
+fn is_undocumented(mnemonic: Mnemonic) -> bool {
+    use Mnemonic::*;
+
+    matches!(
+        mnemonic,
+        ALR | ANC | ARR | DCP | ISC | LAX | RLA | RRA | SAX | SBX | SLO | SRE
+    )
+}
+
+// Branches take the target address and the assembler works out the offset
+pub fn is_branch(mnemonic: Mnemonic) -> bool {
+    matches!(
+        mnemonic,
+        Mnemonic::BCC
+            | Mnemonic::BCS
+            | Mnemonic::BEQ
+            | Mnemonic::BMI
+            | Mnemonic::BNE
+            | Mnemonic::BPL
+            | Mnemonic::BRA
+            | Mnemonic::BVC
+            | Mnemonic::BVS
+    )
+}
+
+// bbr and bbs test a bit in zeropage, then branch like the above
+pub fn is_bit_branch(mnemonic: Mnemonic) -> bool {
+    matches!(
+        mnemonic,
+        Mnemonic::BBR0
+            | Mnemonic::BBR1
+            | Mnemonic::BBR2
+            | Mnemonic::BBR3
+            | Mnemonic::BBR4
+            | Mnemonic::BBR5
+            | Mnemonic::BBR6
+            | Mnemonic::BBR7
+            | Mnemonic::BBS0
+            | Mnemonic::BBS1
+            | Mnemonic::BBS2
+            | Mnemonic::BBS3
+            | Mnemonic::BBS4
+            | Mnemonic::BBS5
+            | Mnemonic::BBS6
+            | Mnemonic::BBS7
+    )
+}
+
+pub fn mnemonic_to_str(mnemonic: Mnemonic) -> String {
+    format!("{:?}", mnemonic).to_lowercase()
+}
+
 // Something will inevitably wrong in the below!
 pub fn generate_instruction_set() -> InstructionMap {
     let mut instruction_set = InstructionMap::new();
@@ -159,13 +453,6 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x69,
     );
-    instruction_set.insert(
-        InstructionKey {
-            mnemonic: Mnemonic::ADC,
-            address_mode: AddressMode::Immediate,
-        },
-        0x69,
-    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::ADC,
@@ -215,6 +502,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x71,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0x72,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::AND,
@@ -273,10 +567,10 @@ pub fn generate_instruction_set() -> InstructionMap {
     );
     instruction_set.insert(
         InstructionKey {
-            mnemonic: Mnemonic::ASL,
-            address_mode: AddressMode::Implied,
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::ZeropageIndirect,
         },
-        0x0a,
+        0x32,
     );
     instruction_set.insert(
         InstructionKey {
@@ -306,115 +600,122 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x1e,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ASL,
+            address_mode: AddressMode::Implied,
+        },
+        0x0a,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR0,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x0f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR1,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x1f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR2,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x2f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR3,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x3f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR4,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x4f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR5,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x5f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR6,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x6f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBR7,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x7f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS0,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x8f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS1,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0x9f,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS2,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xaf,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS3,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xbf,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS4,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xcf,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS5,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xdf,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS6,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xef,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BBS7,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::ZeropageRelative,
         },
         0xff,
     );
@@ -442,9 +743,9 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BIT,
-            address_mode: AddressMode::Absolute,
+            address_mode: AddressMode::Immediate,
         },
-        0x2c,
+        0x89,
     );
     instruction_set.insert(
         InstructionKey {
@@ -453,6 +754,27 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x24,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::BIT,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0x34,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::BIT,
+            address_mode: AddressMode::Absolute,
+        },
+        0x2c,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::BIT,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x3c,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::BMI,
@@ -586,10 +908,17 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xd1,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0xd2,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::CPX,
-            address_mode: AddressMode::Implied,
+            address_mode: AddressMode::Immediate,
         },
         0xe0,
     );
@@ -610,7 +939,7 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::CPY,
-            address_mode: AddressMode::Implied,
+            address_mode: AddressMode::Immediate,
         },
         0xc0,
     );
@@ -656,6 +985,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xde,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DEC,
+            address_mode: AddressMode::Implied,
+        },
+        0x3a,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::DEX,
@@ -689,7 +1025,7 @@ pub fn generate_instruction_set() -> InstructionMap {
             mnemonic: Mnemonic::EOR,
             address_mode: AddressMode::ZeropageX,
         },
-        0x45,
+        0x55,
     );
     instruction_set.insert(
         InstructionKey {
@@ -728,10 +1064,10 @@ pub fn generate_instruction_set() -> InstructionMap {
     );
     instruction_set.insert(
         InstructionKey {
-            mnemonic: Mnemonic::INC,
-            address_mode: AddressMode::Implied,
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::ZeropageIndirect,
         },
-        0x1a,
+        0x52,
     );
     instruction_set.insert(
         InstructionKey {
@@ -761,6 +1097,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xfe,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::INC,
+            address_mode: AddressMode::Implied,
+        },
+        0x1a,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::INX,
@@ -789,6 +1132,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x6c,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::JMP,
+            address_mode: AddressMode::AbsoluteIndexedIndirect,
+        },
+        0x7c,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::JSR,
@@ -852,6 +1202,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xb1,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0xb2,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::LDX,
@@ -869,7 +1226,7 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::LDX,
-            address_mode: AddressMode::ZeropageX,
+            address_mode: AddressMode::ZeropageY,
         },
         0xb6,
     );
@@ -883,7 +1240,7 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::LDX,
-            address_mode: AddressMode::AbsoluteX,
+            address_mode: AddressMode::AbsoluteY,
         },
         0xbe,
     );
@@ -922,13 +1279,6 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xbc,
     );
-    instruction_set.insert(
-        InstructionKey {
-            mnemonic: Mnemonic::LSR,
-            address_mode: AddressMode::Immediate,
-        },
-        0x4a,
-    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::LSR,
@@ -957,6 +1307,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x5e,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LSR,
+            address_mode: AddressMode::Implied,
+        },
+        0x4a,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::NOP,
@@ -1020,6 +1377,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x11,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0x12,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::PHA,
@@ -1065,14 +1429,14 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::PLX,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::Implied,
         },
         0xfa,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::PLY,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::Implied,
         },
         0x7a,
     );
@@ -1132,13 +1496,6 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x77,
     );
-    instruction_set.insert(
-        InstructionKey {
-            mnemonic: Mnemonic::ROL,
-            address_mode: AddressMode::Implied,
-        },
-        0x2a,
-    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::ROL,
@@ -1169,10 +1526,10 @@ pub fn generate_instruction_set() -> InstructionMap {
     );
     instruction_set.insert(
         InstructionKey {
-            mnemonic: Mnemonic::ROR,
+            mnemonic: Mnemonic::ROL,
             address_mode: AddressMode::Implied,
         },
-        0x6a,
+        0x2a,
     );
     instruction_set.insert(
         InstructionKey {
@@ -1202,6 +1559,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x7e,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ROR,
+            address_mode: AddressMode::Implied,
+        },
+        0x6a,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::RTI,
@@ -1272,6 +1636,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0xf1,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0xf2,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::SEC,
@@ -1398,6 +1769,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x91,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0x92,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STP,
@@ -1410,42 +1788,42 @@ pub fn generate_instruction_set() -> InstructionMap {
             mnemonic: Mnemonic::STX,
             address_mode: AddressMode::Zeropage,
         },
-        0x85,
+        0x86,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STX,
             address_mode: AddressMode::ZeropageY,
         },
-        0x95,
+        0x96,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STX,
             address_mode: AddressMode::Absolute,
         },
-        0x8d,
+        0x8e,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STY,
             address_mode: AddressMode::Zeropage,
         },
-        0x85,
+        0x84,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STY,
             address_mode: AddressMode::ZeropageX,
         },
-        0x95,
+        0x94,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::STY,
             address_mode: AddressMode::Absolute,
         },
-        0x8d,
+        0x8c,
     );
     instruction_set.insert(
         InstructionKey {
@@ -1459,7 +1837,7 @@ pub fn generate_instruction_set() -> InstructionMap {
             mnemonic: Mnemonic::STZ,
             address_mode: AddressMode::ZeropageX,
         },
-        0x94,
+        0x74,
     );
     instruction_set.insert(
         InstructionKey {
@@ -1468,6 +1846,13 @@ pub fn generate_instruction_set() -> InstructionMap {
         },
         0x9c,
     );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STZ,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x9e,
+    );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::TAX,
@@ -1485,30 +1870,30 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::TRB,
-            address_mode: AddressMode::Absolute,
+            address_mode: AddressMode::Zeropage,
         },
-        0x1c,
+        0x14,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::TRB,
-            address_mode: AddressMode::Zeropage,
+            address_mode: AddressMode::Absolute,
         },
-        0x14,
+        0x1c,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::TSB,
-            address_mode: AddressMode::Absolute,
+            address_mode: AddressMode::Zeropage,
         },
-        0x0c,
+        0x04,
     );
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::TSB,
-            address_mode: AddressMode::Zeropage,
+            address_mode: AddressMode::Absolute,
         },
-        0x04,
+        0x0c,
     );
     instruction_set.insert(
         InstructionKey {
@@ -1541,37 +1926,967 @@ pub fn generate_instruction_set() -> InstructionMap {
     instruction_set.insert(
         InstructionKey {
             mnemonic: Mnemonic::WAI,
-            address_mode: AddressMode::Relative,
+            address_mode: AddressMode::Implied,
         },
         0xcb,
     );
-    instruction_set
-}
-
-#[derive(Eq, PartialEq, Debug)]
-pub enum Value {
-    String(String),
-    U8(u8),
-    U16(u16),
-    Null,
-}
-
-// TODO: Shoudl this be Option instead of Result?
-pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
-    match instruction {
-        "adc" => Ok(Mnemonic::ADC),
-        "and" => Ok(Mnemonic::AND),
-        "asl" => Ok(Mnemonic::ASL),
-        "bbr0" => Ok(Mnemonic::BBR0),
-        "bbr1" => Ok(Mnemonic::BBR1),
-        "bbr2" => Ok(Mnemonic::BBR2),
-        "bbr3" => Ok(Mnemonic::BBR3),
-        "bbr4" => Ok(Mnemonic::BBR4),
-        "bbr5" => Ok(Mnemonic::BBR5),
-        "bbr6" => Ok(Mnemonic::BBR6),
-        "bbr7" => Ok(Mnemonic::BBR7),
-        "bbs0" => Ok(Mnemonic::BBS0),
-        "bbs1" => Ok(Mnemonic::BBS1),
+    // The 65816's additions
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::StackRelative,
+        },
+        0x03,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0x07,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x0f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0x13,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0x17,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ORA,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0x1f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::StackRelative,
+        },
+        0x23,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0x27,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x2f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0x33,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0x37,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::AND,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0x3f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::StackRelative,
+        },
+        0x43,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0x47,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x4f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0x53,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0x57,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::EOR,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0x5f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::StackRelative,
+        },
+        0x63,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0x67,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x6f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0x73,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0x77,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ADC,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0x7f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::StackRelative,
+        },
+        0x83,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0x87,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x8f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0x93,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0x97,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::STA,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0x9f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::StackRelative,
+        },
+        0xa3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0xa7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0xaf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0xb3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0xb7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LDA,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0xbf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::StackRelative,
+        },
+        0xc3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0xc7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0xcf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0xd3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0xd7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::CMP,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0xdf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::StackRelative,
+        },
+        0xe3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::DirectIndirectLong,
+        },
+        0xe7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0xef,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::StackRelativeIndirectY,
+        },
+        0xf3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::DirectIndirectLongY,
+        },
+        0xf7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBC,
+            address_mode: AddressMode::AbsoluteLongX,
+        },
+        0xff,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::BRL,
+            address_mode: AddressMode::RelativeLong,
+        },
+        0x82,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::COP,
+            address_mode: AddressMode::Immediate,
+        },
+        0x02,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::JML,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x5c,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::JML,
+            address_mode: AddressMode::AbsoluteIndirectLong,
+        },
+        0xdc,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::JSL,
+            address_mode: AddressMode::AbsoluteLong,
+        },
+        0x22,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::JSR,
+            address_mode: AddressMode::AbsoluteIndexedIndirect,
+        },
+        0xfc,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::MVN,
+            address_mode: AddressMode::BlockMove,
+        },
+        0x54,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::MVP,
+            address_mode: AddressMode::BlockMove,
+        },
+        0x44,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PEA,
+            address_mode: AddressMode::Absolute,
+        },
+        0xf4,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PEI,
+            address_mode: AddressMode::ZeropageIndirect,
+        },
+        0xd4,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PER,
+            address_mode: AddressMode::RelativeLong,
+        },
+        0x62,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PHB,
+            address_mode: AddressMode::Implied,
+        },
+        0x8b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PHD,
+            address_mode: AddressMode::Implied,
+        },
+        0x0b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PHK,
+            address_mode: AddressMode::Implied,
+        },
+        0x4b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PLB,
+            address_mode: AddressMode::Implied,
+        },
+        0xab,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::PLD,
+            address_mode: AddressMode::Implied,
+        },
+        0x2b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::REP,
+            address_mode: AddressMode::Immediate,
+        },
+        0xc2,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RTL,
+            address_mode: AddressMode::Implied,
+        },
+        0x6b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SEP,
+            address_mode: AddressMode::Immediate,
+        },
+        0xe2,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TCD,
+            address_mode: AddressMode::Implied,
+        },
+        0x5b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TCS,
+            address_mode: AddressMode::Implied,
+        },
+        0x1b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TDC,
+            address_mode: AddressMode::Implied,
+        },
+        0x7b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TSC,
+            address_mode: AddressMode::Implied,
+        },
+        0x3b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TXY,
+            address_mode: AddressMode::Implied,
+        },
+        0x9b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::TYX,
+            address_mode: AddressMode::Implied,
+        },
+        0xbb,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::WDM,
+            address_mode: AddressMode::Immediate,
+        },
+        0x42,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::XBA,
+            address_mode: AddressMode::Implied,
+        },
+        0xeb,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::XCE,
+            address_mode: AddressMode::Implied,
+        },
+        0xfb,
+    );
+    // The NMOS 6502's stable undocumented instructions, for the 6502x
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::IndirectX,
+        },
+        0x03,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::Zeropage,
+        },
+        0x07,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::Absolute,
+        },
+        0x0f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::IndirectY,
+        },
+        0x13,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0x17,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0x1b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SLO,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x1f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::IndirectX,
+        },
+        0x23,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::Zeropage,
+        },
+        0x27,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::Absolute,
+        },
+        0x2f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::IndirectY,
+        },
+        0x33,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0x37,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0x3b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RLA,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x3f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::IndirectX,
+        },
+        0x43,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::Zeropage,
+        },
+        0x47,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::Absolute,
+        },
+        0x4f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::IndirectY,
+        },
+        0x53,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0x57,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0x5b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SRE,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x5f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::IndirectX,
+        },
+        0x63,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::Zeropage,
+        },
+        0x67,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::Absolute,
+        },
+        0x6f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::IndirectY,
+        },
+        0x73,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0x77,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0x7b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::RRA,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0x7f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::IndirectX,
+        },
+        0xc3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::Zeropage,
+        },
+        0xc7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::Absolute,
+        },
+        0xcf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::IndirectY,
+        },
+        0xd3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0xd7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0xdb,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::DCP,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0xdf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::IndirectX,
+        },
+        0xe3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::Zeropage,
+        },
+        0xe7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::Absolute,
+        },
+        0xef,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::IndirectY,
+        },
+        0xf3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::ZeropageX,
+        },
+        0xf7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0xfb,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ISC,
+            address_mode: AddressMode::AbsoluteX,
+        },
+        0xff,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SAX,
+            address_mode: AddressMode::IndirectX,
+        },
+        0x83,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SAX,
+            address_mode: AddressMode::Zeropage,
+        },
+        0x87,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SAX,
+            address_mode: AddressMode::Absolute,
+        },
+        0x8f,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SAX,
+            address_mode: AddressMode::ZeropageY,
+        },
+        0x97,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::IndirectX,
+        },
+        0xa3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::Zeropage,
+        },
+        0xa7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::Absolute,
+        },
+        0xaf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::IndirectY,
+        },
+        0xb3,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::ZeropageY,
+        },
+        0xb7,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::LAX,
+            address_mode: AddressMode::AbsoluteY,
+        },
+        0xbf,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ANC,
+            address_mode: AddressMode::Immediate,
+        },
+        0x0b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ALR,
+            address_mode: AddressMode::Immediate,
+        },
+        0x4b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::ARR,
+            address_mode: AddressMode::Immediate,
+        },
+        0x6b,
+    );
+    instruction_set.insert(
+        InstructionKey {
+            mnemonic: Mnemonic::SBX,
+            address_mode: AddressMode::Immediate,
+        },
+        0xcb,
+    );
+    instruction_set
+}
+
+#[derive(Eq, PartialEq, Debug)]
+pub enum Value {
+    String(String),
+    U8(u8),
+    U16(u16),
+    U24(u32), // A long address on the 65816
+    Null,
+}
+
+// TODO: Shoudl this be Option instead of Result?
+pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
+    match instruction {
+        "adc" => Ok(Mnemonic::ADC),
+        "alr" => Ok(Mnemonic::ALR),
+        "anc" => Ok(Mnemonic::ANC),
+        "and" => Ok(Mnemonic::AND),
+        "arr" => Ok(Mnemonic::ARR),
+        "asl" => Ok(Mnemonic::ASL),
+        "bbr0" => Ok(Mnemonic::BBR0),
+        "bbr1" => Ok(Mnemonic::BBR1),
+        "bbr2" => Ok(Mnemonic::BBR2),
+        "bbr3" => Ok(Mnemonic::BBR3),
+        "bbr4" => Ok(Mnemonic::BBR4),
+        "bbr5" => Ok(Mnemonic::BBR5),
+        "bbr6" => Ok(Mnemonic::BBR6),
+        "bbr7" => Ok(Mnemonic::BBR7),
+        "bbs0" => Ok(Mnemonic::BBS0),
+        "bbs1" => Ok(Mnemonic::BBS1),
         "bbs2" => Ok(Mnemonic::BBS2),
         "bbs3" => Ok(Mnemonic::BBS3),
         "bbs4" => Ok(Mnemonic::BBS4),
@@ -1587,6 +2902,7 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "bpl" => Ok(Mnemonic::BPL),
         "bra" => Ok(Mnemonic::BRA),
         "brk" => Ok(Mnemonic::BRK),
+        "brl" => Ok(Mnemonic::BRL),
         "bvc" => Ok(Mnemonic::BVC),
         "bvs" => Ok(Mnemonic::BVS),
         "clc" => Ok(Mnemonic::CLC),
@@ -1594,8 +2910,10 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "cli" => Ok(Mnemonic::CLI),
         "clv" => Ok(Mnemonic::CLV),
         "cmp" => Ok(Mnemonic::CMP),
+        "cop" => Ok(Mnemonic::COP),
         "cpx" => Ok(Mnemonic::CPX),
         "cpy" => Ok(Mnemonic::CPY),
+        "dcp" => Ok(Mnemonic::DCP),
         "dec" => Ok(Mnemonic::DEC),
         "dex" => Ok(Mnemonic::DEX),
         "dey" => Ok(Mnemonic::DEY),
@@ -1603,22 +2921,38 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "inc" => Ok(Mnemonic::INC),
         "inx" => Ok(Mnemonic::INX),
         "iny" => Ok(Mnemonic::INY),
+        "isc" => Ok(Mnemonic::ISC),
+        "jml" => Ok(Mnemonic::JML),
         "jmp" => Ok(Mnemonic::JMP),
+        "jsl" => Ok(Mnemonic::JSL),
         "jsr" => Ok(Mnemonic::JSR),
+        "lax" => Ok(Mnemonic::LAX),
         "lda" => Ok(Mnemonic::LDA),
         "ldx" => Ok(Mnemonic::LDX),
         "ldy" => Ok(Mnemonic::LDY),
         "lsr" => Ok(Mnemonic::LSR),
+        "mvn" => Ok(Mnemonic::MVN),
+        "mvp" => Ok(Mnemonic::MVP),
         "nop" => Ok(Mnemonic::NOP),
         "ora" => Ok(Mnemonic::ORA),
+        "pea" => Ok(Mnemonic::PEA),
+        "pei" => Ok(Mnemonic::PEI),
+        "per" => Ok(Mnemonic::PER),
         "pha" => Ok(Mnemonic::PHA),
+        "phb" => Ok(Mnemonic::PHB),
+        "phd" => Ok(Mnemonic::PHD),
+        "phk" => Ok(Mnemonic::PHK),
         "php" => Ok(Mnemonic::PHP),
         "phx" => Ok(Mnemonic::PHX),
         "phy" => Ok(Mnemonic::PHY),
         "pla" => Ok(Mnemonic::PLA),
+        "plb" => Ok(Mnemonic::PLB),
+        "pld" => Ok(Mnemonic::PLD),
         "plp" => Ok(Mnemonic::PLP),
         "plx" => Ok(Mnemonic::PLX),
         "ply" => Ok(Mnemonic::PLY),
+        "rep" => Ok(Mnemonic::REP),
+        "rla" => Ok(Mnemonic::RLA),
         "rmb0" => Ok(Mnemonic::RMB0),
         "rmb1" => Ok(Mnemonic::RMB1),
         "rmb2" => Ok(Mnemonic::RMB2),
@@ -1629,11 +2963,18 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "rmb7" => Ok(Mnemonic::RMB7),
         "rol" => Ok(Mnemonic::ROL),
         "ror" => Ok(Mnemonic::ROR),
+        "rra" => Ok(Mnemonic::RRA),
+        "rti" => Ok(Mnemonic::RTI),
+        "rtl" => Ok(Mnemonic::RTL),
         "rts" => Ok(Mnemonic::RTS),
+        "sax" => Ok(Mnemonic::SAX),
         "sbc" => Ok(Mnemonic::SBC),
+        "sbx" => Ok(Mnemonic::SBX),
         "sec" => Ok(Mnemonic::SEC),
         "sed" => Ok(Mnemonic::SED),
         "sei" => Ok(Mnemonic::SEI),
+        "sep" => Ok(Mnemonic::SEP),
+        "slo" => Ok(Mnemonic::SLO),
         "smb0" => Ok(Mnemonic::SMB0),
         "smb1" => Ok(Mnemonic::SMB1),
         "smb2" => Ok(Mnemonic::SMB2),
@@ -1642,6 +2983,7 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "smb5" => Ok(Mnemonic::SMB5),
         "smb6" => Ok(Mnemonic::SMB6),
         "smb7" => Ok(Mnemonic::SMB7),
+        "sre" => Ok(Mnemonic::SRE),
         "sta" => Ok(Mnemonic::STA),
         "stp" => Ok(Mnemonic::STP),
         "stx" => Ok(Mnemonic::STX),
@@ -1649,13 +2991,22 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
         "stz" => Ok(Mnemonic::STZ),
         "tax" => Ok(Mnemonic::TAX),
         "tay" => Ok(Mnemonic::TAY),
+        "tcd" => Ok(Mnemonic::TCD),
+        "tcs" => Ok(Mnemonic::TCS),
+        "tdc" => Ok(Mnemonic::TDC),
         "trb" => Ok(Mnemonic::TRB),
         "tsb" => Ok(Mnemonic::TSB),
+        "tsc" => Ok(Mnemonic::TSC),
         "tsx" => Ok(Mnemonic::TSX),
         "txa" => Ok(Mnemonic::TXA),
         "txs" => Ok(Mnemonic::TXS),
+        "txy" => Ok(Mnemonic::TXY),
         "tya" => Ok(Mnemonic::TYA),
+        "tyx" => Ok(Mnemonic::TYX),
         "wai" => Ok(Mnemonic::WAI),
+        "wdm" => Ok(Mnemonic::WDM),
+        "xba" => Ok(Mnemonic::XBA),
+        "xce" => Ok(Mnemonic::XCE),
         _ => Err(error(
             ErrorCode::UnknownInstruction,
             format!("Unknown instruction: {}", instruction),
@@ -1665,7 +3016,172 @@ pub fn str_to_mnemonic(instruction: &str) -> Result<Mnemonic, Error> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+
+    use super::available;
+    use super::generate_instruction_set;
+    use super::generate_opcode_table;
+    use super::instruction_cycles;
+    use super::minimum_cpu;
+    use super::mnemonic_to_str;
     use super::str_to_mnemonic;
+    use super::AddressMode;
+    use super::Cpu;
+    use super::InstructionKey;
+    use super::Mnemonic;
+
+    #[test]
+    fn test_opcodes_are_unique() {
+        let instruction_set = generate_instruction_set();
+        let opcodes = generate_opcode_table(&instruction_set);
+        assert_eq!(instruction_set.len(), 344);
+        assert_eq!(opcodes.len(), 212);
+
+        // The 65816 fills every opcode, and only clashes with the bit instructions
+        let w65816: HashSet<u8> = instruction_set
+            .iter()
+            .filter(|(key, _)| available(**key, Cpu::W65816))
+            .map(|(_, opcode)| *opcode)
+            .collect();
+        assert_eq!(w65816.len(), 256);
+
+        // Nor do the undocumented instructions clash with the documented ones
+        let nmos: HashSet<u8> = instruction_set
+            .iter()
+            .filter(|(key, _)| available(**key, Cpu::Mos6502X))
+            .map(|(_, opcode)| *opcode)
+            .collect();
+        assert_eq!(nmos.len(), 151 + 56);
+        for key in instruction_set.keys() {
+            if str_to_mnemonic(&mnemonic_to_str(key.mnemonic)).is_err() {
+                panic!("Expected {:?} to have a name", key.mnemonic);
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrected_opcodes() {
+        let instruction_set = generate_instruction_set();
+        let opcode = |mnemonic, address_mode| {
+            instruction_set.get(&InstructionKey {
+                mnemonic,
+                address_mode,
+            })
+        };
+        assert_eq!(opcode(Mnemonic::STX, AddressMode::Zeropage), Some(&0x86));
+        assert_eq!(opcode(Mnemonic::STY, AddressMode::Absolute), Some(&0x8c));
+        assert_eq!(opcode(Mnemonic::LDX, AddressMode::ZeropageY), Some(&0xb6));
+        assert_eq!(opcode(Mnemonic::LDX, AddressMode::AbsoluteY), Some(&0xbe));
+        assert_eq!(opcode(Mnemonic::EOR, AddressMode::ZeropageX), Some(&0x55));
+        assert_eq!(opcode(Mnemonic::STZ, AddressMode::ZeropageX), Some(&0x74));
+        assert_eq!(opcode(Mnemonic::TSB, AddressMode::Zeropage), Some(&0x04));
+        assert_eq!(opcode(Mnemonic::TRB, AddressMode::Absolute), Some(&0x1c));
+        assert_eq!(opcode(Mnemonic::BIT, AddressMode::Immediate), Some(&0x89));
+        assert_eq!(opcode(Mnemonic::CPX, AddressMode::Immediate), Some(&0xe0));
+        assert_eq!(opcode(Mnemonic::ROL, AddressMode::Implied), Some(&0x2a));
+        assert_eq!(opcode(Mnemonic::ROR, AddressMode::Implied), Some(&0x6a));
+        assert_eq!(opcode(Mnemonic::RTI, AddressMode::Implied), Some(&0x40));
+        assert_eq!(
+            opcode(Mnemonic::STA, AddressMode::ZeropageIndirect),
+            Some(&0x92)
+        );
+        assert_eq!(
+            opcode(Mnemonic::BBR0, AddressMode::ZeropageRelative),
+            Some(&0x0f)
+        );
+        assert_eq!(opcode(Mnemonic::LSR, AddressMode::Immediate), None);
+    }
+
+    #[test]
+    fn test_instruction_cycles() {
+        let cycles = |mnemonic, address_mode| {
+            let cycles = instruction_cycles(InstructionKey {
+                mnemonic,
+                address_mode,
+            });
+            (cycles.min(), cycles.max())
+        };
+        assert_eq!(cycles(Mnemonic::LDA, AddressMode::Immediate), (2, 2));
+        assert_eq!(cycles(Mnemonic::LDA, AddressMode::AbsoluteX), (4, 5));
+        assert_eq!(cycles(Mnemonic::STA, AddressMode::AbsoluteX), (5, 5));
+        assert_eq!(cycles(Mnemonic::LDA, AddressMode::IndirectY), (5, 6));
+        assert_eq!(cycles(Mnemonic::INC, AddressMode::Zeropage), (5, 5));
+        assert_eq!(cycles(Mnemonic::BNE, AddressMode::Relative), (2, 4));
+        assert_eq!(cycles(Mnemonic::JSR, AddressMode::Absolute), (6, 6));
+        assert_eq!(cycles(Mnemonic::JMP, AddressMode::Indirect), (6, 6));
+        assert_eq!(cycles(Mnemonic::JSL, AddressMode::AbsoluteLong), (8, 8));
+        assert_eq!(cycles(Mnemonic::LDA, AddressMode::AbsoluteLongX), (5, 5));
+        assert_eq!(cycles(Mnemonic::BRL, AddressMode::RelativeLong), (4, 4));
+        assert_eq!(cycles(Mnemonic::REP, AddressMode::Immediate), (3, 3));
+        assert_eq!(cycles(Mnemonic::DCP, AddressMode::IndirectY), (8, 8));
+        assert_eq!(cycles(Mnemonic::SLO, AddressMode::AbsoluteX), (7, 7));
+        assert_eq!(cycles(Mnemonic::LAX, AddressMode::AbsoluteY), (4, 5));
+        assert_eq!(cycles(Mnemonic::SAX, AddressMode::ZeropageY), (4, 4));
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        let cycles = |mnemonic, address_mode| {
+            let cycles = instruction_cycles(InstructionKey {
+                mnemonic,
+                address_mode,
+            });
+            (cycles.min(), cycles.max())
+        };
+        assert_eq!(cycles(Mnemonic::BEQ, AddressMode::Relative), (2, 4));
+        // Always taken, with one more crossing a page
+        assert_eq!(cycles(Mnemonic::BRA, AddressMode::Relative), (3, 4));
+        assert_eq!(
+            cycles(Mnemonic::BBS7, AddressMode::ZeropageRelative),
+            (5, 7)
+        );
+    }
+
+    #[test]
+    fn test_minimum_cpu() {
+        let instruction_set = generate_instruction_set();
+        let count = |cpu| {
+            instruction_set
+                .keys()
+                .filter(|key| available(**key, cpu))
+                .count()
+        };
+        // The documented opcodes of each part
+        assert_eq!(count(Cpu::Mos6502), 151);
+        assert_eq!(count(Cpu::Mos6502X), 207);
+        assert_eq!(count(Cpu::Cmos65C02), 178);
+        assert_eq!(count(Cpu::R65C02), 210);
+        assert_eq!(count(Cpu::W65C02), 212);
+        assert_eq!(count(Cpu::W65816), 256);
+
+        let cpu = |mnemonic, address_mode| {
+            minimum_cpu(InstructionKey {
+                mnemonic,
+                address_mode,
+            })
+        };
+        assert_eq!(cpu(Mnemonic::LDA, AddressMode::IndirectY), Cpu::Mos6502);
+        assert_eq!(
+            cpu(Mnemonic::LDA, AddressMode::ZeropageIndirect),
+            Cpu::Cmos65C02
+        );
+        assert_eq!(cpu(Mnemonic::INC, AddressMode::Implied), Cpu::Cmos65C02);
+        assert_eq!(
+            cpu(Mnemonic::BBR3, AddressMode::ZeropageRelative),
+            Cpu::R65C02
+        );
+        assert_eq!(cpu(Mnemonic::STP, AddressMode::Implied), Cpu::W65C02);
+        assert_eq!(cpu(Mnemonic::XCE, AddressMode::Implied), Cpu::W65816);
+        assert_eq!(cpu(Mnemonic::LAX, AddressMode::IndirectY), Cpu::Mos6502X);
+        assert_eq!(
+            cpu(Mnemonic::LDA, AddressMode::StackRelativeIndirectY),
+            Cpu::W65816
+        );
+        assert_eq!(
+            cpu(Mnemonic::JSR, AddressMode::AbsoluteIndexedIndirect),
+            Cpu::W65816
+        );
+    }
 
     #[test]
     fn test_good_str_to_mnemonic() {
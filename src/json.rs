@@ -0,0 +1,341 @@
+// Just enough JSON for the language server to read requests and write replies
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>), // In the order written, which keeps replies readable
+}
+
+static NULL: Json = Json::Null;
+
+impl Json {
+    // A field of an object, or null when there's no such field
+    pub fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(name, _)| name == key)
+                .map_or(&NULL, |(_, value)| value),
+            _ => &NULL,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Json::Number(number) if *number >= 0.0 && number.fract() == 0.0 => Some(*number as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Json::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+// An object from its fields, to save on to_string()s when building replies
+pub fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect(),
+    )
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            Json::Number(number) => write!(f, "{}", number),
+            Json::String(string) => write_string(f, string),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(fields) => {
+                write!(f, "{{")?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter, string: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for ch in string.chars() {
+        match ch {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(f, "\\u{:04x}", ch as u32)?,
+            ch => write!(f, "{}", ch)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+pub fn parse(text: &str) -> Result<Json, Error> {
+    let mut chars = text.chars().peekable();
+    let value = parse_value(&mut chars)?;
+    skip_whitespace(&mut chars);
+    match chars.next() {
+        None => Ok(value),
+        Some(ch) => Err(malformed(format!("Unexpected {} after the JSON", ch))),
+    }
+}
+
+fn malformed(message: String) -> Error {
+    error(ErrorCode::MalformedMessage, message)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars>) {
+    while chars.peek().is_some_and(|ch| ch.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, word: &str) -> Result<(), Error> {
+    for expected in word.chars() {
+        if chars.next() != Some(expected) {
+            return Err(malformed(format!("Expected {}", word)));
+        }
+    }
+    Ok(())
+}
+
+fn parse_value(chars: &mut Peekable<Chars>) -> Result<Json, Error> {
+    skip_whitespace(chars);
+    match chars.peek() {
+        Some('n') => expect(chars, "null").map(|_| Json::Null),
+        Some('t') => expect(chars, "true").map(|_| Json::Bool(true)),
+        Some('f') => expect(chars, "false").map(|_| Json::Bool(false)),
+        Some('"') => parse_string(chars).map(Json::String),
+        Some('[') => parse_array(chars),
+        Some('{') => parse_object(chars),
+        Some(ch) if *ch == '-' || ch.is_ascii_digit() => parse_number(chars),
+        Some(ch) => Err(malformed(format!("Unexpected {} in JSON", ch))),
+        None => Err(malformed("Unexpected end of JSON".to_string())),
+    }
+}
+
+fn parse_number(chars: &mut Peekable<Chars>) -> Result<Json, Error> {
+    let mut number = String::new();
+    while let Some(ch) = chars
+        .peek()
+        .filter(|ch| "+-.eE".contains(**ch) || ch.is_ascii_digit())
+    {
+        number.push(*ch);
+        chars.next();
+    }
+    match number.parse::<f64>() {
+        Ok(number) => Ok(Json::Number(number)),
+        Err(_) => Err(malformed(format!("Bad number {} in JSON", number))),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, Error> {
+    chars.next(); // The opening quote
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(string),
+            Some('\\') => {
+                let escaped = match chars.next() {
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some('/') => '/',
+                    Some('b') => '\u{8}',
+                    Some('f') => '\u{c}',
+                    Some('n') => '\n',
+                    Some('r') => '\r',
+                    Some('t') => '\t',
+                    Some('u') => parse_unicode(chars)?,
+                    _ => return Err(malformed("Bad escape in JSON string".to_string())),
+                };
+                string.push(escaped);
+            }
+            Some(ch) => string.push(ch),
+            None => return Err(malformed("Unterminated JSON string".to_string())),
+        }
+    }
+}
+
+// The four hex digits after a \u, and the low half that follows a high surrogate
+fn parse_unicode(chars: &mut Peekable<Chars>) -> Result<char, Error> {
+    let mut code = hex4(chars)?;
+    if (0xd800..0xdc00).contains(&code) {
+        expect(chars, "\\u")?;
+        let low = hex4(chars)?;
+        code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+    }
+    std::char::from_u32(code).ok_or_else(|| malformed("Bad \\u escape in JSON".to_string()))
+}
+
+fn hex4(chars: &mut Peekable<Chars>) -> Result<u32, Error> {
+    let digits: String = chars.take(4).collect();
+    match u32::from_str_radix(&digits, 16) {
+        Ok(code) if digits.len() == 4 => Ok(code),
+        _ => Err(malformed(format!("Bad \\u{} escape in JSON", digits))),
+    }
+}
+
+fn parse_array(chars: &mut Peekable<Chars>) -> Result<Json, Error> {
+    chars.next(); // The [
+    let mut values = vec![];
+    skip_whitespace(chars);
+    if chars.peek() == Some(&']') {
+        chars.next();
+        return Ok(Json::Array(values));
+    }
+    loop {
+        values.push(parse_value(chars)?);
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some(']') => return Ok(Json::Array(values)),
+            _ => return Err(malformed("Expected , or ] in JSON array".to_string())),
+        }
+    }
+}
+
+fn parse_object(chars: &mut Peekable<Chars>) -> Result<Json, Error> {
+    chars.next(); // The {
+    let mut fields = vec![];
+    skip_whitespace(chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+        return Ok(Json::Object(fields));
+    }
+    loop {
+        skip_whitespace(chars);
+        if chars.peek() != Some(&'"') {
+            return Err(malformed(
+                "Expected a field name in JSON object".to_string(),
+            ));
+        }
+        let name = parse_string(chars)?;
+        skip_whitespace(chars);
+        expect(chars, ":")?;
+        fields.push((name, parse_value(chars)?));
+        skip_whitespace(chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => return Ok(Json::Object(fields)),
+            _ => return Err(malformed("Expected , or } in JSON object".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::object;
+    use super::parse;
+    use super::Json;
+
+    #[test]
+    fn test_parse() {
+        let text = r#" {"id": 3, "params": {"text": "lda #$01\n\u00e9\ud83d\ude00", "ok": [true, false, null, -1.5e1]}} "#;
+        let json = match parse(text) {
+            Ok(json) => json,
+            Err(err) => panic!("Did not expect the JSON to fail: {}", err.1),
+        };
+        assert_eq!(json.get("id").as_u64(), Some(3));
+        assert_eq!(
+            json.get("params").get("text").as_str(),
+            Some("lda #$01\n\u{e9}\u{1f600}")
+        );
+        assert_eq!(
+            json.get("params").get("ok").as_array(),
+            Some(
+                &[
+                    Json::Bool(true),
+                    Json::Bool(false),
+                    Json::Null,
+                    Json::Number(-15.0)
+                ][..]
+            )
+        );
+        assert_eq!(json.get("missing"), &Json::Null);
+
+        for bad in &[
+            "",
+            "{",
+            "[1,]",
+            "{\"a\" 1}",
+            "\"open",
+            "nul",
+            "1 2",
+            "\"\\x\"",
+        ] {
+            if parse(bad).is_ok() {
+                panic!("Did not expect {} to parse", bad);
+            }
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let json = object(vec![
+            ("id", Json::Number(1.0)),
+            ("result", Json::Null),
+            (
+                "text",
+                Json::Array(vec![Json::String("say \"hi\"\n\u{1}".to_string())]),
+            ),
+        ]);
+        let text = json.to_string();
+        assert_eq!(
+            text,
+            r#"{"id":1,"result":null,"text":["say \"hi\"\n\u0001"]}"#
+        );
+        if let Ok(parsed) = parse(&text) {
+            assert_eq!(parsed, json);
+        } else {
+            panic!("Did not expect the JSON written to fail to parse");
+        }
+    }
+}
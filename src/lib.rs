@@ -7,21 +7,71 @@ mod strings;
 
 mod ascii;
 
+pub mod assembly;
+pub use assembly::assemble;
+pub use assembly::assemble_with;
+pub use assembly::Assembly;
+pub use assembly::Diagnostics;
+pub use assembly::Options;
+
+pub mod checksum;
+pub mod disassembler;
+mod expression;
+
 mod files;
-use files::read_source;
+use files::str_to_output_format;
+use files::write_file;
 use files::write_out;
+use files::OutputFormat;
+use files::Start;
+
+pub mod formatter;
+
+mod ihex;
+
+pub mod image;
 
 pub mod instructions;
-use instructions::generate_instruction_set;
+use instructions::str_to_cpu;
+use instructions::Cpu;
+
+mod json;
+
+pub mod linker;
+
+pub mod linter;
+
+mod listing;
+use listing::listing;
+
+pub mod lsp;
+
+pub mod map;
+use map::str_to_map_format;
+use map::symbol_map;
+use map::MapFormat;
+
+pub mod object;
+use object::assemble_object;
 
 pub mod pass1;
-use pass1::pass1;
+use pass1::Address;
 
 pub mod pass2;
-use pass2::pass2;
+
+pub mod runner;
 
 mod scanner;
-use scanner::scanner;
+
+pub mod simulator;
+
+pub mod sources;
+use sources::FileSystem;
+use sources::SourceProvider;
+
+pub mod tester;
+
+mod srec;
 
 const OUTFILE_DEFAULT: &str = "a.out"; // A typical default
 
@@ -31,13 +81,25 @@ pub struct Config {
 
     // These config options are added because the assembler is
     // generally used for producing a file to burn to EEPROM
-    padding: u16, // The number of 0x00 bytes to pad at start of file
-    size: u16,    // The total size of file, pads 0x00 at end
+    padding: u32,     // The number of fill bytes to pad at start of file
+    size: u32,        // The total size of file, pads fill bytes at end
+    fill: Option<u8>, // Overrides .fillbyte for padding and gaps
+    cpu: Cpu,         // The instructions available until a .cpu
+    format: OutputFormat,
+    start: Start,              // The first address in the output
+    end: Option<Address>,      // The last address in the output
+    split: Option<u32>,        // Bytes per bank when splitting across ROMs
+    interleave: Option<usize>, // Number of ROMs to interleave bytes across
+
+    object: bool, // Write an object for kld instead of an image
+    listing_file: Option<String>,
+    map_file: Option<String>,
+    map_format: MapFormat,
 }
 
 pub fn usage(cmd: &str) {
     println!(
-        "usage: {} [-o <outfile>] [-p <padding>] [-s <size>] <source>",
+        "usage: {} [-c] [-o <outfile>] [-l <listing>] [-m <map>] [--map-format plain|vice|dbg]\n       [--format bin|ihex|srec]\n       [--start <address>|auto] [--end <address>]\n       [--split <size>] [--interleave <count>]\n       [--fill <byte>] [-p <padding>] [-s <size>]\n       [--cpu 6502|6502x|65c02|r65c02|w65c02|65816] <source>",
         cmd
     );
 }
@@ -55,39 +117,147 @@ impl Config {
         let mut temp_val = String::new();
         let mut padding = 0;
         let mut size = 0;
+        let mut format = OutputFormat::Binary;
+        let mut start = Start::Address(0);
+        let mut end = None;
+        let mut split = None;
+        let mut fill = None;
+        let mut cpu = Cpu::W65C02;
+        let mut interleave = None;
+        let mut object = false;
+        let mut listing_file = None;
+        let mut map_file = None;
+        let mut map_format = MapFormat::Plain;
 
-        while !args.is_empty() {
-            let val = args.pop().unwrap();
-            if val == "-o" {
+        while let Some(val) = args.pop() {
+            if val == "-c" {
+                // A flag on its own, so nothing should be waiting for it
+                if !temp_val.is_empty() {
+                    return Err((ErrorCode::Usage, "Missing arguments"));
+                }
+                object = true;
+                continue;
+            } else if val == "-o" {
                 if temp_val.is_empty() {
                     return Err((ErrorCode::Usage, "No output filename provided"));
                 }
                 out_file = temp_val.to_string();
                 temp_val = String::from("");
                 continue;
+            } else if val == "-l" {
+                if temp_val.is_empty() {
+                    return Err((ErrorCode::Usage, "No listing filename provided"));
+                }
+                listing_file = Some(temp_val.to_string());
+                temp_val = String::from("");
+                continue;
+            } else if val == "-m" {
+                if temp_val.is_empty() {
+                    return Err((ErrorCode::Usage, "No map filename provided"));
+                }
+                map_file = Some(temp_val.to_string());
+                temp_val = String::from("");
+                continue;
+            } else if val == "--map-format" {
+                match str_to_map_format(&temp_val) {
+                    Some(format) => {
+                        temp_val = String::from("");
+                        map_format = format;
+                        continue;
+                    }
+                    None => return Err((ErrorCode::Usage, "Unknown map format")),
+                };
+            } else if val == "--format" {
+                match str_to_output_format(&temp_val) {
+                    Some(output_format) => {
+                        temp_val = String::from("");
+                        format = output_format;
+                        continue;
+                    }
+                    None => return Err((ErrorCode::Usage, "Unknown output format")),
+                };
+            } else if val == "--start" {
+                if temp_val == "auto" {
+                    temp_val = String::from("");
+                    start = Start::Lowest;
+                    continue;
+                }
+                match parse_address(&temp_val) {
+                    Some(address) => {
+                        temp_val = String::from("");
+                        start = Start::Address(address);
+                        continue;
+                    }
+                    None => return Err((ErrorCode::Usage, "Invalid start address")),
+                };
+            } else if val == "--end" {
+                match parse_address(&temp_val) {
+                    Some(address) => {
+                        temp_val = String::from("");
+                        end = Some(address);
+                        continue;
+                    }
+                    None => return Err((ErrorCode::Usage, "Invalid end address")),
+                };
+            } else if val == "--split" {
+                match parse_size(&temp_val) {
+                    Some(split_val) if split_val > 0 => {
+                        temp_val = String::from("");
+                        split = Some(split_val);
+                        continue;
+                    }
+                    _ => return Err((ErrorCode::Usage, "Invalid split size")),
+                };
+            } else if val == "--interleave" {
+                match temp_val.parse::<usize>() {
+                    Ok(interleave_val) if interleave_val > 0 => {
+                        temp_val = String::from("");
+                        interleave = Some(interleave_val);
+                        continue;
+                    }
+                    _ => return Err((ErrorCode::Usage, "Invalid interleave count")),
+                };
+            } else if val == "--fill" {
+                match parse_address(&temp_val) {
+                    Some(fill_val) if fill_val <= 0xff => {
+                        temp_val = String::from("");
+                        fill = Some(fill_val as u8);
+                        continue;
+                    }
+                    _ => return Err((ErrorCode::Usage, "Invalid fill byte")),
+                };
+            } else if val == "--cpu" {
+                match str_to_cpu(&temp_val) {
+                    Some(cpu_val) => {
+                        temp_val = String::from("");
+                        cpu = cpu_val;
+                        continue;
+                    }
+                    None => return Err((ErrorCode::Usage, "Unknown CPU")),
+                };
             } else if val == "-p" {
                 if temp_val.is_empty() {
                     return Err((ErrorCode::Usage, "No padding size provided provided"));
                 }
-                match u16::from_str_radix(temp_val.as_str(), 10) {
-                    Ok(pad_val) => {
+                match parse_size(&temp_val) {
+                    Some(pad_val) => {
                         temp_val = String::from("");
                         padding = pad_val;
                         continue;
                     }
-                    Err(_) => return Err((ErrorCode::Usage, "Invalid padding size")),
+                    None => return Err((ErrorCode::Usage, "Invalid padding size")),
                 };
             } else if val == "-s" {
                 if temp_val.is_empty() {
                     return Err((ErrorCode::Usage, "No file size provided provided"));
                 }
-                match u16::from_str_radix(temp_val.as_str(), 10) {
-                    Ok(size_val) => {
+                match parse_size(&temp_val) {
+                    Some(size_val) => {
                         temp_val = String::from("");
                         size = size_val;
                         continue;
                     }
-                    Err(_) => return Err((ErrorCode::Usage, "Invalid file size")),
+                    None => return Err((ErrorCode::Usage, "Invalid file size")),
                 };
             }
             temp_val = val;
@@ -98,6 +268,19 @@ impl Config {
             return Err((ErrorCode::Usage, "Missing arguments"));
         }
 
+        if let (Start::Address(start), Some(end)) = (start, end) {
+            if end < start {
+                return Err((ErrorCode::Usage, "End address is before the start address"));
+            }
+        }
+
+        if (split.is_some() || interleave.is_some()) && format != OutputFormat::Binary {
+            return Err((
+                ErrorCode::Usage,
+                "Only bin images can be split or interleaved",
+            ));
+        }
+
         if source_file == out_file {
             return Err((
                 ErrorCode::OverwriteSource,
@@ -110,50 +293,95 @@ impl Config {
             out_file,
             padding,
             size,
+            fill,
+            cpu,
+            format,
+            start,
+            end,
+            split,
+            interleave,
+            object,
+            listing_file,
+            map_file,
+            map_format,
         })
     }
 }
 
-// XXX run isn't nicely testable with the filesystem calls
-pub fn run(config: &Config) -> Result<(), Error> {
-    // Read in the source file
-    let source = match read_source(&config.source_file) {
-        Ok(source) => source,
-        Err(err) => return Err(err),
+// Addresses are hex with a $ or 0x in front, otherwise decimal, and may
+// be up to 24 bits for the 65816
+fn parse_address(value: &str) -> Option<Address> {
+    let parsed = if let Some(hex) = value.strip_prefix('$') {
+        str_to_u32!(hex)
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        str_to_u32!(hex)
+    } else {
+        value.parse::<Address>()
     };
+    parsed.ok().filter(|address| *address <= 0xffffff)
+}
 
-    // Scan in and clean up the raw
-    let scanned = scanner(&source);
+// Sizes are like addresses but may go past 64K, and 32K means 32768 bytes
+fn parse_size(value: &str) -> Option<u32> {
+    let parsed = if let Some(hex) = value.strip_prefix('$') {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(hex) = value.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(kilobytes) = value.strip_suffix(['K', 'k']) {
+        return kilobytes.parse::<u32>().ok()?.checked_mul(1024);
+    } else {
+        value.parse::<u32>()
+    };
+    parsed.ok()
+}
 
-    // Create a data structure containing details of our
-    // instruction set
-    let instruction_set = generate_instruction_set();
+// The command line around assemble, reading the source and writing out
+// everything that was asked for
+pub fn run(config: &Config) -> Result<(), Error> {
+    let source = FileSystem.read_source(&config.source_file)?;
 
-    // Create a data structure containing the instruction,
-    // the addressing mode, and the value
-    let pass1_code = match pass1(scanned) {
-        Ok(pass1_code) => pass1_code,
-        Err(err) => return Err(err),
+    let options = Options {
+        cpu: config.cpu,
+        fill: config.fill,
+        object: config.object,
     };
+    let assembly = assemble_with(&source, Some(&config.source_file), &options, &FileSystem)
+        .map_err(Diagnostics::into_error)?;
+    for message in &assembly.program.messages {
+        println!("{}", message);
+    }
 
-    // Create a new data structure of instructions by resolving
-    // all the labels
-    let output = match pass2(instruction_set, pass1_code) {
-        Ok(output) => output,
-        Err(err) => return Err(err),
-    };
+    if let Some(listing_file) = &config.listing_file {
+        let text = listing(&source, &assembly.program, assembly.lines());
+        write_file(listing_file, text.as_bytes())?;
+    }
 
-    match write_out(&config, output) {
-        Ok(_) => {}
-        Err(err) => return Err(err),
-    };
+    if let Some(map_file) = &config.map_file {
+        let text = symbol_map(
+            config.map_format,
+            assembly.symbols(),
+            &assembly.program.files,
+        );
+        write_file(map_file, text.as_bytes())?;
+    }
 
-    Ok(())
+    // Leave the imported labels for kld to sort out
+    if config.object {
+        let object = assemble_object(&assembly.program, assembly.output);
+        return write_file(&config.out_file, object.to_text().as_bytes());
+    }
+
+    let segments = assembly.output.segments()?;
+    write_out(config, segments, assembly.fill)
 }
 
 #[cfg(test)]
 mod tests {
     use super::Config;
+    use super::Cpu;
+    use super::MapFormat;
+    use super::OutputFormat;
+    use super::Start;
 
     #[test]
     fn test_config() {
@@ -187,6 +415,259 @@ mod tests {
         };
     }
 
+    #[test]
+    fn test_fill() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--fill".to_string(),
+            "$ff".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.fill, Some(0xff));
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_fill_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.fill, None);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_cpu() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--cpu".to_string(),
+            "6502".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.cpu, Cpu::Mos6502);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_cpu_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.cpu, Cpu::W65C02);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_object() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "-c".to_string(),
+            "-o".to_string(),
+            "test.o".to_string(),
+            "-l".to_string(),
+            "test.lst".to_string(),
+            "-m".to_string(),
+            "test.map".to_string(),
+            "--map-format".to_string(),
+            "vice".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert!(c.object);
+            assert_eq!(c.out_file, "test.o");
+            assert_eq!(c.listing_file, Some("test.lst".to_string()));
+            assert_eq!(c.map_file, Some("test.map".to_string()));
+            assert_eq!(c.map_format, MapFormat::Vice);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_object_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert!(!c.object);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_listing_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.listing_file, None);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_map_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.map_file, None);
+            assert_eq!(c.map_format, MapFormat::Plain);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_format() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--format".to_string(),
+            "ihex".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.format, OutputFormat::IntelHex);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_format_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.format, OutputFormat::Binary);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_window() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--start".to_string(),
+            "$8000".to_string(),
+            "--end".to_string(),
+            "0xffff".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.start, Start::Address(0x8000));
+            assert_eq!(c.end, Some(0xffff));
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--start".to_string(),
+            "auto".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.start, Start::Lowest);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_window_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.start, Start::Address(0));
+            assert_eq!(c.end, None);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_bad_window() {
+        if Config::new(&mut vec![
+            "kasm".to_string(),
+            "--start".to_string(),
+            "$8000".to_string(),
+            "--end".to_string(),
+            "$7fff".to_string(),
+            "test.s".to_string(),
+        ])
+        .is_ok()
+        {
+            panic!("Did not expect an end before the start to work");
+        };
+    }
+
+    #[test]
+    fn test_split() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "--split".to_string(),
+            "8192".to_string(),
+            "--interleave".to_string(),
+            "2".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.split, Some(8192));
+            assert_eq!(c.interleave, Some(2));
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_split_default() {
+        if let Ok(c) = Config::new(&mut vec!["kasm".to_string(), "test.s".to_string()]) {
+            assert_eq!(c.split, None);
+            assert_eq!(c.interleave, None);
+        } else {
+            panic!("Did not expect creating a new Config to error");
+        };
+    }
+
+    #[test]
+    fn test_bad_split() {
+        if Config::new(&mut vec![
+            "kasm".to_string(),
+            "--format".to_string(),
+            "ihex".to_string(),
+            "--split".to_string(),
+            "8192".to_string(),
+            "test.s".to_string(),
+        ])
+        .is_ok()
+        {
+            panic!("Did not expect splitting Intel HEX to work");
+        };
+    }
+
+    #[test]
+    fn test_bad_map_format() {
+        if Config::new(&mut vec![
+            "kasm".to_string(),
+            "--map-format".to_string(),
+            "ld65".to_string(),
+            "test.s".to_string(),
+        ])
+        .is_ok()
+        {
+            panic!("Did not expect an unknown map format to work");
+        };
+    }
+
+    #[test]
+    fn test_large_size() {
+        if let Ok(c) = Config::new(&mut vec![
+            "kasm".to_string(),
+            "-p".to_string(),
+            "$100".to_string(),
+            "-s".to_string(),
+            "64K".to_string(),
+            "--split".to_string(),
+            "0x10000".to_string(),
+            "test.s".to_string(),
+        ]) {
+            assert_eq!(c.padding, 0x100);
+            assert_eq!(c.size, 65536);
+            assert_eq!(c.split, Some(65536));
+        } else {
+            panic!("Did not expect a 64K size to error");
+        };
+    }
+
     #[test]
     fn test_bad_padding_size() {
         if Config::new(&mut vec![
@@ -0,0 +1,198 @@
+// kld combines the object files written by kasm -c into a final image
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::files::read_source;
+use crate::files::str_to_output_format;
+use crate::files::write_out;
+use crate::files::OutputFormat;
+use crate::files::Start;
+use crate::instructions::Cpu;
+use crate::map::MapFormat;
+use crate::object::link;
+use crate::object::Object;
+use crate::parse_address;
+use crate::Config;
+use crate::OUTFILE_DEFAULT;
+
+pub struct LinkConfig {
+    object_files: Vec<String>,
+    out_file: String,
+    fill: u8, // Objects don't carry a .fillbyte, so it's only ever this
+    format: OutputFormat,
+    start: Start,
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} [-o <outfile>] [--format bin|ihex|srec] [--start <address>|auto] [--fill <byte>]\n       <object> [<object> ...]",
+        cmd
+    );
+}
+
+impl LinkConfig {
+    pub fn new(args: &mut Vec<String>) -> Result<LinkConfig, (ErrorCode, &'static str)> {
+        let mut args = args.drain(1..); // Remove first arg
+        let mut out_file = String::from(OUTFILE_DEFAULT);
+        let mut object_files = Vec::new();
+        let mut fill = 0x00;
+        let mut format = OutputFormat::Binary;
+        let mut start = Start::Address(0);
+
+        while let Some(val) = args.next() {
+            if val == "-o" {
+                match args.next() {
+                    Some(file) => out_file = file,
+                    None => return Err((ErrorCode::Usage, "No output filename provided")),
+                }
+            } else if val == "--format" {
+                match args.next().as_deref().and_then(str_to_output_format) {
+                    Some(output_format) => format = output_format,
+                    None => return Err((ErrorCode::Usage, "Unknown output format")),
+                }
+            } else if val == "--start" {
+                match args.next().as_deref() {
+                    Some("auto") => start = Start::Lowest,
+                    Some(address) => match parse_address(address) {
+                        Some(address) => start = Start::Address(address),
+                        None => return Err((ErrorCode::Usage, "Invalid start address")),
+                    },
+                    None => return Err((ErrorCode::Usage, "Invalid start address")),
+                }
+            } else if val == "--fill" {
+                match args.next().as_deref().and_then(parse_address) {
+                    Some(fill_val) if fill_val <= 0xff => fill = fill_val as u8,
+                    _ => return Err((ErrorCode::Usage, "Invalid fill byte")),
+                }
+            } else {
+                object_files.push(val);
+            }
+        }
+
+        if object_files.is_empty() {
+            return Err((ErrorCode::Usage, "Missing arguments"));
+        }
+
+        if object_files.contains(&out_file) {
+            return Err((
+                ErrorCode::OverwriteSource,
+                "You really don't want to overwrite an object file",
+            ));
+        }
+
+        Ok(LinkConfig {
+            object_files,
+            out_file,
+            fill,
+            format,
+            start,
+        })
+    }
+}
+
+pub fn run(config: &LinkConfig) -> Result<(), Error> {
+    let mut objects = Vec::new();
+
+    for file in &config.object_files {
+        let text = read_source(file)?;
+        let object = match Object::parse(&text) {
+            Ok(object) => object,
+            Err((code, msg)) => return Err((code, format!("{}: {}", file, msg))),
+        };
+        objects.push((file.to_string(), object));
+    }
+
+    let segments = link(objects)?;
+
+    // The image is written out just as kasm would, with only the options
+    // kld has and the defaults for the rest
+    let output = Config {
+        source_file: String::new(),
+        out_file: config.out_file.to_string(),
+        padding: 0,
+        size: 0,
+        fill: Some(config.fill),
+        cpu: Cpu::W65C02,
+        format: config.format,
+        start: config.start,
+        end: None,
+        split: None,
+        interleave: None,
+        object: false,
+        listing_file: None,
+        map_file: None,
+        map_format: MapFormat::Plain,
+    };
+    write_out(&output, segments, config.fill)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkConfig;
+    use crate::files::OutputFormat;
+    use crate::files::Start;
+
+    #[test]
+    fn test_link_config() {
+        if let Ok(c) = LinkConfig::new(&mut vec![
+            "kld".to_string(),
+            "a.o".to_string(),
+            "-o".to_string(),
+            "rom.bin".to_string(),
+            "b.o".to_string(),
+        ]) {
+            assert_eq!(c.out_file, "rom.bin");
+            assert_eq!(c.object_files, vec!["a.o", "b.o"]);
+        } else {
+            panic!("Did not expect creating a new LinkConfig to error");
+        };
+    }
+
+    #[test]
+    fn test_link_config_output() {
+        let mut args: Vec<String> = [
+            "kld", "--start", "$e000", "--fill", "$ff", "--format", "ihex", "a.o",
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+        if let Ok(c) = LinkConfig::new(&mut args) {
+            assert_eq!(c.start, Start::Address(0xe000));
+            assert_eq!(c.fill, 0xff);
+            assert_eq!(c.format, OutputFormat::IntelHex);
+            assert_eq!(c.object_files, vec!["a.o"]);
+        } else {
+            panic!("Did not expect creating a new LinkConfig to error");
+        };
+
+        let mut args: Vec<String> = ["kld", "--start", "auto", "a.o"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Ok(c) = LinkConfig::new(&mut args) {
+            assert_eq!(c.start, Start::Lowest);
+            assert_eq!(c.fill, 0x00);
+            assert_eq!(c.format, OutputFormat::Binary);
+        } else {
+            panic!("Did not expect creating a new LinkConfig to error");
+        };
+
+        let mut args: Vec<String> = ["kld", "--fill", "$100", "a.o"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert!(LinkConfig::new(&mut args).is_err());
+    }
+
+    #[test]
+    fn test_link_config_no_objects() {
+        if LinkConfig::new(&mut vec![
+            "kld".to_string(),
+            "-o".to_string(),
+            "rom.bin".to_string(),
+        ])
+        .is_ok()
+        {
+            panic!("Did not expect linking nothing to work");
+        };
+    }
+}
@@ -0,0 +1,462 @@
+// kasm lint: look over what pass1 made of a program for code that assembles
+// but is very likely a bug
+use std::collections::HashMap;
+use std::collections::HashSet;
+
+use crate::assembly::assemble_with;
+use crate::assembly::Options;
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::files::read_source;
+use crate::instructions::address_mode_length;
+use crate::instructions::str_to_cpu;
+use crate::instructions::AddressMode;
+use crate::instructions::Cpu;
+use crate::instructions::Mnemonic;
+use crate::instructions::Value;
+use crate::pass1::Address;
+use crate::pass1::Code;
+use crate::pass1::CodeTableEntry;
+use crate::pass1::Content;
+use crate::pass1::Program;
+use crate::scanner::FileId;
+use crate::scanner::Line;
+use crate::sources::FileSystem;
+
+const PRAGMA: &str = "lint-allow:"; // ; lint-allow: unreachable, immediate-label
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LintCheck {
+    IndirectJump,   // jmp ($xxff) reads its high byte from $xx00 on NMOS parts
+    Unreachable,    // Code after a jmp, rts, rti or bra that nothing jumps to
+    MidInstruction, // A branch or jump into the operand of an instruction
+    ImmediateLabel, // lda #label only loads the low byte
+    RunsIntoData,   // Code that carries on into data, or off the end of its .org
+}
+
+pub struct Lint {
+    pub check: LintCheck,
+    pub file: FileId,
+    pub line: Line,
+    pub message: String,
+}
+
+pub fn lint_to_str(check: LintCheck) -> &'static str {
+    match check {
+        LintCheck::IndirectJump => "indirect-jump",
+        LintCheck::Unreachable => "unreachable",
+        LintCheck::MidInstruction => "mid-instruction",
+        LintCheck::ImmediateLabel => "immediate-label",
+        LintCheck::RunsIntoData => "runs-into-data",
+    }
+}
+
+pub fn str_to_lint(check: &str) -> Option<LintCheck> {
+    match check {
+        "indirect-jump" => Some(LintCheck::IndirectJump),
+        "unreachable" => Some(LintCheck::Unreachable),
+        "mid-instruction" => Some(LintCheck::MidInstruction),
+        "immediate-label" => Some(LintCheck::ImmediateLabel),
+        "runs-into-data" => Some(LintCheck::RunsIntoData),
+        _ => None,
+    }
+}
+
+pub struct LintConfig {
+    source_file: String,
+    cpu: Cpu, // The instructions available until a .cpu
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} lint [--cpu 6502|6502x|65c02|r65c02|w65c02|65816] <sourcefile>",
+        cmd
+    );
+}
+
+impl LintConfig {
+    pub fn new(args: &mut Vec<String>) -> Result<LintConfig, (ErrorCode, &'static str)> {
+        let mut args = args.drain(2..); // Remove the command and "lint"
+        let mut source_file = None;
+        let mut cpu = Cpu::W65C02;
+
+        while let Some(val) = args.next() {
+            if val == "--cpu" {
+                match args.next().and_then(|cpu| str_to_cpu(&cpu)) {
+                    Some(cpu_val) => cpu = cpu_val,
+                    None => return Err((ErrorCode::Usage, "Unknown CPU")),
+                }
+            } else if source_file.is_none() {
+                source_file = Some(val);
+            } else {
+                return Err((ErrorCode::Usage, "Only one source file can be linted"));
+            }
+        }
+
+        match source_file {
+            Some(source_file) => Ok(LintConfig { source_file, cpu }),
+            None => Err((ErrorCode::Usage, "Missing arguments")),
+        }
+    }
+}
+
+pub fn run(config: &LintConfig) -> Result<(), Error> {
+    let source = read_source(&config.source_file)?;
+    let options = Options {
+        cpu: config.cpu,
+        ..Options::default()
+    };
+    let assembly = assemble_with(&source, Some(&config.source_file), &options, &FileSystem)
+        .map_err(|diagnostics| diagnostics.into_error())?;
+
+    let lints = lint(&assembly.program, &source)?;
+    for found in &lints {
+        println!(
+            "Warning! Line {}{}: {} [{}]",
+            found.line,
+            assembly.program.of_file(found.file),
+            found.message,
+            lint_to_str(found.check)
+        );
+    }
+
+    if !lints.is_empty() {
+        return Err(error(
+            ErrorCode::LintWarnings,
+            format!("{} lint warnings in {}", lints.len(), config.source_file),
+        ));
+    }
+    Ok(())
+}
+
+// Everything the checks find, in address order, less what the pragmas in
+// the source and the files it includes allow
+pub fn lint(program: &Program, source: &str) -> Result<Vec<Lint>, Error> {
+    let mut allowed = pragmas(source, 0)?;
+    for (file, included) in program.files.iter().enumerate().skip(1) {
+        allowed.extend(
+            pragmas(&included.text, file)
+                .map_err(|(code, message)| error(code, message + &program.of_file(file)))?,
+        );
+    }
+
+    Ok(find(program)
+        .into_iter()
+        .filter(|found| {
+            !allowed
+                .get(&(found.file, found.line))
+                .is_some_and(|checks| checks.contains(&found.check))
+        })
+        .collect())
+}
+
+// A pragma allows checks on its own line, or on the next line of code when
+// it's on a line of its own
+fn pragmas(text: &str, file: FileId) -> Result<HashMap<(FileId, Line), Vec<LintCheck>>, Error> {
+    let mut allowed: HashMap<(FileId, Line), Vec<LintCheck>> = HashMap::new();
+    let mut pending = vec![];
+
+    for (number, text) in text.split('\n').enumerate() {
+        let line = (number + 1) as Line;
+        let (code, comment) = text.split_once(';').unwrap_or((text, ""));
+        if let Some((_, names)) = comment.split_once(PRAGMA) {
+            for name in names.split(',').map(str::trim) {
+                match str_to_lint(name) {
+                    Some(check) => pending.push(check),
+                    None => {
+                        return Err(error(
+                            ErrorCode::UnknownSyntax,
+                            format!("Unknown lint {} at line {}", name, line),
+                        ))
+                    }
+                }
+            }
+        }
+        if !code.trim().is_empty() {
+            allowed
+                .entry((file, line))
+                .or_default()
+                .append(&mut pending);
+        }
+    }
+
+    Ok(allowed)
+}
+
+fn find(program: &Program) -> Vec<Lint> {
+    let mut found = vec![];
+    let labelled: HashSet<Address> = program
+        .symbol_table
+        .values()
+        .map(|label| label.address)
+        .collect();
+
+    // The operand bytes of every instruction, and the line it's on
+    let mut operands: HashMap<Address, &CodeTableEntry> = HashMap::new();
+    for entry in &program.code {
+        if let Content::Code(code) = &entry.content {
+            for address in entry.address + 1..entry.address + length(code) {
+                operands.insert(address, entry);
+            }
+        }
+    }
+
+    for (i, entry) in program.code.iter().enumerate() {
+        let code = match &entry.content {
+            Content::Code(code) => code,
+            _ => continue,
+        };
+        let mut report = |check, at: &CodeTableEntry, message| {
+            found.push(Lint {
+                check,
+                file: at.file,
+                line: at.line,
+                message,
+            })
+        };
+        let target = value_of(program, &code.value);
+
+        // Fixed on the 65C02, which takes an extra cycle to get it right
+        if code.mnemonic == Mnemonic::JMP
+            && code.address_mode == AddressMode::Indirect
+            && program.cpu <= Cpu::Mos6502X
+        {
+            if let Some(pointer) = target.filter(|pointer| pointer & 0xff == 0xff) {
+                report(
+                    LintCheck::IndirectJump,
+                    entry,
+                    format!(
+                        "jmp (${:04x}) reads its high byte from ${:04x} on an NMOS 6502, not ${:04x}",
+                        pointer,
+                        pointer & 0xff00,
+                        pointer + 1
+                    ),
+                );
+            }
+        }
+
+        if let (AddressMode::Immediate, false, Value::String(label)) =
+            (code.address_mode, code.wide, &code.value)
+        {
+            if let Some(address) = target.filter(|address| *address > 0xff) {
+                report(
+                    LintCheck::ImmediateLabel,
+                    entry,
+                    format!(
+                        "Only the low byte of {} (${:04x}) is loaded",
+                        label, address
+                    ),
+                );
+            }
+        }
+
+        if jumps(code) {
+            if let Some((address, other)) =
+                target.and_then(|address| operands.get(&address).map(|other| (address, *other)))
+            {
+                report(
+                    LintCheck::MidInstruction,
+                    entry,
+                    format!(
+                        "${:04x} is in the middle of the instruction at line {}{}",
+                        address,
+                        other.line,
+                        program.of_file(other.file)
+                    ),
+                );
+            }
+        }
+
+        // What comes straight after, if anything does. A brk is often
+        // followed by a signature byte, so that's not running into data.
+        let end = entry.address + length(code);
+        let stops = ends_flow(code.mnemonic);
+        let carries_on = !stops && code.mnemonic != Mnemonic::BRK;
+        match program.code.get(i + 1).filter(|next| next.address == end) {
+            Some(next) if stops && is_code(&next.content) && !labelled.contains(&end) => {
+                report(
+                    LintCheck::Unreachable,
+                    next,
+                    format!(
+                        "Nothing jumps to ${:04x}, and line {}{} never carries on to it",
+                        end,
+                        entry.line,
+                        program.of_file(entry.file)
+                    ),
+                );
+            }
+            Some(next) if carries_on && !is_code(&next.content) => {
+                report(
+                    LintCheck::RunsIntoData,
+                    entry,
+                    format!(
+                        "Carries on into the data at line {}{}",
+                        next.line,
+                        program.of_file(next.file)
+                    ),
+                );
+            }
+            None if carries_on => {
+                report(
+                    LintCheck::RunsIntoData,
+                    entry,
+                    format!(
+                        "Carries on past the end of its .org region into ${:04x}",
+                        end
+                    ),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    found
+}
+
+fn is_code(content: &Content) -> bool {
+    matches!(content, Content::Code(_))
+}
+
+fn length(code: &Code) -> Address {
+    address_mode_length(code.address_mode) as Address + code.wide as Address
+}
+
+fn value_of(program: &Program, value: &Value) -> Option<Address> {
+    match value {
+        Value::U8(val) => Some(*val as Address),
+        Value::U16(val) => Some(*val as Address),
+        Value::U24(val) => Some(*val),
+        Value::String(label) => program.symbol_table.get(label).map(|label| label.address),
+        Value::Null => None,
+    }
+}
+
+// Instructions that go somewhere else, and only ever to their operand
+fn jumps(code: &Code) -> bool {
+    matches!(
+        code.address_mode,
+        AddressMode::Relative | AddressMode::RelativeLong
+    ) || matches!(
+        (code.mnemonic, code.address_mode),
+        (Mnemonic::JMP, AddressMode::Absolute)
+            | (Mnemonic::JSR, AddressMode::Absolute)
+            | (Mnemonic::JML, AddressMode::AbsoluteLong)
+            | (Mnemonic::JSL, AddressMode::AbsoluteLong)
+    )
+}
+
+// Instructions that never carry on to the next one
+fn ends_flow(mnemonic: Mnemonic) -> bool {
+    use Mnemonic::*;
+
+    matches!(mnemonic, JMP | JML | BRA | BRL | RTS | RTL | RTI | STP)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::lint;
+    use super::lint_to_str;
+    use crate::assembly::assemble_with;
+    use crate::assembly::Options;
+    use crate::instructions::Cpu;
+    use crate::pass1::pass1_for_cpu;
+    use crate::scanner::scanner;
+    use crate::scanner::Line;
+
+    fn lints(source: &str, cpu: Cpu) -> Vec<(Line, &'static str)> {
+        let program = match pass1_for_cpu(scanner(source), cpu) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}\n{}", err.1, source),
+        };
+        match lint(&program, source) {
+            Ok(found) => found
+                .iter()
+                .map(|found| (found.line, lint_to_str(found.check)))
+                .collect(),
+            Err(err) => panic!("Did not expect linting to fail: {}", err.1),
+        }
+    }
+
+    #[test]
+    fn test_checks() {
+        let source = ".org $e000\n\
+                      reset:\n\
+                      lda #message\n\
+                      jmp ($02ff)\n\
+                      nop\n\
+                      loop:\n\
+                      bne $e00a\n\
+                      sta $0200\n\
+                      jmp loop\n\
+                      message:\n\
+                      .byte $01\n\
+                      tail:\n\
+                      inx\n";
+        assert_eq!(
+            lints(source, Cpu::Mos6502),
+            vec![
+                (3, "immediate-label"),
+                (4, "indirect-jump"),
+                (5, "unreachable"),
+                (7, "mid-instruction"),
+                (13, "runs-into-data"),
+            ]
+        );
+
+        // The 65C02 fixed the indirect jump
+        assert!(!lints(source, Cpu::W65C02).contains(&(4, "indirect-jump")));
+
+        let clean = ".org $e000\nreset:\n    ldx #$00\nloop:\n    inx\n    bne loop\n    rts\n";
+        assert_eq!(lints(clean, Cpu::W65C02), vec![]);
+    }
+
+    #[test]
+    fn test_pragmas() {
+        let source = ".org $e000\n\
+                      reset:\n\
+                      ; lint-allow: immediate-label\n\
+                      lda #reset\n\
+                      rts\n\
+                      inx ; lint-allow: unreachable, runs-into-data\n\
+                      lda #reset ; lint-allow: runs-into-data\n";
+        assert_eq!(lints(source, Cpu::W65C02), vec![(7, "immediate-label")]);
+
+        let program = match pass1_for_cpu(scanner("nop\n"), Cpu::W65C02) {
+            Ok(program) => program,
+            Err(_) => panic!("Did not expect pass1 to fail"),
+        };
+        if lint(&program, "nop ; lint-allow: everything\n").is_ok() {
+            panic!("Did not expect an unknown lint to be allowed");
+        }
+    }
+
+    #[test]
+    fn test_included_pragmas() {
+        // Pragmas and lines in an included file are its own, not the .include's
+        let files: HashMap<String, Vec<u8>> = [(
+            "io.s".to_string(),
+            b"; lint-allow: immediate-label\nlda #reset\nlda #reset\n".to_vec(),
+        )]
+        .iter()
+        .cloned()
+        .collect();
+        let source = ".org $e000\nreset:\n.include \"io.s\"\nrts\n";
+        let assembly = match assemble_with(source, None, &Options::default(), &files) {
+            Ok(assembly) => assembly,
+            Err(_) => panic!("Did not expect the include to fail"),
+        };
+        match lint(&assembly.program, source) {
+            Ok(found) => assert_eq!(
+                found
+                    .iter()
+                    .map(|found| (found.file, found.line, lint_to_str(found.check)))
+                    .collect::<Vec<_>>(),
+                vec![(1, 3, "immediate-label")]
+            ),
+            Err(err) => panic!("Did not expect linting to fail: {}", err.1),
+        }
+    }
+}
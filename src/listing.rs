@@ -0,0 +1,302 @@
+// Listing files show each line of source next to the bytes it produced
+use std::collections::HashMap;
+
+use crate::instructions::instruction_cycles;
+use crate::instructions::InstructionKey;
+use crate::pass1::Address;
+use crate::pass1::Content;
+use crate::pass1::ListingControl;
+use crate::pass1::Program;
+use crate::pass2::ListingEntry;
+use crate::scanner::FileId;
+use crate::scanner::Line;
+
+const BYTES_PER_ROW: usize = 4;
+const CYCLES_WIDTH: usize = 5; // Room for 2-4, or a block's total
+const PAGE_LENGTH: usize = 60; // Rows per page, not counting the header
+const TITLE_WIDTH: usize = 70;
+
+struct Listing {
+    text: String,
+    title: String,
+    page: usize,
+    row: usize,    // The row we're up to on the current page
+    enabled: bool, // Between a .list off and a .list on
+}
+
+impl Listing {
+    fn push(&mut self, row: String) {
+        // Pages are started lazily so a .title at the top of the page is used
+        if self.row == PAGE_LENGTH {
+            if self.page > 0 {
+                self.text.push('\x0c');
+            }
+            self.page += 1;
+            self.row = 0;
+            self.text.push_str(&format!(
+                "{:<width$}Page {}\n\n",
+                self.title,
+                self.page,
+                width = TITLE_WIDTH
+            ));
+        }
+        self.text.push_str(row.trim_end());
+        self.text.push('\n');
+        self.row += 1;
+    }
+}
+
+// What each line did, by file and line
+struct Lines<'a> {
+    emitted: HashMap<(FileId, Line), Vec<&'a ListingEntry>>,
+    labels: HashMap<(FileId, Line), Address>,
+    cycles: HashMap<(FileId, Line), String>,
+    controls: HashMap<(FileId, Line), &'a ListingControl>,
+}
+
+// The source, with each included file listed after the .include that read it
+pub fn listing(source: &str, program: &Program, entries: &[ListingEntry]) -> String {
+    // A line like .vectors can emit bytes at more than one address
+    let mut emitted: HashMap<(FileId, Line), Vec<&ListingEntry>> = HashMap::new();
+    for entry in entries {
+        emitted
+            .entry((entry.file, entry.line))
+            .or_default()
+            .push(entry);
+    }
+
+    // Labels and .equ's show their value in the address column
+    let mut labels: HashMap<(FileId, Line), Address> = HashMap::new();
+    for label in program.symbol_table.values() {
+        labels.insert((label.file, label.line), label.address);
+    }
+
+    // Instructions show how long they take, and .endcycles the block's total
+    let mut cycles: HashMap<(FileId, Line), String> = HashMap::new();
+    for entry in &program.code {
+        if let Content::Code(code) = &entry.content {
+            let timing = instruction_cycles(InstructionKey {
+                mnemonic: code.mnemonic,
+                address_mode: code.address_mode,
+            });
+            cycles.insert(
+                (entry.file, entry.line),
+                cycle_range(timing.min(), timing.max()),
+            );
+        }
+    }
+    for block in &program.cycle_blocks {
+        cycles.insert((block.file, block.end), cycle_range(block.min, block.max));
+    }
+
+    let mut controls: HashMap<(FileId, Line), &ListingControl> = HashMap::new();
+    for (file, line, control) in &program.listing {
+        controls.insert((*file, *line), control);
+    }
+
+    let mut listing = Listing {
+        text: String::new(),
+        title: String::new(),
+        page: 0,
+        row: PAGE_LENGTH,
+        enabled: true,
+    };
+    let lines = Lines {
+        emitted,
+        labels,
+        cycles,
+        controls,
+    };
+    list_file(&mut listing, &lines, program, 0, source);
+
+    listing.text
+}
+
+fn list_file(listing: &mut Listing, found: &Lines, program: &Program, file: FileId, text: &str) {
+    let lines: Vec<&str> = text.split('\n').collect();
+    for (index, raw_line) in lines.iter().enumerate() {
+        // Don't list the empty line after the final newline
+        if index == lines.len() - 1 && raw_line.is_empty() {
+            break;
+        }
+
+        let line = (index + 1) as Line;
+        list_line(listing, found, (file, line), raw_line.trim_end());
+
+        for (included, source) in program.files.iter().enumerate() {
+            if source.included_at == Some((file, line)) {
+                list_file(listing, found, program, included, &source.text);
+            }
+        }
+    }
+}
+
+fn list_line(listing: &mut Listing, found: &Lines, at: (FileId, Line), text: &str) {
+    let line = at.1;
+
+    // Control directives change the listing but aren't listed themselves
+    if let Some(control) = found.controls.get(&at) {
+        match control {
+            ListingControl::On => listing.enabled = true,
+            ListingControl::Off => listing.enabled = false,
+            ListingControl::Title(title) => listing.title = title.to_string(),
+            ListingControl::Page => listing.row = PAGE_LENGTH,
+        }
+        return;
+    }
+
+    if !listing.enabled {
+        return;
+    }
+
+    let taken = found.cycles.get(&at).map(String::as_str).unwrap_or("");
+    match found
+        .emitted
+        .get(&at)
+        .and_then(|entries| entries.split_first())
+    {
+        Some((entry, rest)) => {
+            let mut address = entry.address;
+            let mut chunks = entry.bytes.chunks(BYTES_PER_ROW);
+            let first = chunks.next().unwrap_or(&[]);
+            listing.push(format!(
+                "{:>5}  {:04x}  {:<width$}  {:<CYCLES_WIDTH$}  {}",
+                line,
+                address,
+                hex_bytes(first),
+                taken,
+                text,
+                width = BYTES_PER_ROW * 3 - 1
+            ));
+            // Long data wraps onto rows of its own
+            for chunk in chunks {
+                address = address.wrapping_add(BYTES_PER_ROW as Address);
+                listing.push(format!("       {:04x}  {}", address, hex_bytes(chunk)));
+            }
+            for entry in rest {
+                let mut address = entry.address;
+                for chunk in entry.bytes.chunks(BYTES_PER_ROW) {
+                    listing.push(format!("       {:04x}  {}", address, hex_bytes(chunk)));
+                    address = address.wrapping_add(BYTES_PER_ROW as Address);
+                }
+            }
+        }
+        None => match found.labels.get(&at) {
+            Some(address) => listing.push(format!(
+                "{:>5}  {:04x}  {:<width$}  {:<CYCLES_WIDTH$}  {}",
+                line,
+                address,
+                "",
+                taken,
+                text,
+                width = BYTES_PER_ROW * 3 - 1
+            )),
+            None => listing.push(format!(
+                "{:>5}  {:<width$}  {:<CYCLES_WIDTH$}  {}",
+                line,
+                "",
+                taken,
+                text,
+                width = 4 + 2 + BYTES_PER_ROW * 3 - 1
+            )),
+        },
+    }
+}
+
+// A single number when there's nothing to vary it
+fn cycle_range(min: u32, max: u32) -> String {
+    if min == max {
+        min.to_string()
+    } else {
+        format!("{}-{}", min, max)
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    bytes.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listing;
+    use crate::instructions::generate_instruction_set;
+    use crate::pass1::pass1;
+    use crate::pass2::generate;
+    use crate::scanner::scanner;
+
+    fn list(source: &str) -> String {
+        let program = match pass1(scanner(source)) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}", err.1),
+        };
+        let output = match generate(&generate_instruction_set(), &program) {
+            Ok(output) => output,
+            Err(err) => panic!("Did not expect pass2 to fail: {}", err.1),
+        };
+        listing(source, &program, &output.listing)
+    }
+
+    #[test]
+    fn test_listing() {
+        let text = list(
+            ".title \"Test\"\n.org $1000\nstart:\n    lda #$2b ; load\n.byte $01,$02,$03,$04,$05\n",
+        );
+        let lines: Vec<&str> = text.lines().collect();
+        assert!(lines[0].starts_with("Test"));
+        assert!(lines[0].ends_with("Page 1"));
+        assert_eq!(lines[2], "    2                            .org $1000");
+        assert_eq!(lines[3], "    3  1000                      start:");
+        assert_eq!(
+            lines[4],
+            "    4  1000  a9 2b        2          lda #$2b ; load"
+        );
+        assert_eq!(
+            lines[5],
+            "    5  1002  01 02 03 04         .byte $01,$02,$03,$04,$05"
+        );
+        assert_eq!(lines[6], "       1006  05");
+    }
+
+    #[test]
+    fn test_listing_cycles() {
+        let text = list(".cycles 5, 10\nloop:\nlda $1234,x\nbne loop\n.endcycles\n");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[4], "    3  1000  bd 34 12     4-5    lda $1234,x");
+        assert_eq!(lines[5], "    4  1003  d0 fb        2-4    bne loop");
+        assert_eq!(lines[6], "    5                     6-9    .endcycles");
+    }
+
+    #[test]
+    fn test_listing_bra() {
+        // Always taken, so never the 2 cycles of a branch that isn't
+        let text = list(".cycles 3, 4\nloop:\nbra loop\n.endcycles\n");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[4], "    3  1000  80 fe        3-4    bra loop");
+        assert_eq!(lines[5], "    4                     3-4    .endcycles");
+    }
+
+    #[test]
+    fn test_listing_vectors() {
+        let text = list("start:\nnop\n.vectors nmi=start, reset=start\n");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(
+            lines[4],
+            "    3  fffa  00 10               .vectors nmi=start, reset=start"
+        );
+        assert_eq!(lines[5], "       fffc  00 10");
+    }
+
+    #[test]
+    fn test_listing_off() {
+        let text = list("nop\n.list off\nnop\n.list on\nnop\n.page\nnop\n");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[2], "    1  1000  ea           2      nop");
+        assert_eq!(lines[3], "    5  1002  ea           2      nop");
+        assert_eq!(
+            lines[4],
+            "\x0c                                                                      Page 2"
+        );
+        assert_eq!(lines[6], "    7  1003  ea           2      nop");
+    }
+}
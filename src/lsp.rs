@@ -0,0 +1,745 @@
+// A language server over stdio, so editors can show kasm's errors as the
+// source is typed and find their way around its labels
+use std::collections::HashMap;
+use std::io::BufRead;
+use std::io::Write;
+
+use crate::assembly::assemble_with;
+use crate::assembly::Options;
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::instructions::address_mode_length;
+use crate::instructions::cpu_to_str;
+use crate::instructions::generate_instruction_set;
+use crate::instructions::minimum_cpu;
+use crate::instructions::mnemonic_to_str;
+use crate::instructions::str_to_mnemonic;
+use crate::instructions::InstructionMap;
+use crate::json::object;
+use crate::json::parse;
+use crate::json::Json;
+use crate::pass1::LabelTable;
+use crate::scanner::FileId;
+use crate::scanner::Line;
+use crate::sources::FileSystem;
+use crate::sources::SourceFile;
+use crate::sources::SourceProvider;
+
+const METHOD_NOT_FOUND: f64 = -32601.0;
+
+struct Document {
+    text: String,
+    symbols: LabelTable, // From the last time it assembled, so they survive typos
+    files: Vec<SourceFile>, // And the files it included then, for their labels
+}
+
+struct Server {
+    documents: HashMap<String, Document>, // By URI
+    instruction_set: InstructionMap,
+    exited: bool,
+}
+
+// Open documents win over what's saved, for includes that are being edited
+struct Workspace<'a> {
+    documents: &'a HashMap<String, Document>,
+}
+
+impl SourceProvider for Workspace<'_> {
+    fn read_source(&self, name: &str) -> Result<String, Error> {
+        match self
+            .documents
+            .iter()
+            .find(|(uri, _)| uri_to_path(uri) == name)
+        {
+            Some((_, document)) => Ok(document.text.clone()),
+            None => FileSystem.read_source(name),
+        }
+    }
+
+    fn read_binary(&self, name: &str) -> Result<Vec<u8>, Error> {
+        FileSystem.read_binary(name)
+    }
+}
+
+// Answers messages until the client says to exit, or stops talking
+pub fn serve(input: &mut dyn BufRead, output: &mut dyn Write) -> Result<(), Error> {
+    let mut server = Server::new();
+    while let Some(message) = read_message(input)? {
+        for reply in server.handle(&message) {
+            write_message(output, &reply)?;
+        }
+        if server.exited {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+// A message is a Content-Length header, a blank line, then that much JSON
+fn read_message(input: &mut dyn BufRead) -> Result<Option<Json>, Error> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        match input.read_line(&mut header) {
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(err) => {
+                return Err(error(
+                    ErrorCode::FileRead,
+                    format!("Couldn't read a message: {}", err),
+                ))
+            }
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = match length {
+        Some(length) => length,
+        None => {
+            return Err(error(
+                ErrorCode::MalformedMessage,
+                "Message without a Content-Length".to_string(),
+            ))
+        }
+    };
+    let mut body = vec![0; length];
+    if let Err(err) = input.read_exact(&mut body) {
+        return Err(error(
+            ErrorCode::FileRead,
+            format!("Couldn't read a message: {}", err),
+        ));
+    }
+    match String::from_utf8(body) {
+        Ok(body) => parse(&body).map(Some),
+        Err(_) => Err(error(
+            ErrorCode::MalformedMessage,
+            "Message isn't UTF-8".to_string(),
+        )),
+    }
+}
+
+fn write_message(output: &mut dyn Write, message: &Json) -> Result<(), Error> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)
+        .and_then(|_| output.flush())
+        .map_err(|err| {
+            error(
+                ErrorCode::FileWrite,
+                format!("Couldn't write a message: {}", err),
+            )
+        })
+}
+
+impl Server {
+    pub fn new() -> Server {
+        Server {
+            documents: HashMap::new(),
+            instruction_set: generate_instruction_set(),
+            exited: false,
+        }
+    }
+
+    // The replies and notifications for one message from the client
+    pub fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let id = message.get("id");
+        let params = message.get("params");
+        let uri = params.get("textDocument").get("uri").as_str().unwrap_or("");
+
+        match message.get("method").as_str().unwrap_or("") {
+            "initialize" => vec![response(id, capabilities())],
+            "shutdown" => vec![response(id, Json::Null)],
+            "exit" => {
+                self.exited = true;
+                vec![]
+            }
+            "textDocument/didOpen" => {
+                let text = params.get("textDocument").get("text").as_str();
+                self.update(uri, text.unwrap_or(""))
+            }
+            "textDocument/didChange" => {
+                // Only whole documents are asked for, so the last change is all of it
+                let changes = params.get("contentChanges").as_array().unwrap_or(&[]);
+                match changes
+                    .last()
+                    .and_then(|change| change.get("text").as_str())
+                {
+                    Some(text) => self.update(uri, text),
+                    None => vec![],
+                }
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                vec![diagnostics(uri, vec![])]
+            }
+            "textDocument/definition" => vec![response(id, self.definition(params))],
+            "textDocument/references" => vec![response(id, self.references(params))],
+            "textDocument/hover" => vec![response(id, self.hover(params))],
+            _ if *id != Json::Null => vec![object(vec![
+                ("jsonrpc", Json::String("2.0".to_string())),
+                ("id", id.clone()),
+                (
+                    "error",
+                    object(vec![
+                        ("code", Json::Number(METHOD_NOT_FOUND)),
+                        ("message", Json::String("Method not found".to_string())),
+                    ]),
+                ),
+            ])],
+            _ => vec![], // Notifications we've no use for, like initialized
+        }
+    }
+
+    // Assemble the new text and say what's wrong with it, if anything
+    fn update(&mut self, uri: &str, text: &str) -> Vec<Json> {
+        let workspace = Workspace {
+            documents: &self.documents,
+        };
+        let path = uri_to_path(uri);
+        let assembled = assemble_with(text, Some(&path), &Options::default(), &workspace);
+
+        let (assembled, found) = match assembled {
+            Ok(assembly) => (
+                Some((assembly.program.symbol_table, assembly.program.files)),
+                vec![],
+            ),
+            Err(errors) => (
+                None,
+                errors
+                    .errors
+                    .into_iter()
+                    .map(|diagnostic| {
+                        // Errors in an included file are shown on the .include
+                        let (line, message) = match &diagnostic.file {
+                            Some(file) => (
+                                diagnostic.include_line,
+                                format!(
+                                    "{} line {}: {}",
+                                    file,
+                                    diagnostic.line.unwrap_or(1),
+                                    diagnostic.message
+                                ),
+                            ),
+                            None => (diagnostic.line, diagnostic.message),
+                        };
+                        object(vec![
+                            ("range", line_range(text, line.unwrap_or(1))),
+                            ("severity", Json::Number(1.0)),
+                            ("code", Json::Number(diagnostic.code as i32 as f64)),
+                            ("source", Json::String("kasm".to_string())),
+                            ("message", Json::String(message)),
+                        ])
+                    })
+                    .collect(),
+            ),
+        };
+
+        let previous = self
+            .documents
+            .remove(uri)
+            .map(|document| (document.symbols, document.files));
+        let (symbols, files) = assembled.or(previous).unwrap_or_default();
+        self.documents.insert(
+            uri.to_string(),
+            Document {
+                text: text.to_string(),
+                symbols,
+                files,
+            },
+        );
+
+        vec![diagnostics(uri, found)]
+    }
+
+    // The document and the word under the cursor
+    fn word_at<'a>(&'a self, params: &'a Json) -> Option<(&'a str, &'a Document, String)> {
+        let uri = params.get("textDocument").get("uri").as_str()?;
+        let document = self.documents.get(uri)?;
+        let line = params.get("position").get("line").as_u64()? as usize;
+        let character = params.get("position").get("character").as_u64()? as usize;
+
+        let text = document.text.split('\n').nth(line)?;
+        let character = from_utf16(text, character);
+        let (_, word) = identifiers(text)
+            .into_iter()
+            .find(|(start, word)| (*start..=start + word.len()).contains(&character))?;
+        Some((uri, document, word.to_string()))
+    }
+
+    fn definition(&self, params: &Json) -> Json {
+        let (uri, document, word) = match self.word_at(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        match document.symbols.get(&word) {
+            Some(label) => match document.included(label.file) {
+                Some(included) => location(
+                    &path_to_uri(&included.name),
+                    &included.text,
+                    label.line,
+                    &word,
+                ),
+                None => location(uri, &document.text, label.line, &word),
+            },
+            None => Json::Null,
+        }
+    }
+
+    fn references(&self, params: &Json) -> Json {
+        let (uri, document, word) = match self.word_at(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+        let label = match document.symbols.get(&word) {
+            Some(label) => label,
+            None => return Json::Null,
+        };
+        let declaration = params
+            .get("context")
+            .get("includeDeclaration")
+            .as_bool()
+            .unwrap_or(true);
+
+        let mut found = vec![];
+        for (number, text) in document.text.split('\n').enumerate() {
+            let line = (number + 1) as Line;
+            if label.file == 0 && line == label.line && !declaration {
+                continue;
+            }
+            for (start, _) in identifiers(text).into_iter().filter(|(_, w)| *w == word) {
+                found.push(object(vec![
+                    ("uri", Json::String(uri.to_string())),
+                    ("range", range(number, text, start, start + word.len())),
+                ]));
+            }
+        }
+        Json::Array(found)
+    }
+
+    fn hover(&self, params: &Json) -> Json {
+        let (_, document, word) = match self.word_at(params) {
+            Some(found) => found,
+            None => return Json::Null,
+        };
+
+        let markdown = if let Some(label) = document.symbols.get(&word) {
+            format!("`{}` is at `{}`", word, format_address(label.address))
+        } else if let Ok(mnemonic) = str_to_mnemonic(&word.to_lowercase()) {
+            let mut keys: Vec<_> = self
+                .instruction_set
+                .iter()
+                .filter(|(key, _)| key.mnemonic == mnemonic)
+                .collect();
+            keys.sort_by_key(|(key, opcode)| (minimum_cpu(**key), **opcode));
+
+            let mut markdown = format!(
+                "**{}**\n\n|Mode|Opcode|Bytes|CPU|\n|----|------|-----|---|\n",
+                mnemonic_to_str(mnemonic)
+            );
+            for (key, opcode) in keys {
+                markdown.push_str(&format!(
+                    "|{:?}|${:02x}|{}|{}|\n",
+                    key.address_mode,
+                    opcode,
+                    address_mode_length(key.address_mode),
+                    cpu_to_str(minimum_cpu(*key))
+                ));
+            }
+            markdown
+        } else {
+            return Json::Null;
+        };
+
+        object(vec![(
+            "contents",
+            object(vec![
+                ("kind", Json::String("markdown".to_string())),
+                ("value", Json::String(markdown)),
+            ]),
+        )])
+    }
+}
+
+impl Document {
+    // A file the document included, when it isn't the document itself
+    fn included(&self, file: FileId) -> Option<&SourceFile> {
+        self.files.get(file).filter(|_| file > 0)
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn capabilities() -> Json {
+    object(vec![(
+        "capabilities",
+        object(vec![
+            ("textDocumentSync", Json::Number(1.0)), // The whole document on every change
+            ("definitionProvider", Json::Bool(true)),
+            ("referencesProvider", Json::Bool(true)),
+            ("hoverProvider", Json::Bool(true)),
+        ]),
+    )])
+}
+
+fn response(id: &Json, result: Json) -> Json {
+    object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn diagnostics(uri: &str, found: Vec<Json>) -> Json {
+    object(vec![
+        ("jsonrpc", Json::String("2.0".to_string())),
+        (
+            "method",
+            Json::String("textDocument/publishDiagnostics".to_string()),
+        ),
+        (
+            "params",
+            object(vec![
+                ("uri", Json::String(uri.to_string())),
+                ("diagnostics", Json::Array(found)),
+            ]),
+        ),
+    ])
+}
+
+// The range between two byte offsets into a line's code
+fn range(line: usize, code: &str, start: usize, end: usize) -> Json {
+    let position = |character: usize| {
+        object(vec![
+            ("line", Json::Number(line as f64)),
+            ("character", Json::Number(to_utf16(code, character) as f64)),
+        ])
+    };
+    object(vec![("start", position(start)), ("end", position(end))])
+}
+
+// The code on a line, without the indentation. kasm counts lines from 1.
+fn line_range(text: &str, line: Line) -> Json {
+    let number = (line as usize).saturating_sub(1);
+    let code = text.split('\n').nth(number).unwrap_or("").trim_end();
+    let indent = code.len() - code.trim_start().len();
+    range(number, code, indent, code.len())
+}
+
+// Editors count characters in UTF-16 code units, but kasm works in bytes
+fn to_utf16(code: &str, byte: usize) -> usize {
+    code[..byte].encode_utf16().count()
+}
+
+fn from_utf16(code: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (byte, ch) in code.char_indices() {
+        if units >= character {
+            return byte;
+        }
+        units += ch.len_utf16();
+    }
+    code.len()
+}
+
+// Where a label is defined, on the line it's defined on when it's there
+fn location(uri: &str, text: &str, line: Line, word: &str) -> Json {
+    let number = (line as usize).saturating_sub(1);
+    let code = text.split('\n').nth(number).unwrap_or("");
+    let found = identifiers(code).into_iter().find(|(_, w)| *w == word);
+    object(vec![
+        ("uri", Json::String(uri.to_string())),
+        (
+            "range",
+            match found {
+                Some((start, _)) => range(number, code, start, start + word.len()),
+                None => line_range(text, line),
+            },
+        ),
+    ])
+}
+
+// The words on a line that could be labels or mnemonics, and where they
+// start. Numbers, directives, strings and comments are left out.
+fn identifiers(line: &str) -> Vec<(usize, &str)> {
+    let is_word = |ch: char| ch.is_ascii_alphanumeric() || ch == '_';
+    let mut found = vec![];
+    let mut in_string = false;
+    let mut start = None;
+
+    for (i, ch) in line
+        .char_indices()
+        .chain(std::iter::once((line.len(), ' ')))
+    {
+        if in_string {
+            in_string = ch != '"';
+            continue;
+        }
+        if is_word(ch) {
+            start = start.or(Some(i));
+            continue;
+        }
+        if let Some(start) = start.take() {
+            let before = line[..start].chars().last();
+            let number = line[start..].starts_with(|c: char| c.is_ascii_digit());
+            if !number && !matches!(before, Some('$') | Some('%') | Some('.')) {
+                found.push((start, &line[start..i]));
+            }
+        }
+        match ch {
+            '"' => in_string = true,
+            ';' => break,
+            _ => {}
+        }
+    }
+
+    found
+}
+
+fn format_address(address: u32) -> String {
+    if address > 0xffff {
+        format!("${:06x}", address)
+    } else {
+        format!("${:04x}", address)
+    }
+}
+
+// file:///home/me/rom.s is /home/me/rom.s, with any %20s and friends decoded
+// Escaping anything that isn't safe in a URI, which is what clients send
+fn path_to_uri(path: &str) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+fn uri_to_path(uri: &str) -> String {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = vec![];
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (byte, escaped) {
+            (b'%', Some(decoded)) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::identifiers;
+    use super::path_to_uri;
+    use super::serve;
+    use super::uri_to_path;
+    use super::Server;
+    use crate::json::parse;
+    use crate::json::Json;
+
+    const URI: &str = "file:///tmp/kasm%20lsp/rom.s";
+
+    fn message(text: &str) -> Json {
+        match parse(text) {
+            Ok(json) => json,
+            Err(err) => panic!("Did not expect the test message to fail: {}", err.1),
+        }
+    }
+
+    fn open(server: &mut Server, text: &str) -> Vec<Json> {
+        open_at(server, URI, text)
+    }
+
+    fn open_at(server: &mut Server, uri: &str, text: &str) -> Vec<Json> {
+        let text = Json::String(text.to_string()).to_string();
+        server.handle(&message(&format!(
+            r#"{{"method":"textDocument/didOpen","params":{{"textDocument":{{"uri":"{}","text":{}}}}}}}"#,
+            uri, text
+        )))
+    }
+
+    fn request(server: &mut Server, method: &str, line: u32, character: u32) -> Json {
+        let replies = server.handle(&message(&format!(
+            r#"{{"id":7,"method":"{}","params":{{"textDocument":{{"uri":"{}"}},"position":{{"line":{},"character":{}}},"context":{{"includeDeclaration":true}}}}}}"#,
+            method, URI, line, character
+        )));
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].get("id").as_u64(), Some(7));
+        replies[0].get("result").clone()
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let mut server = Server::new();
+        let published = open(
+            &mut server,
+            ".org $e000\nreset:\n    lda #$01\n    jmp nowhere\n",
+        );
+        assert_eq!(published.len(), 1);
+        let found = published[0].get("params").get("diagnostics");
+        let found = found.as_array().unwrap_or(&[]);
+        assert_eq!(found.len(), 1);
+        let start = found[0].get("range").get("start");
+        assert_eq!(start.get("line").as_u64(), Some(3));
+        assert_eq!(start.get("character").as_u64(), Some(4));
+
+        // Fixing it clears them
+        let published = open(&mut server, ".org $e000\nreset:\n    jmp reset\n");
+        assert_eq!(
+            published[0].get("params").get("diagnostics"),
+            &Json::Array(vec![])
+        );
+    }
+
+    #[test]
+    fn test_navigation() {
+        let mut server = Server::new();
+        open(
+            &mut server,
+            ".org $e000\nreset:\n    lda #$01 ; reset the thing\n    jmp reset\n",
+        );
+
+        let definition = request(&mut server, "textDocument/definition", 3, 9);
+        assert_eq!(definition.get("uri").as_str(), Some(URI));
+        let start = definition.get("range").get("start");
+        assert_eq!(start.get("line").as_u64(), Some(1));
+        assert_eq!(start.get("character").as_u64(), Some(0));
+
+        // The comment mentioning it isn't a reference
+        let references = request(&mut server, "textDocument/references", 1, 2);
+        let lines: Vec<_> = references
+            .as_array()
+            .unwrap_or(&[])
+            .iter()
+            .map(|found| found.get("range").get("start").get("line").as_u64())
+            .collect();
+        assert_eq!(lines, vec![Some(1), Some(3)]);
+
+        let hover = request(&mut server, "textDocument/hover", 3, 10);
+        assert_eq!(
+            hover.get("contents").get("value").as_str(),
+            Some("`reset` is at `$e000`")
+        );
+        let hover = request(&mut server, "textDocument/hover", 2, 5);
+        let markdown = hover.get("contents").get("value").as_str().unwrap_or("");
+        assert!(markdown.starts_with("**lda**"));
+        assert!(markdown.contains("|Immediate|$a9|2|6502|"));
+
+        // Nothing to say about numbers
+        assert_eq!(
+            request(&mut server, "textDocument/hover", 2, 11),
+            Json::Null
+        );
+    }
+
+    #[test]
+    fn test_includes() {
+        let io = "file:///tmp/kasm%20lsp/io.s";
+        let mut server = Server::new();
+        open_at(&mut server, io, "nop\nputc:\n    rts\n");
+        open(&mut server, ".org $e000\n.include \"io.s\"\n    jsr putc\n");
+
+        // The label is found in the file that has it, on its own line there
+        let definition = request(&mut server, "textDocument/definition", 2, 9);
+        assert_eq!(definition.get("uri").as_str(), Some(io));
+        let start = definition.get("range").get("start");
+        assert_eq!(start.get("line").as_u64(), Some(1));
+
+        // An error in the include is shown on the .include
+        open_at(&mut server, io, "nop\nbogus\n");
+        let published = open(&mut server, ".org $e000\n.include \"io.s\"\n    jsr putc\n");
+        let found = published[0].get("params").get("diagnostics");
+        let found = found.as_array().unwrap_or(&[]);
+        assert_eq!(found.len(), 1);
+        let start = found[0].get("range").get("start");
+        assert_eq!(start.get("line").as_u64(), Some(1));
+        let message = found[0].get("message").as_str().unwrap_or("");
+        assert!(message.starts_with("/tmp/kasm lsp/io.s line 2: "));
+    }
+
+    #[test]
+    fn test_serve() {
+        let messages: String = [
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+            r#"{"jsonrpc":"2.0","id":2,"method":"bogus"}"#,
+            r#"{"jsonrpc":"2.0","id":3,"method":"shutdown"}"#,
+            r#"{"jsonrpc":"2.0","method":"exit"}"#,
+            r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#,
+        ]
+        .iter()
+        .map(|body| format!("Content-Length: {}\r\n\r\n{}", body.len(), body))
+        .collect();
+        let mut output = vec![];
+        if serve(&mut Cursor::new(messages), &mut output).is_err() {
+            panic!("Did not expect serving to fail");
+        }
+
+        let output = String::from_utf8_lossy(&output);
+        let replies: Vec<_> = output.split("Content-Length: ").skip(1).collect();
+        assert_eq!(replies.len(), 3);
+        assert!(replies[0].contains(r#""hoverProvider":true"#));
+        assert!(replies[1].contains(r#""code":-32601"#));
+        assert!(replies[2].ends_with(r#"{"jsonrpc":"2.0","id":3,"result":null}"#));
+    }
+
+    #[test]
+    fn test_utf16_positions() {
+        let mut server = Server::new();
+        open(&mut server, ".org $e000\nreset:\n    jmp reset\n");
+        // The emoji is two UTF-16 code units but four bytes, so reset starts
+        // at character 17 but byte 20. The labels survive the error.
+        open(
+            &mut server,
+            ".org $e000\nreset:\n    .byte \"\u{e9}\u{1f600}\", reset\n",
+        );
+
+        let references = request(&mut server, "textDocument/references", 2, 18);
+        let found = references.as_array().unwrap_or(&[]);
+        assert_eq!(found.len(), 2);
+        let range = found[1].get("range");
+        assert_eq!(range.get("start").get("line").as_u64(), Some(2));
+        assert_eq!(range.get("start").get("character").as_u64(), Some(17));
+        assert_eq!(range.get("end").get("character").as_u64(), Some(22));
+    }
+
+    #[test]
+    fn test_identifiers() {
+        assert_eq!(
+            identifiers("loop: lda $ff,x ; loop back"),
+            vec![(0, "loop"), (6, "lda"), (14, "x")]
+        );
+        assert_eq!(
+            identifiers(".byte 12, \"a label\", %0101, end"),
+            vec![(28, "end")]
+        );
+        assert_eq!(uri_to_path(URI), "/tmp/kasm lsp/rom.s");
+        assert_eq!(path_to_uri("/tmp/kasm lsp/rom.s"), URI);
+    }
+}
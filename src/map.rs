@@ -0,0 +1,216 @@
+// Symbol maps, so we can find where everything ended up after assembly
+use crate::pass1::Label;
+use crate::pass1::LabelTable;
+use crate::sources::SourceFile;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MapFormat {
+    Plain, // Symbols sorted by name, then by address
+    Vice,  // Labels for the VICE monitor, load with `ll "file"`
+    Dbg,   // A ca65 style debug info file
+}
+
+pub fn str_to_map_format(format: &str) -> Option<MapFormat> {
+    match format {
+        "plain" => Some(MapFormat::Plain),
+        "vice" => Some(MapFormat::Vice),
+        "dbg" => Some(MapFormat::Dbg),
+        _ => None,
+    }
+}
+
+// The files are the source and everything it included, for the debug info
+pub fn symbol_map(format: MapFormat, symbols: &LabelTable, files: &[SourceFile]) -> String {
+    match format {
+        MapFormat::Plain => plain_map(symbols),
+        MapFormat::Vice => vice_map(symbols),
+        MapFormat::Dbg => dbg_map(symbols, files),
+    }
+}
+
+// HashMap iteration order changes from run to run, so always sort
+fn by_name(symbols: &LabelTable) -> Vec<(&String, &Label)> {
+    let mut sorted: Vec<(&String, &Label)> = symbols.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(b.0));
+    sorted
+}
+
+fn by_address(symbols: &LabelTable) -> Vec<(&String, &Label)> {
+    let mut sorted = by_name(symbols);
+    sorted.sort_by_key(|(_, label)| label.address); // Stable, so names stay in order
+    sorted
+}
+
+fn plain_map(symbols: &LabelTable) -> String {
+    let width = symbols.keys().map(|name| name.len()).max().unwrap_or(0);
+    let mut map = String::from("Symbols by name\n\n");
+
+    for (name, label) in by_name(symbols) {
+        map.push_str(&format!(
+            "{:<width$}  ${:04x}\n",
+            name,
+            label.address,
+            width = width
+        ));
+    }
+
+    map.push_str("\nSymbols by address\n\n");
+    for (name, label) in by_address(symbols) {
+        map.push_str(&format!("${:04x}  {}\n", label.address, name));
+    }
+
+    map
+}
+
+fn vice_map(symbols: &LabelTable) -> String {
+    let mut map = String::new();
+
+    for (name, label) in by_address(symbols) {
+        // VICE labels are limited to letters, digits and underscores
+        let name: String = name
+            .chars()
+            .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+            .collect();
+        map.push_str(&format!("al C:{:04x} .{}\n", label.address, name));
+    }
+
+    map
+}
+
+fn dbg_map(symbols: &LabelTable, files: &[SourceFile]) -> String {
+    let sorted = by_name(symbols);
+
+    let mut map = String::from("version\tmajor=2,minor=0\n");
+    map.push_str(&format!(
+        "info\tcsym=0,file={},lib=0,line={},mod=1,scope=1,seg=0,span=0,sym={},type=0\n",
+        files.len(),
+        sorted.len(),
+        sorted.len()
+    ));
+    for (id, file) in files.iter().enumerate() {
+        map.push_str(&format!(
+            "file\tid={},name=\"{}\",size={},mtime=0x00000000,mod=0\n",
+            id,
+            file.name,
+            file.text.len()
+        ));
+    }
+
+    for (id, (_, label)) in sorted.iter().enumerate() {
+        map.push_str(&format!(
+            "line\tid={},file={},line={}\n",
+            id, label.file, label.line
+        ));
+    }
+
+    let source_file = files.first().map_or("", |file| file.name.as_str());
+    map.push_str(&format!("mod\tid=0,name=\"{}\",file=0\n", source_file));
+    map.push_str("scope\tid=0,name=\"\",mod=0\n");
+
+    for (id, (name, label)) in sorted.iter().enumerate() {
+        let addrsize = if label.address <= 0xff {
+            "zeropage"
+        } else {
+            "absolute"
+        };
+        map.push_str(&format!(
+            "sym\tid={},name=\"{}\",addrsize={},scope=0,def={},val=0x{:x},type=lab\n",
+            id, name, addrsize, id, label.address
+        ));
+    }
+
+    map
+}
+
+#[cfg(test)]
+mod tests {
+    use super::symbol_map;
+    use super::MapFormat;
+    use crate::pass1::Label;
+    use crate::pass1::LabelTable;
+    use crate::sources::SourceFile;
+
+    fn files() -> Vec<SourceFile> {
+        vec![
+            SourceFile {
+                name: "test.s".to_string(),
+                text: "nop\n".to_string(),
+                included_at: None,
+            },
+            SourceFile {
+                name: "zp.s".to_string(),
+                text: "ptr = $10\n".to_string(),
+                included_at: Some((0, 2)),
+            },
+        ]
+    }
+
+    fn symbols() -> LabelTable {
+        let mut symbols = LabelTable::new();
+        symbols.insert(
+            "start".to_string(),
+            Label {
+                address: 0x1000,
+                file: 0,
+                line: 3,
+            },
+        );
+        symbols.insert(
+            "zp:ptr".to_string(),
+            Label {
+                address: 0x0010,
+                file: 1,
+                line: 1,
+            },
+        );
+        symbols.insert(
+            "loop".to_string(),
+            Label {
+                address: 0x1000,
+                file: 0,
+                line: 4,
+            },
+        );
+        symbols
+    }
+
+    #[test]
+    fn test_plain_map() {
+        let map = symbol_map(MapFormat::Plain, &symbols(), &files());
+        assert_eq!(
+            map,
+            "Symbols by name\n\n\
+             loop    $1000\n\
+             start   $1000\n\
+             zp:ptr  $0010\n\
+             \n\
+             Symbols by address\n\n\
+             $0010  zp:ptr\n\
+             $1000  loop\n\
+             $1000  start\n"
+        );
+    }
+
+    #[test]
+    fn test_vice_map() {
+        let map = symbol_map(MapFormat::Vice, &symbols(), &files());
+        assert_eq!(
+            map,
+            "al C:0010 .zp_ptr\nal C:1000 .loop\nal C:1000 .start\n"
+        );
+    }
+
+    #[test]
+    fn test_dbg_map() {
+        let map = symbol_map(MapFormat::Dbg, &symbols(), &files());
+        assert!(map.starts_with("version\tmajor=2,minor=0\n"));
+        assert!(map.contains("info\tcsym=0,file=2,"));
+        assert!(map.contains("file\tid=0,name=\"test.s\",size=4,"));
+        assert!(map.contains("file\tid=1,name=\"zp.s\",size=10,"));
+        assert!(map.contains("line\tid=1,file=0,line=3\n"));
+        assert!(map.contains("line\tid=2,file=1,line=1\n"));
+        assert!(map.contains(
+            "sym\tid=2,name=\"zp:ptr\",addrsize=zeropage,scope=0,def=2,val=0x10,type=lab\n"
+        ));
+    }
+}
@@ -0,0 +1,413 @@
+// Object files, and linking them into a final image. Objects aren't
+// relocated, their segments stay at the addresses from .org and only the
+// imported labels are filled in.
+use std::collections::HashMap;
+
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::image::MachineCode;
+use crate::image::Segment;
+use crate::pass1::Address;
+use crate::pass1::Program;
+use crate::pass2::Output;
+use crate::pass2::Relocation;
+use crate::pass2::RelocationKind;
+
+const OBJECT_MAGIC: &str = "kasm-object 1";
+const BYTES_PER_LINE: usize = 16;
+
+pub struct Symbol {
+    pub name: String,
+    pub address: Address,
+}
+
+pub struct Object {
+    pub segments: Vec<Segment>,
+    pub exports: Vec<Symbol>,
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+pub fn assemble_object(program: &Program, output: Output) -> Object {
+    // pass1 has already checked that every export is defined
+    let exports = program
+        .exports
+        .iter()
+        .map(|name| Symbol {
+            name: name.to_string(),
+            address: program.symbol_table[name].address,
+        })
+        .collect();
+
+    Object {
+        segments: output.image.segments(),
+        exports,
+        imports: program.imports.clone(),
+        relocations: output.relocations,
+    }
+}
+
+impl Object {
+    // Objects are plain text so they can be inspected and diffed
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", OBJECT_MAGIC);
+
+        for symbol in &self.exports {
+            text.push_str(&format!("export {} ${:04x}\n", symbol.name, symbol.address));
+        }
+        for name in &self.imports {
+            text.push_str(&format!("import {}\n", name));
+        }
+        for segment in &self.segments {
+            text.push_str(&format!("segment ${:04x}\n", segment.address));
+            for chunk in segment.data.chunks(BYTES_PER_LINE) {
+                let bytes: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+                text.push_str(&format!("data {}\n", bytes.join(" ")));
+            }
+        }
+        for relocation in &self.relocations {
+            let kind = match relocation.kind {
+                RelocationKind::Byte => "byte",
+                RelocationKind::Word => "word",
+                RelocationKind::Long => "long",
+            };
+            // Every hole was emitted along with the rest of its line
+            let (segment, offset) = self.locate(relocation).unwrap_or((0, 0));
+            text.push_str(&format!(
+                "reloc {} ${:04x} {} {}\n",
+                segment, offset, kind, relocation.symbol
+            ));
+        }
+
+        text
+    }
+
+    pub fn parse(text: &str) -> Result<Object, Error> {
+        let mut object = Object {
+            segments: Vec::new(),
+            exports: Vec::new(),
+            imports: Vec::new(),
+            relocations: Vec::new(),
+        };
+
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, magic)) if magic.trim() == OBJECT_MAGIC => {}
+            _ => return Err(malformed(1, "not a kasm object file")),
+        }
+
+        for (line_number, line) in lines {
+            let line_number = line_number + 1;
+            let parts: Vec<&str> = line.split_ascii_whitespace().collect();
+            match parts.as_slice() {
+                [] => {}
+                ["export", name, address] => object.exports.push(Symbol {
+                    name: name.to_string(),
+                    address: parse_hex(address, line_number)?,
+                }),
+                ["import", name] => object.imports.push(name.to_string()),
+                ["segment", address] => object.segments.push(Segment {
+                    address: parse_hex(address, line_number)?,
+                    data: MachineCode::new(),
+                }),
+                ["data", bytes @ ..] => {
+                    let segment = match object.segments.last_mut() {
+                        Some(segment) => segment,
+                        None => return Err(malformed(line_number, "data outside of a segment")),
+                    };
+                    for byte in bytes {
+                        match str_to_u8!(byte) {
+                            Ok(byte) => segment.data.push(byte),
+                            Err(_) => return Err(malformed(line_number, "bad data byte")),
+                        }
+                    }
+                }
+                ["reloc", segment, offset, kind, symbol] => {
+                    let segment = match segment.parse::<usize>() {
+                        Ok(segment) => segment,
+                        Err(_) => return Err(malformed(line_number, "bad segment number")),
+                    };
+                    let kind = match *kind {
+                        "byte" => RelocationKind::Byte,
+                        "word" => RelocationKind::Word,
+                        "long" => RelocationKind::Long,
+                        _ => return Err(malformed(line_number, "unknown relocation kind")),
+                    };
+                    let offset = parse_hex(offset, line_number)?;
+                    let address = match object.segments.get(segment) {
+                        Some(segment) => segment.address.wrapping_add(offset),
+                        None => return Err(malformed(line_number, "no such segment")),
+                    };
+                    object.relocations.push(Relocation {
+                        address,
+                        kind,
+                        symbol: symbol.to_string(),
+                    });
+                }
+                _ => return Err(malformed(line_number, "unknown record")),
+            }
+        }
+
+        // Make sure every relocation points at a hole we actually have
+        for relocation in &object.relocations {
+            if object.locate(relocation).is_none() {
+                return Err(error(
+                    ErrorCode::MalformedObject,
+                    format!(
+                        "Relocation for {} is outside its segment",
+                        relocation.symbol
+                    ),
+                ));
+            }
+            if !object.imports.contains(&relocation.symbol) {
+                return Err(error(
+                    ErrorCode::MalformedObject,
+                    format!("Relocation for {} which is not imported", relocation.symbol),
+                ));
+            }
+        }
+
+        Ok(object)
+    }
+
+    // Find the segment holding a relocation, and how far into it the hole is
+    fn locate(&self, relocation: &Relocation) -> Option<(usize, usize)> {
+        let size = match relocation.kind {
+            RelocationKind::Byte => 1,
+            RelocationKind::Word => 2,
+            RelocationKind::Long => 3,
+        };
+        self.segments
+            .iter()
+            .enumerate()
+            .find_map(|(index, segment)| {
+                let offset = relocation.address.checked_sub(segment.address)? as usize;
+                if offset + size <= segment.data.len() {
+                    Some((index, offset))
+                } else {
+                    None
+                }
+            })
+    }
+}
+
+fn malformed(line_number: usize, reason: &str) -> Error {
+    error(
+        ErrorCode::MalformedObject,
+        format!("Malformed object file at line {}: {}", line_number, reason),
+    )
+}
+
+fn parse_hex(value: &str, line_number: usize) -> Result<Address, Error> {
+    match str_to_u32!(value.trim_start_matches('$')) {
+        Ok(value) => Ok(value),
+        Err(_) => Err(malformed(line_number, "expected a hex address")),
+    }
+}
+
+// Combine named objects into the segments of a single image, resolving
+// every import against the exports of all of the objects
+pub fn link(objects: Vec<(String, Object)>) -> Result<Vec<Segment>, Error> {
+    let mut symbols: HashMap<String, (Address, String)> = HashMap::new();
+
+    for (name, object) in &objects {
+        for symbol in &object.exports {
+            if let Some((_, other)) = symbols.get(&symbol.name) {
+                return Err(error(
+                    ErrorCode::DuplicateSymbol,
+                    format!(
+                        "Duplicate symbol {} exported by {} and {}",
+                        symbol.name, other, name
+                    ),
+                ));
+            }
+            symbols.insert(symbol.name.to_string(), (symbol.address, name.to_string()));
+        }
+    }
+
+    let mut missing = Vec::new();
+    for (name, object) in &objects {
+        for import in &object.imports {
+            if !symbols.contains_key(import) {
+                missing.push(format!("{} (imported by {})", import, name));
+            }
+        }
+    }
+    if !missing.is_empty() {
+        return Err(error(
+            ErrorCode::MissingSymbol,
+            format!("Missing symbols: {}", missing.join(", ")),
+        ));
+    }
+
+    // Patch every hole with its now known address
+    let mut segments = Vec::new();
+    for (name, mut object) in objects {
+        for relocation in &object.relocations {
+            let (address, _) = symbols[&relocation.symbol];
+            let bytes = address.to_le_bytes();
+            let (segment, offset) = match object.locate(relocation) {
+                Some(location) => location,
+                None => {
+                    return Err(error(
+                        ErrorCode::MalformedObject,
+                        format!(
+                            "{}: relocation for {} is outside its segment",
+                            name, relocation.symbol
+                        ),
+                    ))
+                }
+            };
+            let data = &mut object.segments[segment].data;
+            match relocation.kind {
+                RelocationKind::Byte => data[offset] = bytes[0],
+                RelocationKind::Word => data[offset..offset + 2].copy_from_slice(&bytes[..2]),
+                RelocationKind::Long => data[offset..offset + 3].copy_from_slice(&bytes[..3]),
+            }
+        }
+        for segment in object.segments {
+            segments.push((name.to_string(), segment));
+        }
+    }
+
+    // Objects can't be allowed to land on top of each other, and they
+    // aren't moved out of the way, so their .orgs have to keep them apart
+    segments.sort_by_key(|(_, segment)| segment.address);
+    for pair in segments.windows(2) {
+        let (name, segment) = &pair[0];
+        let (other_name, other) = &pair[1];
+        if segment.address as usize + segment.data.len() > other.address as usize {
+            return Err(error(
+                ErrorCode::Overlap,
+                format!(
+                    "{} overlaps {} at ${:04x}, objects stay at their .org addresses",
+                    other_name, name, other.address
+                ),
+            ));
+        }
+    }
+
+    Ok(segments.into_iter().map(|(_, segment)| segment).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::link;
+    use super::Object;
+    use super::Symbol;
+    use crate::image::Segment;
+    use crate::pass2::Relocation;
+    use crate::pass2::RelocationKind;
+
+    fn caller() -> Object {
+        Object {
+            segments: vec![Segment {
+                address: 0x0002,
+                data: vec![0x20, 0x00, 0x00, 0xa9, 0x00],
+            }],
+            exports: Vec::new(),
+            imports: vec!["putc".to_string(), "zp:char".to_string()],
+            relocations: vec![
+                Relocation {
+                    address: 0x0003,
+                    kind: RelocationKind::Word,
+                    symbol: "putc".to_string(),
+                },
+                Relocation {
+                    address: 0x0006,
+                    kind: RelocationKind::Byte,
+                    symbol: "zp:char".to_string(),
+                },
+            ],
+        }
+    }
+
+    fn callee() -> Object {
+        Object {
+            segments: vec![Segment {
+                address: 0x0000,
+                data: vec![0x60],
+            }],
+            exports: vec![
+                Symbol {
+                    name: "putc".to_string(),
+                    address: 0x0000,
+                },
+                Symbol {
+                    name: "zp:char".to_string(),
+                    address: 0x00fe,
+                },
+            ],
+            imports: Vec::new(),
+            relocations: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let text = caller().to_text();
+        let object = match Object::parse(&text) {
+            Ok(object) => object,
+            Err(err) => panic!("Did not expect parsing to fail: {}", err.1),
+        };
+        assert_eq!(object.to_text(), text);
+        assert_eq!(object.segments[0].data, vec![0x20, 0x00, 0x00, 0xa9, 0x00]);
+        assert_eq!(object.relocations[1].kind, RelocationKind::Byte);
+    }
+
+    #[test]
+    fn test_parse_bad_relocation() {
+        let text = "kasm-object 1\nimport putc\nsegment $1000\ndata 20\nreloc 0 $0001 word putc\n";
+        if Object::parse(text).is_ok() {
+            panic!("Did not expect a relocation outside its segment to parse");
+        }
+    }
+
+    #[test]
+    fn test_link() {
+        let objects = vec![
+            ("caller.o".to_string(), caller()),
+            ("callee.o".to_string(), callee()),
+        ];
+        let segments = match link(objects) {
+            Ok(segments) => segments,
+            Err(err) => panic!("Did not expect linking to fail: {}", err.1),
+        };
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].address, 0x0000);
+        assert_eq!(segments[0].data, vec![0x60]);
+        assert_eq!(segments[1].address, 0x0002);
+        assert_eq!(segments[1].data, vec![0x20, 0x00, 0x00, 0xa9, 0xfe]);
+    }
+
+    #[test]
+    fn test_link_overlap() {
+        let mut other = callee();
+        other.exports.clear();
+        other.segments[0].address = 0x0004;
+        let objects = vec![
+            ("caller.o".to_string(), caller()),
+            ("callee.o".to_string(), callee()),
+            ("other.o".to_string(), other),
+        ];
+        if link(objects).is_ok() {
+            panic!("Did not expect linking overlapping objects to work");
+        }
+    }
+
+    #[test]
+    fn test_link_missing_symbol() {
+        if link(vec![("caller.o".to_string(), caller())]).is_ok() {
+            panic!("Did not expect linking with a missing symbol to work");
+        }
+    }
+
+    #[test]
+    fn test_link_duplicate_symbol() {
+        let objects = vec![("a.o".to_string(), callee()), ("b.o".to_string(), callee())];
+        if link(objects).is_ok() {
+            panic!("Did not expect linking a duplicate symbol to work");
+        }
+    }
+}
@@ -2,31 +2,56 @@ use std::cmp::Ordering;
 use std::collections::HashMap;
 
 use crate::ascii::ascii_to_bytes;
+use crate::checksum::checksum_width;
+use crate::checksum::str_to_checksum_kind;
+use crate::checksum::Checksum;
+use crate::checksum::ChecksumKind;
 use crate::errors::error;
 use crate::errors::Error;
 use crate::errors::ErrorCode;
+use crate::errors::LocatedError;
+use crate::expression::evaluate;
+use crate::expression::Number;
 use crate::instructions::address_mode_length;
+use crate::instructions::available;
+use crate::instructions::cpu_to_str;
+use crate::instructions::instruction_cycles;
+use crate::instructions::is_bit_branch;
+use crate::instructions::is_branch;
+use crate::instructions::minimum_cpu;
+use crate::instructions::str_to_cpu;
 use crate::instructions::str_to_mnemonic;
 use crate::instructions::AddressMode;
+use crate::instructions::Cpu;
+use crate::instructions::InstructionKey;
 use crate::instructions::Mnemonic;
 use crate::instructions::Value;
+use crate::scanner::FileId;
+use crate::scanner::Line;
 use crate::scanner::SourceTable;
+use crate::sources::SourceFile;
 
 use regex::Regex;
 
-type LabelTable = HashMap<String, Label>;
-type Address = u16;
+pub type LabelTable = HashMap<String, Label>;
+pub type Address = u32; // 24 bits are used by the 65816, 16 by everything else
+pub const VECTORS: Address = 0xfffa; // NMI, then reset, then IRQ
+type Counter = u32; // Wide enough to sit just past the top of memory after the last byte
 type Data = Vec<u8>;
 
 // Using a struct here because we expect to extend it beyond addresses
 pub struct Label {
     pub address: Address,
+    pub file: FileId,
+    pub line: Line, // Where the label was defined
 }
 
 #[derive(Eq, PartialEq)]
 pub enum Content {
     Code(Code),
     Data(Data),
+    Word(Value),        // A little endian address, possibly of a label
+    Checksum(Checksum), // Filled in once the rest of the image is known
 }
 
 #[derive(Eq, PartialEq)]
@@ -34,12 +59,16 @@ pub struct Code {
     pub mnemonic: Mnemonic,
     pub address_mode: AddressMode,
     pub value: Value,
+    pub wide: bool,   // A 16 bit immediate for one of the 65816's 16 bit registers
+    pub zeropage: u8, // The zeropage address bbr and bbs test, the value is the target
 }
 
 #[derive(Eq)]
 pub struct CodeTableEntry {
     pub address: Address,
     pub content: Content,
+    pub file: FileId,
+    pub line: Line, // The source line this came from
 }
 
 impl Ord for CodeTableEntry {
@@ -62,10 +91,84 @@ impl PartialEq for CodeTableEntry {
 
 pub type CodeTable = Vec<CodeTableEntry>;
 
+// Directives that control how the listing file is laid out
+pub enum ListingControl {
+    On,
+    Off,
+    Title(String),
+    Page,
+}
+
+// Directives that wait until every label is known
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum CheckKind {
+    Assert,  // The first argument must be true, the rest are the message
+    Error,   // Always fails
+    Warning, // Carries on regardless
+    Out,     // Just prints its arguments
+}
+
+struct Check {
+    kind: CheckKind,
+    arguments: Vec<String>, // Quoted strings or expressions
+    here: Counter,          // The address for *
+    file: FileId,
+    line: Line,
+}
+
+// What a .test block does, in order
+pub enum TestStep {
+    Given(Vec<(String, String)>), // Registers, flags or addresses and their values
+    Call(String),                 // The routine to run until it returns
+    Expect(Vec<(String, String)>), // The same as .given, but checked
+}
+
+// A .test block, run by kasm test and never placed in the image
+pub struct Test {
+    pub name: String,
+    pub file: FileId,
+    pub line: Line,
+    pub steps: Vec<(Line, TestStep)>,
+}
+
+// The fastest and slowest a .cycles block can run
+pub struct CycleBlock {
+    pub file: FileId, // Where the .endcycles is
+    pub start: Line,  // The .cycles line
+    pub end: Line,    // The .endcycles line
+    pub min: u32,
+    pub max: u32,
+}
+
+// An open .cycles block
+struct Budget {
+    file: FileId,
+    line: Line,
+    min: u32,
+    max: u32,
+    first: usize, // The first entry in the code table it covers
+}
+
 pub struct Program {
     pub symbol_table: LabelTable,
     pub code: CodeTable,
-    counter: Address, // The current address as we go through pass1
+    pub imports: Vec<String>, // Labels expected to be resolved by the linker
+    pub exports: Vec<String>, // Labels made visible to the linker
+    pub listing: Vec<(FileId, Line, ListingControl)>,
+    pub fill: Option<u8>, // The byte used for padding and gaps
+    pub cpu: Cpu,         // Which instructions are available
+    pub tests: Vec<Test>,
+    pub cycle_blocks: Vec<CycleBlock>,
+    pub messages: Vec<String>, // From .warning and .out, for whoever's assembling to show
+    pub files: Vec<SourceFile>, // Where the lines came from, once the includes are read
+    checks: Vec<Check>,        // .assert and friends, in source order
+    budget: Option<Budget>,
+    testing: bool,    // Inside a .test block
+    wide_a: bool,     // The 65816's accumulator and memory are 16 bits, from rep or .a16
+    wide_xy: bool,    // Its index registers are 16 bits, from rep or .i16
+    counter: Counter, // The current address as we go through pass1
+    file: FileId,     // The file it's in
+    line: Line,       // The current source line as we go through pass1
 }
 
 impl Program {
@@ -73,7 +176,31 @@ impl Program {
         Program {
             symbol_table: LabelTable::new(),
             code: CodeTable::new(),
+            imports: Vec::new(),
+            exports: Vec::new(),
+            listing: Vec::new(),
+            fill: None,
+            cpu: Cpu::W65C02,
+            tests: Vec::new(),
+            cycle_blocks: Vec::new(),
+            messages: Vec::new(),
+            files: Vec::new(),
+            checks: Vec::new(),
+            budget: None,
+            testing: false,
+            wide_a: false,
+            wide_xy: false,
             counter: 0x1000, // Worry about zeropage a little later
+            file: 0,
+            line: 0,
+        }
+    }
+
+    // " of io.s" for a line from an included file, to add to messages
+    pub fn of_file(&self, file: FileId) -> String {
+        match self.files.get(file) {
+            Some(source) if file > 0 => format!(" of {}", source.name),
+            _ => String::new(),
         }
     }
 }
@@ -85,62 +212,165 @@ impl Default for Program {
 }
 
 pub fn pass1(source: SourceTable) -> Result<Program, Error> {
+    pass1_for_cpu(source, Cpu::W65C02)
+}
+
+// The CPU can be changed along the way with .cpu
+pub fn pass1_for_cpu(source: SourceTable, cpu: Cpu) -> Result<Program, Error> {
+    pass1_with_line(source, cpu).map_err(|(err, _)| err)
+}
+
+// The same, but also saying which line an error was found on
+pub fn pass1_with_line(source: SourceTable, cpu: Cpu) -> Result<Program, LocatedError> {
     let mut program = Program::new();
+    program.cpu = cpu;
 
     // TODO:
     // - Handle the zeropage! Labels are funky here.
     // zeropage is addresses $00 through to $ff
 
     for line in source {
+        program.file = line.file;
+        program.line = line.line_number;
         let first_char = match line.line.chars().next() {
             Some(first_char) => first_char,
             None => panic!("Got a line of source with not characters!"),
         };
-        if line.line.ends_with(':') {
-            match handle_label(&mut program, &line.line) {
-                Ok(()) => (),
-                Err(err) => return Err(err),
-            }
+        let handled = if program.testing {
+            handle_test_line(&mut program, &line.line)
+        } else if line.line.ends_with(':') {
+            handle_label(&mut program, &line.line)
         } else if first_char == '.' {
-            match handle_directive(&mut program, &line.line) {
-                Ok(()) => (),
-                Err(err) => return Err(err),
-            }
+            handle_directive(&mut program, &line.line)
         } else if first_char.is_ascii_alphabetic() {
-            match handle_instruction(&mut program, &line.line) {
-                Ok(()) => (),
-                Err(err) => return Err(err),
-            }
+            handle_instruction(&mut program, &line.line)
         } else {
-            return Err(error(
+            Err(error(
                 ErrorCode::UnknownSyntax,
                 format!(
                     "Unknown syntax: {} at line: {}",
                     line.line, line.line_number
                 ),
+            ))
+        };
+        if let Err(err) = handled {
+            return Err((err, Some((line.file, line.line_number))));
+        }
+    }
+
+    if let Some(test) = program.tests.last().filter(|_| program.testing) {
+        return Err((
+            error(
+                ErrorCode::MalformedTest,
+                format!("Test at line {} is missing .endtest", test.line),
+            ),
+            Some((test.file, test.line)),
+        ));
+    }
+
+    if let Some(budget) = &program.budget {
+        return Err((
+            error(
+                ErrorCode::CycleBudget,
+                format!(".cycles at line {} is missing .endcycles", budget.line),
+            ),
+            Some((budget.file, budget.line)),
+        ));
+    }
+
+    for label in &program.exports {
+        if !program.symbol_table.contains_key(label) {
+            return Err((
+                error(
+                    ErrorCode::UndefinedExport,
+                    format!("Exported label is not defined: {}", label),
+                ),
+                None,
             ));
         }
     }
 
+    let mut messages = Vec::new();
+    for check in &program.checks {
+        let located = |err| (err, Some((check.file, check.line)));
+        if let Some(message) = run_check(&program, check).map_err(located)? {
+            messages.push(message);
+        }
+    }
+    program.messages = messages;
+
     program.code.sort();
     Ok(program)
 }
 
+// An .assert, .error, .warning or .out, now every label is known. Anything
+// .warning or .out has to say is handed back rather than printed.
+fn run_check(program: &Program, check: &Check) -> Result<Option<String>, Error> {
+    let lookup = |label: &str| {
+        program
+            .symbol_table
+            .get(label)
+            .map(|label| label.address as Number)
+    };
+
+    let here = check.here as Number;
+    let arguments = match check.kind {
+        CheckKind::Assert => &check.arguments[1..],
+        _ => &check.arguments[..],
+    };
+    let mut message = String::new();
+    for argument in arguments {
+        match argument.strip_prefix('"') {
+            Some(text) => message.push_str(text.trim_end_matches('"')),
+            None => message.push_str(&evaluate(argument, here, &lookup)?.to_string()),
+        }
+    }
+
+    match check.kind {
+        CheckKind::Assert => {
+            if evaluate(&check.arguments[0], here, &lookup)? == 0 {
+                if message.is_empty() {
+                    message = check.arguments[0].clone();
+                }
+                return Err(error(
+                    ErrorCode::AssertionFailed,
+                    format!("Assertion failed at line {}: {}", check.line, message),
+                ));
+            }
+        }
+        CheckKind::Error => {
+            return Err(error(
+                ErrorCode::UserError,
+                format!("Error at line {}: {}", check.line, message),
+            ))
+        }
+        CheckKind::Warning => {
+            return Ok(Some(format!("Warning! Line {}: {}", check.line, message)))
+        }
+        CheckKind::Out => return Ok(Some(message)),
+    }
+
+    Ok(None)
+}
+
 // TODO: finish implement labels!
 fn handle_label(program: &mut Program, raw_label: &str) -> Result<(), Error> {
     let label = String::from(raw_label.trim_end_matches(':'));
 
-    if program.symbol_table.contains_key(&label) {
+    if program.symbol_table.contains_key(&label) || program.imports.contains(&label) {
         return Err(error(
             ErrorCode::DuplicateLabel,
             format!("Duplicate label found: {}", label),
         ));
     }
 
+    let address = reserve(program, 0)?;
     program.symbol_table.insert(
         label,
         Label {
-            address: program.counter,
+            address,
+            file: program.file,
+            line: program.line,
         },
     );
 
@@ -153,12 +383,23 @@ fn handle_directive(program: &mut Program, raw_line: &str) -> Result<(), Error>
 
     let split: Vec<&str> = trimmed.splitn(2, ' ').collect(); // Get two parts, the directive and data
     let dir = split[0];
-    let value = String::from(split[1]);
+    let value = String::from(split.get(1).copied().unwrap_or(""));
     match dir {
         "org" => {
             let value = value.trim_start_matches('$');
-            let address = match str_to_u16!(value) {
-                Ok(address) => address,
+            let address = match str_to_u32!(value) {
+                Ok(address) if address < address_limit(program.cpu) => address,
+                Ok(_) => {
+                    return Err(error(
+                        ErrorCode::AddressExpected,
+                        format!(
+                            "${} is outside the {}'s memory at line {}",
+                            value,
+                            cpu_to_str(program.cpu),
+                            program.line
+                        ),
+                    ))
+                }
                 Err(_) => {
                     return Err(error(
                         ErrorCode::AddressExpected,
@@ -166,34 +407,72 @@ fn handle_directive(program: &mut Program, raw_line: &str) -> Result<(), Error>
                     ))
                 }
             };
-            program.counter = address;
+            program.counter = address as Counter;
         }
         "byte" => {
-            let data = match parse_bytes(&value) {
-                Ok(data) => data,
-                Err(err) => return Err(err),
-            };
-            program.counter += data.len() as u16;
+            let data = parse_bytes(&value)?;
+            let address = reserve(program, data.len() as Counter)?;
             program.code.push(CodeTableEntry {
-                address: program.counter,
+                address,
                 content: Content::Data(data),
+                file: program.file,
+                line: program.line,
             });
         }
+        "cpu" => match str_to_cpu(value.trim()) {
+            Some(cpu) => program.cpu = cpu,
+            None => {
+                return Err(error(
+                    ErrorCode::UnknownDirective,
+                    format!(
+                        "Expected .cpu 6502, 6502x, 65c02, r65c02, w65c02 or 65816, found {}",
+                        raw_line
+                    ),
+                ))
+            }
+        },
+        "a8" | "a16" | "i8" | "i16" => {
+            if program.cpu != Cpu::W65816 {
+                return Err(error(
+                    ErrorCode::UnsupportedInstruction,
+                    format!(
+                        "Only the 65816 has 16 bit registers, found {} at line {}",
+                        raw_line, program.line
+                    ),
+                ));
+            }
+            match dir {
+                "a8" => program.wide_a = false,
+                "a16" => program.wide_a = true,
+                "i8" => program.wide_xy = false,
+                _ => program.wide_xy = true,
+            }
+        }
+        "fillbyte" => {
+            let raw_value = value.trim().trim_start_matches('$');
+            match str_to_u8!(raw_value) {
+                Ok(fill) => program.fill = Some(fill),
+                Err(_) => {
+                    return Err(error(
+                        ErrorCode::HexExpected,
+                        format!("Expected hexadecimal but found {}", raw_value),
+                    ))
+                }
+            };
+        }
         "ascii" => {
             let trimmed = String::from(value.trim_start_matches('\"').trim_end_matches('\"'));
             let (data, size) = ascii_to_bytes(&trimmed);
+            let address = reserve(program, size as Counter)?;
             program.code.push(CodeTableEntry {
-                address: program.counter,
+                address,
                 content: Content::Data(data),
+                file: program.file,
+                line: program.line,
             });
-            program.counter += size;
         }
         "equ" => {
-            let (label, value) = match parse_equ(&value) {
-                Ok((label, value)) => (label, value),
-                Err(err) => return Err(err),
-            };
-            println!("Found an EQU: {} {:04x}", label, value);
+            let (label, value) = parse_equ(&value)?;
             if program.symbol_table.contains_key(&label) {
                 return Err(error(
                     ErrorCode::DuplicateLabel,
@@ -201,7 +480,185 @@ fn handle_directive(program: &mut Program, raw_line: &str) -> Result<(), Error>
                 ));
             }
 
-            program.symbol_table.insert(label, Label { address: value });
+            program.symbol_table.insert(
+                label,
+                Label {
+                    address: value,
+                    file: program.file,
+                    line: program.line,
+                },
+            );
+        }
+        "import" => {
+            for label in parse_labels(&value) {
+                if program.symbol_table.contains_key(&label) || program.imports.contains(&label) {
+                    return Err(error(
+                        ErrorCode::DuplicateLabel,
+                        format!("Duplicate label found: {}", label),
+                    ));
+                }
+                program.imports.push(label);
+            }
+        }
+        "list" => {
+            let control = match value.trim() {
+                "on" => ListingControl::On,
+                "off" => ListingControl::Off,
+                _ => {
+                    return Err(error(
+                        ErrorCode::UnknownDirective,
+                        format!("Expected .list on or .list off, found {}", raw_line),
+                    ))
+                }
+            };
+            program.listing.push((program.file, program.line, control));
+        }
+        "title" => {
+            let title = value.trim().trim_start_matches('"').trim_end_matches('"');
+            program.listing.push((
+                program.file,
+                program.line,
+                ListingControl::Title(title.to_string()),
+            ));
+        }
+        "page" => program
+            .listing
+            .push((program.file, program.line, ListingControl::Page)),
+        "checksum" => {
+            let checksum = parse_checksum(&value, program)?;
+            let address = reserve(program, checksum_width(checksum.kind) as Counter)?;
+            program.code.push(CodeTableEntry {
+                address,
+                content: Content::Checksum(checksum),
+                file: program.file,
+                line: program.line,
+            });
+        }
+        "vectors" => {
+            // Each vector sits in its own slot, so any may be left out
+            let mut vectors: [Option<Value>; 3] = [None, None, None];
+            for vector in value.split_terminator(',') {
+                let (name, target) = match vector.split_once('=') {
+                    Some((name, target)) => (name.trim(), target.trim()),
+                    None => {
+                        return Err(error(
+                            ErrorCode::MalformedVectors,
+                            format!("Expected name=label but found {}", vector.trim()),
+                        ))
+                    }
+                };
+                let slot = match name {
+                    "nmi" => 0,
+                    "reset" => 1,
+                    "irq" => 2,
+                    _ => {
+                        return Err(error(
+                            ErrorCode::MalformedVectors,
+                            format!("Unknown vector: {}", name),
+                        ))
+                    }
+                };
+                if vectors[slot].is_some() {
+                    return Err(error(
+                        ErrorCode::MalformedVectors,
+                        format!("Duplicate vector: {}", name),
+                    ));
+                }
+                vectors[slot] = Some(parse_word(target)?);
+            }
+            for (slot, vector) in vectors.iter_mut().enumerate() {
+                if let Some(value) = vector.take() {
+                    program.code.push(CodeTableEntry {
+                        address: VECTORS + 2 * slot as Address,
+                        content: Content::Word(value),
+                        file: program.file,
+                        line: program.line,
+                    });
+                }
+            }
+        }
+        "assert" | "error" | "warning" | "out" => {
+            let kind = match dir {
+                "assert" => CheckKind::Assert,
+                "error" => CheckKind::Error,
+                "warning" => CheckKind::Warning,
+                _ => CheckKind::Out,
+            };
+            let arguments = parse_arguments(&value);
+            if kind == CheckKind::Assert && arguments.is_empty() {
+                return Err(error(
+                    ErrorCode::MalformedExpression,
+                    format!("Expected an expression to check at line {}", program.line),
+                ));
+            }
+            program.checks.push(Check {
+                kind,
+                arguments,
+                here: program.counter,
+                file: program.file,
+                line: program.line,
+            });
+        }
+        "cycles" => {
+            if let Some(budget) = &program.budget {
+                return Err(error(
+                    ErrorCode::CycleBudget,
+                    format!(".cycles at line {} is still open", budget.line),
+                ));
+            }
+            let (min, max) = parse_budget(&value)?;
+            program.budget = Some(Budget {
+                file: program.file,
+                line: program.line,
+                min,
+                max,
+                first: program.code.len(),
+            });
+        }
+        "endcycles" => {
+            let budget = match program.budget.take() {
+                Some(budget) => budget,
+                None => {
+                    return Err(error(
+                        ErrorCode::CycleBudget,
+                        format!(".endcycles without .cycles at line {}", program.line),
+                    ))
+                }
+            };
+            let block = count_cycles(program, &budget);
+            if block.min < budget.min || block.max > budget.max {
+                return Err(error(
+                    ErrorCode::CycleBudget,
+                    format!(
+                        "Lines {}-{} take {} to {} cycles, outside the budget of {} to {}",
+                        block.start, block.end, block.min, block.max, budget.min, budget.max
+                    ),
+                ));
+            }
+            program.cycle_blocks.push(block);
+        }
+        "test" => {
+            let name = value.trim().trim_start_matches('"').trim_end_matches('"');
+            if name.is_empty() {
+                return Err(error(
+                    ErrorCode::MalformedTest,
+                    format!("Expected a name for the test at line {}", program.line),
+                ));
+            }
+            program.tests.push(Test {
+                name: name.to_string(),
+                file: program.file,
+                line: program.line,
+                steps: Vec::new(),
+            });
+            program.testing = true;
+        }
+        "export" => {
+            for label in parse_labels(&value) {
+                if !program.exports.contains(&label) {
+                    program.exports.push(label);
+                }
+            }
         }
         _ => {
             return Err(error(
@@ -234,7 +691,216 @@ fn parse_bytes(bytes: &str) -> Result<Data, Error> {
     Ok(data)
 }
 
-fn parse_equ(equ: &str) -> Result<(String, u16), Error> {
+fn parse_labels(labels: &str) -> Vec<String> {
+    labels
+        .split_terminator(',')
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty())
+        .collect()
+}
+
+// Decimal, since that's how cycles are counted
+fn parse_budget(budget: &str) -> Result<(u32, u32), Error> {
+    let parts: Vec<Option<u32>> = budget
+        .split(',')
+        .map(|part| part.trim().parse::<u32>().ok())
+        .collect();
+    match parts[..] {
+        [Some(min), Some(max)] if min <= max => Ok((min, max)),
+        _ => Err(error(
+            ErrorCode::CycleBudget,
+            format!("Expected .cycles min, max but found {}", budget),
+        )),
+    }
+}
+
+// Add up the instructions since the .cycles, as if they run straight through
+fn count_cycles(program: &Program, budget: &Budget) -> CycleBlock {
+    let mut block = CycleBlock {
+        file: program.file,
+        start: budget.line,
+        end: program.line,
+        min: 0,
+        max: 0,
+    };
+    for entry in &program.code[budget.first..] {
+        if let Content::Code(code) = &entry.content {
+            let cycles = instruction_cycles(InstructionKey {
+                mnemonic: code.mnemonic,
+                address_mode: code.address_mode,
+            });
+            block.min += cycles.min();
+            block.max += cycles.max();
+        }
+    }
+    block
+}
+
+// Inside a .test block only the test directives make sense
+fn handle_test_line(program: &mut Program, line: &str) -> Result<(), Error> {
+    let trimmed = line.trim_start_matches('.');
+    let (dir, value) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+    let step = match dir {
+        _ if !line.starts_with('.') => None,
+        "endtest" => {
+            program.testing = false;
+            return Ok(());
+        }
+        "given" => parse_assignments(value).map(TestStep::Given),
+        "expect" => parse_assignments(value).map(TestStep::Expect),
+        "call" if !value.trim().is_empty() => Some(TestStep::Call(value.trim().to_string())),
+        _ => None,
+    };
+
+    match (step, program.tests.last_mut()) {
+        (Some(step), Some(test)) => {
+            test.steps.push((program.line, step));
+            Ok(())
+        }
+        _ => Err(error(
+            ErrorCode::MalformedTest,
+            format!(
+                "Expected .given, .call, .expect or .endtest but found {} at line {}",
+                line, program.line
+            ),
+        )),
+    }
+}
+
+// name=value pairs, the values are checked when the test runs
+fn parse_assignments(assignments: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for assignment in parse_arguments(assignments) {
+        let (name, value) = assignment.split_once('=')?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || value.is_empty() {
+            return None;
+        }
+        pairs.push((name.to_string(), value.to_string()));
+    }
+    if pairs.is_empty() {
+        return None;
+    }
+    Some(pairs)
+}
+
+// Split on the commas that aren't inside quotes
+fn parse_arguments(arguments: &str) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+    for ch in arguments.chars() {
+        match ch {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                parts.push(part.trim().to_string());
+                part.clear();
+                continue;
+            }
+            _ => {}
+        }
+        part.push(ch);
+    }
+    parts.push(part.trim().to_string());
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+// The kind of checksum, the range it covers, and optionally adjust
+fn parse_checksum(checksum: &str, program: &Program) -> Result<Checksum, Error> {
+    let parts: Vec<&str> = checksum.split(',').map(|part| part.trim()).collect();
+    if parts.len() < 3 || parts.len() > 4 {
+        return Err(error(
+            ErrorCode::MalformedChecksum,
+            format!("Expected kind, start, end but found {}", checksum),
+        ));
+    }
+
+    let kind = match str_to_checksum_kind(parts[0]) {
+        Some(kind) => kind,
+        None => {
+            return Err(error(
+                ErrorCode::MalformedChecksum,
+                format!("Unknown checksum: {}", parts[0]),
+            ))
+        }
+    };
+
+    let mut range = Vec::new();
+    for part in &parts[1..3] {
+        let raw_value = part.trim_start_matches('$');
+        match str_to_u32!(raw_value) {
+            Ok(address) if address < address_limit(program.cpu) => range.push(address),
+            Ok(_) => {
+                return Err(error(
+                    ErrorCode::AddressExpected,
+                    format!(
+                        "Checksum range {} is outside the {}'s memory at line {}",
+                        part,
+                        cpu_to_str(program.cpu),
+                        program.line
+                    ),
+                ))
+            }
+            Err(_) => {
+                return Err(error(
+                    ErrorCode::AddressExpected,
+                    format!("Expected address for checksum, found {}", part),
+                ))
+            }
+        }
+    }
+    if range[1] < range[0] {
+        return Err(error(
+            ErrorCode::MalformedChecksum,
+            format!(
+                "Checksum ends before it starts: {} at line {}",
+                checksum, program.line
+            ),
+        ));
+    }
+
+    let adjust = match parts.get(3) {
+        None => false,
+        Some(&"adjust") if kind == ChecksumKind::Sum8 || kind == ChecksumKind::Xor8 => true,
+        Some(option) => {
+            return Err(error(
+                ErrorCode::MalformedChecksum,
+                format!("Can't {} a {} checksum", option, parts[0]),
+            ))
+        }
+    };
+
+    Ok(Checksum {
+        kind,
+        start: range[0],
+        end: range[1],
+        adjust,
+    })
+}
+
+// An address, or a label to be resolved in pass2
+fn parse_word(word: &str) -> Result<Value, Error> {
+    if let Some(raw_value) = word.strip_prefix('$') {
+        return match str_to_u16!(raw_value) {
+            Ok(value) => Ok(Value::U16(value)),
+            Err(_) => Err(error(
+                ErrorCode::HexExpected,
+                format!("Expected hex but found {}", raw_value),
+            )),
+        };
+    }
+    let re = Regex::new(r"^[a-z_][0-9a-z_]*$").unwrap();
+    if !re.is_match(word) {
+        return Err(error(
+            ErrorCode::AddressExpected,
+            format!("Expected an address or label but found {}", word),
+        ));
+    }
+    Ok(Value::String(word.to_string()))
+}
+
+fn parse_equ(equ: &str) -> Result<(String, Address), Error> {
     // TODO:
     // - handle multiple kinds of values
     let mut parts = equ.split('=');
@@ -258,9 +924,9 @@ fn parse_equ(equ: &str) -> Result<(String, u16), Error> {
         }
     };
 
-    let value = match str_to_u16!(raw_value) {
-        Ok(value) => value,
-        Err(_) => {
+    let value = match str_to_u32!(raw_value) {
+        Ok(value) if value <= 0xffffff => value,
+        _ => {
             return Err(error(
                 ErrorCode::HexExpected,
                 format!("Expected hex but found {}", raw_value),
@@ -270,6 +936,22 @@ fn parse_equ(equ: &str) -> Result<(String, u16), Error> {
     Ok((label, value))
 }
 
+// Split a bbr or bbs operand into its zeropage address and branch target
+fn bit_branch_operand<'a>(program: &Program, operand: &'a str) -> Result<(u8, &'a str), Error> {
+    if let Some((zeropage, target)) = operand.split_once(',') {
+        if let Ok((AddressMode::Zeropage, Value::U8(zeropage))) = get_operand_type(zeropage) {
+            return Ok((zeropage, target));
+        }
+    }
+    Err(error(
+        ErrorCode::UnknownSyntax,
+        format!(
+            "Expected a zeropage address and branch target at line {}",
+            program.line
+        ),
+    ))
+}
+
 fn handle_instruction(program: &mut Program, line: &str) -> Result<(), Error> {
     let mut parts = line.split_ascii_whitespace();
 
@@ -278,12 +960,24 @@ fn handle_instruction(program: &mut Program, line: &str) -> Result<(), Error> {
         None => panic!("Did not receive an instruction!"),
     };
 
-    let value: Value;
-    let address_mode: AddressMode;
+    let mnemonic = str_to_mnemonic(&instruction)?;
+    let bit_branch = is_bit_branch(mnemonic);
+
+    let mut value: Value;
+    let mut address_mode: AddressMode;
+    let mut zeropage = 0;
 
     match parts.next() {
+        // bbr and bbs are written with the zeropage address first, e.g. bbr0 $12,loop
+        Some(operand_part) if bit_branch => {
+            let (zeropage_tmp, target) = bit_branch_operand(program, operand_part)?;
+            let (address_mode_tmp, value_tmp) = get_operand_type(target)?;
+            zeropage = zeropage_tmp;
+            address_mode = address_mode_tmp;
+            value = value_tmp;
+        }
         Some(operand_part) => {
-            let (address_mode_tmp, value_tmp) = get_operand_type(operand_part);
+            let (address_mode_tmp, value_tmp) = get_operand_type(operand_part)?;
             address_mode = address_mode_tmp;
             value = value_tmp;
         }
@@ -293,219 +987,471 @@ fn handle_instruction(program: &mut Program, line: &str) -> Result<(), Error> {
         }
     };
 
-    let mnemonic = match str_to_mnemonic(&instruction) {
-        Ok(string) => string,
-        Err(err) => return Err(err),
+    // Branches are written with the target address, or a label for it
+    let long_branch = matches!(mnemonic, Mnemonic::BRL | Mnemonic::PER);
+    if is_branch(mnemonic) || long_branch || bit_branch {
+        value = match (address_mode, value) {
+            (AddressMode::Zeropage, Value::U8(target)) => Value::U16(target as u16),
+            (AddressMode::Absolute, target) | (AddressMode::AbsoluteLong, target) => target,
+            (_, _) => {
+                return Err(error(
+                    ErrorCode::UnknownSyntax,
+                    format!("Expected a branch target at line {}", program.line),
+                ))
+            }
+        };
+        address_mode = if long_branch {
+            AddressMode::RelativeLong
+        } else if bit_branch {
+            AddressMode::ZeropageRelative
+        } else {
+            AddressMode::Relative
+        };
+    }
+
+    // jsl and jml always take a long address
+    if matches!(mnemonic, Mnemonic::JSL | Mnemonic::JML) && address_mode == AddressMode::Absolute {
+        address_mode = AddressMode::AbsoluteLong;
+        if let Value::U16(target) = value {
+            value = Value::U24(target as u32);
+        }
+    }
+
+    let key = InstructionKey {
+        mnemonic,
+        address_mode,
     };
+    if !available(key, program.cpu) {
+        return Err(error(
+            ErrorCode::UnsupportedInstruction,
+            format!(
+                "{} at line {} isn't available on the {}, it needs the {}",
+                line,
+                program.line,
+                cpu_to_str(program.cpu),
+                cpu_to_str(minimum_cpu(key))
+            ),
+        ));
+    }
+
+    // The 65816 takes a 16 bit immediate when the register it's for is 16 bits
+    let wide = address_mode == AddressMode::Immediate && wide_register(program, mnemonic);
+    value = match (value, wide) {
+        (Value::U8(val), true) => Value::U16(val as u16),
+        (Value::U16(_), false) if address_mode == AddressMode::Immediate => {
+            return Err(error(
+                ErrorCode::UnknownSyntax,
+                format!(
+                    "{} at line {} has a 16 bit immediate for an 8 bit register",
+                    line, program.line
+                ),
+            ))
+        }
+        (value, _) => value,
+    };
+
+    // rep and sep change the register sizes from here on
+    if let (Mnemonic::REP | Mnemonic::SEP, Value::U8(bits)) = (mnemonic, &value) {
+        let set = mnemonic == Mnemonic::REP;
+        if bits & 0x20 != 0 {
+            program.wide_a = set;
+        }
+        if bits & 0x10 != 0 {
+            program.wide_xy = set;
+        }
+    }
+
+    let size = address_mode_length(address_mode) + wide as u16;
+    let address = reserve(program, size as Counter)?;
     let entry = CodeTableEntry {
-        address: program.counter,
+        address,
         content: Content::Code(Code {
             mnemonic,
             address_mode,
             value,
+            wide,
+            zeropage,
         }),
+        file: program.file,
+        line: program.line,
     };
 
     program.code.push(entry);
 
-    // Move our program counter to the next free location
-    program.counter += address_mode_length(address_mode);
-
     Ok(())
 }
 
-fn get_operand_type(operand: &str) -> (AddressMode, Value) {
+// Whether an immediate is for a register the 65816 has made 16 bits
+fn wide_register(program: &Program, mnemonic: Mnemonic) -> bool {
+    use Mnemonic::*;
+
+    program.cpu == Cpu::W65816
+        && match mnemonic {
+            ADC | AND | BIT | CMP | EOR | LDA | ORA | SBC => program.wide_a,
+            CPX | CPY | LDX | LDY => program.wide_xy,
+            _ => false,
+        }
+}
+
+// Claim room for size bytes at the counter, moving the counter on to the
+// next free location. Nothing may run past the top of memory.
+fn reserve(program: &mut Program, size: Counter) -> Result<Address, Error> {
+    let limit = address_limit(program.cpu);
+    if program.counter >= limit || program.counter + size > limit {
+        return Err(error(
+            ErrorCode::CounterOverflow,
+            format!(
+                "Line {} runs past ${:04x} from ${:04x}",
+                program.line,
+                limit - 1,
+                program.counter
+            ),
+        ));
+    }
+    let address = program.counter as Address;
+    program.counter += size;
+    Ok(address)
+}
+
+// Only the 65816 can see past 64K
+fn address_limit(cpu: Cpu) -> Counter {
+    match cpu {
+        Cpu::W65816 => 0x1000000,
+        _ => 0x10000,
+    }
+}
+
+fn get_operand_type(operand: &str) -> Result<(AddressMode, Value), Error> {
     // TODO: use lazy_static somehow!
     // We use unwrap here and know we're good because we tested the code :)
-    let zeropage_re = Regex::new(r"^\$([0-9a-f]{2})$").unwrap();
-    let zeropagex_re = Regex::new(r"^\$([0-9a-f]{2})\s*,\s*x$").unwrap();
-    let zeropagey_re = Regex::new(r"^\$([0-9a-f]{2})\s*,\s*y$").unwrap();
-    let immediate_re = Regex::new(r"^#\$([0-9a-f]{2})$").unwrap();
-    let absolute_re = Regex::new(r"^\$([0-9a-f]{4})$").unwrap();
-    let absolutex_re = Regex::new(r"^\$([0-9a-f]{4})\s*,\s*x$").unwrap();
-    let absolutey_re = Regex::new(r"^\$([0-9a-f]{4})\s*,\s*y$").unwrap();
-    let indirect_re = Regex::new(r"^\(\$([0-9a-f]{4})\)$").unwrap();
-    let xindexed_re = Regex::new(r"^\(\$([0-9a-f]{2})\s*,\s*x\)$").unwrap();
-    let yindexed_re = Regex::new(r"^\(\$([0-9a-f]{2})\)\s*,\s*y$").unwrap();
+    let zeropage_re = Regex::new(r"(?i)^\$([0-9a-f]{2})$").unwrap();
+    let zeropagex_re = Regex::new(r"(?i)^\$([0-9a-f]{2})\s*,\s*x$").unwrap();
+    let zeropagey_re = Regex::new(r"(?i)^\$([0-9a-f]{2})\s*,\s*y$").unwrap();
+    let immediate_re = Regex::new(r"(?i)^#\$([0-9a-f]{2})$").unwrap();
+    let absolute_re = Regex::new(r"(?i)^\$([0-9a-f]{4})$").unwrap();
+    let absolutex_re = Regex::new(r"(?i)^\$([0-9a-f]{4})\s*,\s*x$").unwrap();
+    let absolutey_re = Regex::new(r"(?i)^\$([0-9a-f]{4})\s*,\s*y$").unwrap();
+    let indirect_re = Regex::new(r"(?i)^\(\$([0-9a-f]{4})\)$").unwrap();
+    let xindexed_re = Regex::new(r"(?i)^\(\$([0-9a-f]{2})\s*,\s*x\)$").unwrap();
+    let yindexed_re = Regex::new(r"(?i)^\(\$([0-9a-f]{2})\)\s*,\s*y$").unwrap();
+    let zp_indirect_re = Regex::new(r"(?i)^\(\$([0-9a-f]{2})\)$").unwrap();
+    let abs_indexed_indirect_re = Regex::new(r"(?i)^\(\$([0-9a-f]{4})\s*,\s*x\)$").unwrap();
+    // The 65816's modes
+    let immediate_word_re = Regex::new(r"(?i)^#\$([0-9a-f]{4})$").unwrap();
+    let long_re = Regex::new(r"(?i)^\$([0-9a-f]{6})$").unwrap();
+    let longx_re = Regex::new(r"(?i)^\$([0-9a-f]{6})\s*,\s*x$").unwrap();
+    let indirect_long_re = Regex::new(r"(?i)^\[\$([0-9a-f]{2})\]$").unwrap();
+    let indirect_long_y_re = Regex::new(r"(?i)^\[\$([0-9a-f]{2})\]\s*,\s*y$").unwrap();
+    let stack_re = Regex::new(r"(?i)^\$([0-9a-f]{2})\s*,\s*s$").unwrap();
+    let stack_indirect_y_re = Regex::new(r"(?i)^\(\$([0-9a-f]{2})\s*,\s*s\)\s*,\s*y$").unwrap();
+    let block_move_re = Regex::new(r"(?i)^\$([0-9a-f]{2})\s*,\s*\$([0-9a-f]{2})$").unwrap();
+    let abs_indirect_long_re = Regex::new(r"(?i)^\[\$([0-9a-f]{4})\]$").unwrap();
     // oh no... forgot about opcode $ab relative address mode...
     // for branch targets...
 
     // We use unwrap below but the regexes guarantee we got something sane
     if operand.is_empty() {
-        return (AddressMode::Implied, Value::Null);
+        return Ok((AddressMode::Implied, Value::Null));
     } else if zeropage_re.is_match(operand) {
         let caps = zeropage_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::Zeropage, Value::U8(val));
+        return Ok((AddressMode::Zeropage, Value::U8(val)));
     } else if zeropagex_re.is_match(operand) {
         let caps = zeropagex_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::ZeropageX, Value::U8(val));
+        return Ok((AddressMode::ZeropageX, Value::U8(val)));
     } else if zeropagey_re.is_match(operand) {
         let caps = zeropagey_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::ZeropageY, Value::U8(val));
+        return Ok((AddressMode::ZeropageY, Value::U8(val)));
     } else if immediate_re.is_match(operand) {
         let caps = immediate_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::Immediate, Value::U8(val));
+        return Ok((AddressMode::Immediate, Value::U8(val)));
     } else if absolute_re.is_match(operand) {
         let caps = absolute_re.captures(operand).unwrap();
         let val = str_to_u16!(&caps[1]).unwrap();
-        return (AddressMode::Absolute, Value::U16(val));
+        return Ok((AddressMode::Absolute, Value::U16(val)));
     } else if absolutex_re.is_match(operand) {
         let caps = absolutex_re.captures(operand).unwrap();
         let val = str_to_u16!(&caps[1]).unwrap();
-        return (AddressMode::AbsoluteX, Value::U16(val));
+        return Ok((AddressMode::AbsoluteX, Value::U16(val)));
     } else if absolutey_re.is_match(operand) {
         let caps = absolutey_re.captures(operand).unwrap();
         let val = str_to_u16!(&caps[1]).unwrap();
-        return (AddressMode::AbsoluteY, Value::U16(val));
+        return Ok((AddressMode::AbsoluteY, Value::U16(val)));
     } else if indirect_re.is_match(operand) {
         let caps = indirect_re.captures(operand).unwrap();
         let val = str_to_u16!(&caps[1]).unwrap();
-        return (AddressMode::Indirect, Value::U16(val));
+        return Ok((AddressMode::Indirect, Value::U16(val)));
     } else if xindexed_re.is_match(operand) {
         let caps = xindexed_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::IndirectX, Value::U8(val));
+        return Ok((AddressMode::IndirectX, Value::U8(val)));
     } else if yindexed_re.is_match(operand) {
         let caps = yindexed_re.captures(operand).unwrap();
         let val = str_to_u8!(&caps[1]).unwrap();
-        return (AddressMode::IndirectY, Value::U8(val));
+        return Ok((AddressMode::IndirectY, Value::U8(val)));
+    } else if zp_indirect_re.is_match(operand) {
+        let caps = zp_indirect_re.captures(operand).unwrap();
+        let val = str_to_u8!(&caps[1]).unwrap();
+        return Ok((AddressMode::ZeropageIndirect, Value::U8(val)));
+    } else if abs_indexed_indirect_re.is_match(operand) {
+        let caps = abs_indexed_indirect_re.captures(operand).unwrap();
+        let val = str_to_u16!(&caps[1]).unwrap();
+        return Ok((AddressMode::AbsoluteIndexedIndirect, Value::U16(val)));
+    } else if immediate_word_re.is_match(operand) {
+        // Only allowed once the register is 16 bits, see handle_instruction
+        let caps = immediate_word_re.captures(operand).unwrap();
+        let val = str_to_u16!(&caps[1]).unwrap();
+        return Ok((AddressMode::Immediate, Value::U16(val)));
+    } else if long_re.is_match(operand) {
+        let caps = long_re.captures(operand).unwrap();
+        let val = str_to_u32!(&caps[1]).unwrap();
+        return Ok((AddressMode::AbsoluteLong, Value::U24(val)));
+    } else if longx_re.is_match(operand) {
+        let caps = longx_re.captures(operand).unwrap();
+        let val = str_to_u32!(&caps[1]).unwrap();
+        return Ok((AddressMode::AbsoluteLongX, Value::U24(val)));
+    } else if indirect_long_re.is_match(operand) {
+        let caps = indirect_long_re.captures(operand).unwrap();
+        let val = str_to_u8!(&caps[1]).unwrap();
+        return Ok((AddressMode::DirectIndirectLong, Value::U8(val)));
+    } else if indirect_long_y_re.is_match(operand) {
+        let caps = indirect_long_y_re.captures(operand).unwrap();
+        let val = str_to_u8!(&caps[1]).unwrap();
+        return Ok((AddressMode::DirectIndirectLongY, Value::U8(val)));
+    } else if stack_re.is_match(operand) {
+        let caps = stack_re.captures(operand).unwrap();
+        let val = str_to_u8!(&caps[1]).unwrap();
+        return Ok((AddressMode::StackRelative, Value::U8(val)));
+    } else if stack_indirect_y_re.is_match(operand) {
+        let caps = stack_indirect_y_re.captures(operand).unwrap();
+        let val = str_to_u8!(&caps[1]).unwrap();
+        return Ok((AddressMode::StackRelativeIndirectY, Value::U8(val)));
+    } else if block_move_re.is_match(operand) {
+        // Written source bank first, but the destination bank comes first in memory
+        let caps = block_move_re.captures(operand).unwrap();
+        let source = str_to_u8!(&caps[1]).unwrap();
+        let destination = str_to_u8!(&caps[2]).unwrap();
+        let val = u16::from_be_bytes([source, destination]);
+        return Ok((AddressMode::BlockMove, Value::U16(val)));
+    } else if abs_indirect_long_re.is_match(operand) {
+        let caps = abs_indirect_long_re.captures(operand).unwrap();
+        let val = str_to_u16!(&caps[1]).unwrap();
+        return Ok((AddressMode::AbsoluteIndirectLong, Value::U16(val)));
     }
 
     // Now do it all again for labels :(
     // TODO: Add #< and #> for lo byte and hi byte
     // We use unwrap here and know we're good because we tested the code :)
-    let l_absolute_re = Regex::new(r"^([a-z_][0-9a-z_]*)$").unwrap();
-    let l_absolutex_re = Regex::new(r"^([a-z_][0-9a-z_]*)\s*,\s*x$").unwrap();
-    let l_absolutey_re = Regex::new(r"^([a-z_][0-9a-z_]*)\s*,\s*y$").unwrap();
-    let l_immediate_re = Regex::new(r"^#([a-z_][0-9a-z_]*)$").unwrap();
-    let l_indirect_re = Regex::new(r"^\(([a-z_][0-9a-z_]*)\)$").unwrap();
+    let l_absolute_re = Regex::new(r"(?i)^([a-z_][0-9a-z_]*)$").unwrap();
+    let l_absolutex_re = Regex::new(r"(?i)^([a-z_][0-9a-z_]*)\s*,\s*x$").unwrap();
+    let l_absolutey_re = Regex::new(r"(?i)^([a-z_][0-9a-z_]*)\s*,\s*y$").unwrap();
+    let l_immediate_re = Regex::new(r"(?i)^#([a-z_][0-9a-z_]*)$").unwrap();
+    let l_indirect_re = Regex::new(r"(?i)^\(([a-z_][0-9a-z_]*)\)$").unwrap();
+    let l_abs_indexed_indirect_re = Regex::new(r"(?i)^\(([a-z_][0-9a-z_]*)\s*,\s*x\)$").unwrap();
 
     // We use unwrap below but the regexes guarantee we got something sane
     if l_absolute_re.is_match(operand) {
         let caps = l_absolute_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::Absolute, Value::String(label));
+        return Ok((AddressMode::Absolute, Value::String(label)));
     } else if l_absolutex_re.is_match(operand) {
         let caps = l_absolutex_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::AbsoluteX, Value::String(label));
+        return Ok((AddressMode::AbsoluteX, Value::String(label)));
     } else if l_absolutey_re.is_match(operand) {
         let caps = l_absolutey_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::AbsoluteY, Value::String(label));
+        return Ok((AddressMode::AbsoluteY, Value::String(label)));
     } else if l_immediate_re.is_match(operand) {
         let caps = l_immediate_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::Immediate, Value::String(label));
+        return Ok((AddressMode::Immediate, Value::String(label)));
     } else if l_indirect_re.is_match(operand) {
         let caps = l_indirect_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::Indirect, Value::String(label));
+        return Ok((AddressMode::Indirect, Value::String(label)));
+    } else if l_abs_indexed_indirect_re.is_match(operand) {
+        let caps = l_abs_indexed_indirect_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::AbsoluteIndexedIndirect, Value::String(label)));
     }
 
     // kasm requires all zeropage labels to be prefixed with zp:
-    let l_xindexed_re = Regex::new(r"^\((zp:[a-z_][0-9a-z_]*)\s*,\s*x\)$").unwrap();
-    let l_yindexed_re = Regex::new(r"^\((zp:[a-z_][0-9a-z_]*)\)\s*,\s*y$").unwrap();
-    let l_zeropage_re = Regex::new(r"^(zp:[a-z_][0-9a-z_]*)$").unwrap();
-    let l_zeropagex_re = Regex::new(r"^(zp:[a-z_][0-9a-z_]*)\s*,\s*x$").unwrap();
-    let l_zeropagey_re = Regex::new(r"^(zp:[a-z_][0-9a-z_]*)\s*,\s*y$").unwrap();
+    let l_xindexed_re = Regex::new(r"(?i)^\((zp:[a-z_][0-9a-z_]*)\s*,\s*x\)$").unwrap();
+    let l_yindexed_re = Regex::new(r"(?i)^\((zp:[a-z_][0-9a-z_]*)\)\s*,\s*y$").unwrap();
+    let l_zeropage_re = Regex::new(r"(?i)^(zp:[a-z_][0-9a-z_]*)$").unwrap();
+    let l_zeropagex_re = Regex::new(r"(?i)^(zp:[a-z_][0-9a-z_]*)\s*,\s*x$").unwrap();
+    let l_zeropagey_re = Regex::new(r"(?i)^(zp:[a-z_][0-9a-z_]*)\s*,\s*y$").unwrap();
+    let l_zp_indirect_re = Regex::new(r"(?i)^\((zp:[a-z_][0-9a-z_]*)\)$").unwrap();
 
     if l_xindexed_re.is_match(operand) {
         let caps = l_xindexed_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::IndirectX, Value::String(label));
+        return Ok((AddressMode::IndirectX, Value::String(label)));
     } else if l_yindexed_re.is_match(operand) {
         let caps = l_yindexed_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::IndirectY, Value::String(label));
+        return Ok((AddressMode::IndirectY, Value::String(label)));
     } else if l_zeropage_re.is_match(operand) {
         let caps = l_zeropage_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::Zeropage, Value::String(label));
+        return Ok((AddressMode::Zeropage, Value::String(label)));
     } else if l_zeropagex_re.is_match(operand) {
         let caps = l_zeropagex_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::ZeropageX, Value::String(label));
+        return Ok((AddressMode::ZeropageX, Value::String(label)));
     } else if l_zeropagey_re.is_match(operand) {
         let caps = l_zeropagey_re.captures(operand).unwrap();
         let label = String::from(&caps[1]);
-        return (AddressMode::ZeropageY, Value::String(label));
+        return Ok((AddressMode::ZeropageY, Value::String(label)));
+    } else if l_zp_indirect_re.is_match(operand) {
+        let caps = l_zp_indirect_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::ZeropageIndirect, Value::String(label)));
+    }
+
+    // long: asks for all 24 bits of any label on the 65816
+    let l_long_re = Regex::new(r"(?i)^long:([a-z_][0-9a-z_]*)$").unwrap();
+    let l_longx_re = Regex::new(r"(?i)^long:([a-z_][0-9a-z_]*)\s*,\s*x$").unwrap();
+    let l_indirect_long_re = Regex::new(r"(?i)^\[(zp:[a-z_][0-9a-z_]*)\]$").unwrap();
+    let l_indirect_long_y_re = Regex::new(r"(?i)^\[(zp:[a-z_][0-9a-z_]*)\]\s*,\s*y$").unwrap();
+    let l_abs_indirect_long_re = Regex::new(r"(?i)^\[([a-z_][0-9a-z_]*)\]$").unwrap();
+
+    if l_long_re.is_match(operand) {
+        let caps = l_long_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::AbsoluteLong, Value::String(label)));
+    } else if l_longx_re.is_match(operand) {
+        let caps = l_longx_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::AbsoluteLongX, Value::String(label)));
+    } else if l_indirect_long_re.is_match(operand) {
+        let caps = l_indirect_long_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::DirectIndirectLong, Value::String(label)));
+    } else if l_indirect_long_y_re.is_match(operand) {
+        let caps = l_indirect_long_y_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::DirectIndirectLongY, Value::String(label)));
+    } else if l_abs_indirect_long_re.is_match(operand) {
+        let caps = l_abs_indirect_long_re.captures(operand).unwrap();
+        let label = String::from(&caps[1]);
+        return Ok((AddressMode::AbsoluteIndirectLong, Value::String(label)));
     }
-    // also missing relative mode
+    // Relative mode is picked by handle_instruction, see is_branch
 
-    // We should never get here, I think
-    panic!("Unknown addressing mode!");
+    // Anything else, like hex in capitals, isn't an operand kasm knows
+    Err(error(
+        ErrorCode::UnknownSyntax,
+        format!("Unknown addressing mode: {}", operand),
+    ))
 }
 
 #[cfg(test)]
 mod tests {
     use super::get_operand_type;
+    use super::pass1;
     use super::AddressMode;
+    use super::Content;
     use super::Value;
+    use crate::instructions::generate_instruction_set;
+    use crate::pass2::generate;
+    use crate::scanner::scanner;
+
+    fn operand_type(operand: &str) -> (AddressMode, Value) {
+        match get_operand_type(operand) {
+            Ok(found) => found,
+            Err(err) => panic!("Did not expect {} to fail: {}", operand, err.1),
+        }
+    }
 
     #[test]
     fn test_absolute() {
-        let (am, v) = get_operand_type("$12ab");
+        let (am, v) = operand_type("$12ab");
         assert_eq!(am, AddressMode::Absolute);
         assert_eq!(v, Value::U16(0x12ab));
     }
 
+    #[test]
+    fn test_mixed_case_operands() {
+        for (operand, mode, value) in [
+            ("$12AB", AddressMode::Absolute, Value::U16(0x12ab)),
+            ("$Cd,X", AddressMode::ZeropageX, Value::U8(0xcd)),
+            ("($CD),Y", AddressMode::IndirectY, Value::U8(0xcd)),
+            ("#$2B", AddressMode::Immediate, Value::U8(0x2b)),
+            ("$1F,S", AddressMode::StackRelative, Value::U8(0x1f)),
+            (
+                "table,X",
+                AddressMode::AbsoluteX,
+                Value::String("table".to_string()),
+            ),
+        ] {
+            assert_eq!(operand_type(operand), (mode, value));
+        }
+    }
+
+    #[test]
+    fn test_unknown_addressing_mode() {
+        if get_operand_type("$12345").is_ok() {
+            panic!("Did not expect five hex digits to be an operand");
+        }
+    }
+
     #[test]
     fn test_absolute_x() {
-        let (am, v) = get_operand_type("$12ab,x");
+        let (am, v) = operand_type("$12ab,x");
         assert_eq!(am, AddressMode::AbsoluteX);
         assert_eq!(v, Value::U16(0x12ab));
     }
 
     #[test]
     fn test_absolute_y() {
-        let (am, v) = get_operand_type("$12ab,y");
+        let (am, v) = operand_type("$12ab,y");
         assert_eq!(am, AddressMode::AbsoluteY);
         assert_eq!(v, Value::U16(0x12ab));
     }
 
     #[test]
     fn test_immediate() {
-        let (am, v) = get_operand_type("#$cd");
+        let (am, v) = operand_type("#$cd");
         assert_eq!(am, AddressMode::Immediate);
         assert_eq!(v, Value::U8(0xcd));
     }
 
     #[test]
     fn test_implied() {
-        let (am, v) = get_operand_type("");
+        let (am, v) = operand_type("");
         assert_eq!(am, AddressMode::Implied);
         assert_eq!(v, Value::Null);
     }
 
     #[test]
     fn test_indirect() {
-        let (am, v) = get_operand_type("($12ab)");
+        let (am, v) = operand_type("($12ab)");
         assert_eq!(am, AddressMode::Indirect);
         assert_eq!(v, Value::U16(0x12ab));
     }
 
     #[test]
     fn test_x_indexed_indirect() {
-        let (am, v) = get_operand_type("($cd,x)");
+        let (am, v) = operand_type("($cd,x)");
         assert_eq!(am, AddressMode::IndirectX);
         assert_eq!(v, Value::U8(0xcd));
     }
 
     #[test]
     fn test_indirect_y_indexed() {
-        let (am, v) = get_operand_type("($cd),y");
+        let (am, v) = operand_type("($cd),y");
         assert_eq!(am, AddressMode::IndirectY);
         assert_eq!(v, Value::U8(0xcd));
     }
 
     #[test]
     fn test_relative() {
-        let (am, v) = get_operand_type("$cd");
+        let (am, v) = operand_type("$cd");
         // NOTE: We're doubling up relative and zeropage
         // No instructions use both addressing modes
         assert_eq!(am, AddressMode::Zeropage);
@@ -514,21 +1460,21 @@ mod tests {
 
     #[test]
     fn test_zeropage() {
-        let (am, v) = get_operand_type("$cd");
+        let (am, v) = operand_type("$cd");
         assert_eq!(am, AddressMode::Zeropage);
         assert_eq!(v, Value::U8(0xcd));
     }
 
     #[test]
     fn test_zeropage_x() {
-        let (am, v) = get_operand_type("$cd,x");
+        let (am, v) = operand_type("$cd,x");
         assert_eq!(am, AddressMode::ZeropageX);
         assert_eq!(v, Value::U8(0xcd));
     }
 
     #[test]
     fn test_zeropage_y() {
-        let (am, v) = get_operand_type("$cd,y");
+        let (am, v) = operand_type("$cd,y");
         assert_eq!(am, AddressMode::ZeropageY);
         assert_eq!(v, Value::U8(0xcd));
     }
@@ -536,7 +1482,7 @@ mod tests {
     #[test]
     fn test_label_absolute() {
         // XXX we can't distinguish between absolute and zeropage yet!
-        let (am, v) = get_operand_type("label");
+        let (am, v) = operand_type("label");
         assert_eq!(am, AddressMode::Absolute);
         assert_eq!(v, Value::String(String::from("label")));
     }
@@ -544,7 +1490,7 @@ mod tests {
     #[test]
     fn test_label_absolute_x() {
         // XXX we can't distinguish between absolute and zeropage yet!
-        let (am, v) = get_operand_type("label,x");
+        let (am, v) = operand_type("label,x");
         assert_eq!(am, AddressMode::AbsoluteX);
         assert_eq!(v, Value::String(String::from("label")));
     }
@@ -552,36 +1498,360 @@ mod tests {
     #[test]
     fn test_label_absolute_y() {
         // XXX we can't distinguish between absolute and zeropage yet!
-        let (am, v) = get_operand_type("label,y");
+        let (am, v) = operand_type("label,y");
         assert_eq!(am, AddressMode::AbsoluteY);
         assert_eq!(v, Value::String(String::from("label")));
     }
 
     #[test]
     fn test_label_immediate() {
-        let (am, v) = get_operand_type("#label");
+        let (am, v) = operand_type("#label");
         assert_eq!(am, AddressMode::Immediate);
         assert_eq!(v, Value::String(String::from("label")));
     }
 
     #[test]
     fn test_label_indirect() {
-        let (am, v) = get_operand_type("(label)");
+        let (am, v) = operand_type("(label)");
         assert_eq!(am, AddressMode::Indirect);
         assert_eq!(v, Value::String(String::from("label")));
     }
 
     #[test]
     fn test_label_x_indexed_indirect() {
-        let (am, v) = get_operand_type("(zp:label,x)");
+        let (am, v) = operand_type("(zp:label,x)");
         assert_eq!(am, AddressMode::IndirectX);
         assert_eq!(v, Value::String(String::from("zp:label")));
     }
 
     #[test]
     fn test_label_y_indexed_indirect() {
-        let (am, v) = get_operand_type("(zp:label),y");
+        let (am, v) = operand_type("(zp:label),y");
         assert_eq!(am, AddressMode::IndirectY);
         assert_eq!(v, Value::String(String::from("zp:label")));
     }
+
+    #[test]
+    fn test_encodings() {
+        // The instructions whose opcodes, or addressing, used to be wrong
+        let source = ".org $1000\nstart:\n    stx $12\n    sty $1234\n    ldx $12,y\n    bit #$01\n    tsb $12\n    trb $1234\n    stz $12,x\n    eor $12,x\n    rol\n    cpx #$02\n    sta ($12)\n    jmp ($1234,x)\n    bne start\n    rti\n";
+        let program = match pass1(scanner(source)) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}", err.1),
+        };
+        let segments =
+            match generate(&generate_instruction_set(), &program).and_then(|o| o.segments()) {
+                Ok(segments) => segments,
+                Err(err) => panic!("Did not expect pass2 to fail: {}", err.1),
+            };
+        assert_eq!(
+            segments[0].data,
+            vec![
+                0x86, 0x12, 0x8c, 0x34, 0x12, 0xb6, 0x12, 0x89, 0x01, 0x04, 0x12, 0x1c, 0x34, 0x12,
+                0x74, 0x12, 0x55, 0x12, 0x2a, 0xe0, 0x02, 0x92, 0x12, 0x7c, 0x34, 0x12, 0xd0, 0xe4,
+                0x40
+            ]
+        );
+    }
+
+    #[test]
+    fn test_branch_out_of_range() {
+        let source = scanner(".org $1000\nstart:\n.byte $00\n.org $1100\n    beq start\n");
+        let program = match pass1(source) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}", err.1),
+        };
+        if generate(&generate_instruction_set(), &program).is_ok() {
+            panic!("Did not expect a branch 258 bytes back to work");
+        }
+    }
+
+    #[test]
+    fn test_bit_branches() {
+        let source = ".org $1000\nstart:\n    bbr0 $12,start\n    bbs7 $34,end\n    bbr1 $56,$1000\nend:\n    rts\n";
+        let program = match pass1(scanner(source)) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect pass1 to fail: {}", err.1),
+        };
+        let segments =
+            match generate(&generate_instruction_set(), &program).and_then(|o| o.segments()) {
+                Ok(segments) => segments,
+                Err(err) => panic!("Did not expect pass2 to fail: {}", err.1),
+            };
+        assert_eq!(
+            segments[0].data,
+            vec![0x0f, 0x12, 0xfd, 0xff, 0x34, 0x03, 0x1f, 0x56, 0xf7, 0x60]
+        );
+    }
+
+    #[test]
+    fn test_bit_branch_operands() {
+        for operand in &["$12", "start", "$1234,start", "#$12,start"] {
+            let source = format!("start:\n    bbr0 {}\n", operand);
+            if pass1(scanner(&source)).is_ok() {
+                panic!("Did not expect bbr0 {} to work", operand);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fill_to_top_of_memory() {
+        let source = scanner(".org $fffc\n.byte $00, $10, $00, $10\n");
+        if let Ok(program) = pass1(source) {
+            assert_eq!(program.code[0].address, 0xfffc);
+        } else {
+            panic!("Did not expect filling up to $ffff to error");
+        };
+    }
+
+    #[test]
+    fn test_counter_overflow() {
+        let source = scanner(".org $fffe\nlda $1234\n");
+        match pass1(source) {
+            Ok(_) => panic!("Did not expect running past $ffff to work"),
+            Err(err) => assert_eq!(err.1, "Line 2 runs past $ffff from $fffe"),
+        };
+    }
+
+    #[test]
+    fn test_vectors() {
+        let source = scanner(".vectors reset=start, irq=$1234\n");
+        if let Ok(program) = pass1(source) {
+            assert_eq!(program.code.len(), 2);
+            assert_eq!(program.code[0].address, 0xfffc);
+            assert!(program.code[0].content == Content::Word(Value::String("start".to_string())));
+            assert_eq!(program.code[1].address, 0xfffe);
+            assert!(program.code[1].content == Content::Word(Value::U16(0x1234)));
+        } else {
+            panic!("Did not expect .vectors to error");
+        };
+    }
+
+    #[test]
+    fn test_checksum() {
+        let source = scanner(".org $e000\n.checksum crc16, $e000, $ffff\nnop\n");
+        if let Ok(program) = pass1(source) {
+            assert_eq!(program.code[0].address, 0xe000);
+            assert_eq!(program.code[1].address, 0xe002);
+        } else {
+            panic!("Did not expect .checksum to error");
+        };
+        for checksum in &[
+            ".checksum md5, $e000, $ffff",
+            ".checksum sum8, $ffff, $e000",
+            ".checksum crc32, $e000, $ffff, adjust",
+            ".checksum sum8, $e000",
+            ".checksum sum8, $0, $ffffffff",
+            ".checksum sum8, $10000, $10001",
+        ] {
+            if pass1(scanner(checksum)).is_ok() {
+                panic!("Did not expect {} to work", checksum);
+            }
+        }
+    }
+
+    #[test]
+    fn test_assert() {
+        // Forward labels are fine, checks wait for the end of pass1
+        let source = scanner(
+            ".assert table_end - table == 4, \"Table must have 4 entries\"\n\
+             .assert * == $1000\n\
+             table:\n\
+             .byte $01, $02, $03, $04\n\
+             table_end:\n\
+             .assert table >> 8 == (table_end - 1) >> 8\n\
+             .warning \"Table ends at \", table_end\n\
+             .out \"Done\"\n",
+        );
+        match pass1(source) {
+            Ok(program) => assert_eq!(
+                program.messages,
+                vec!["Warning! Line 7: Table ends at 4100", "Done"]
+            ),
+            Err(_) => panic!("Did not expect the assertions to fail"),
+        }
+        for failing in &[
+            ".org $c000\nnop\n.assert * <= $c000, \"Code runs into I/O\"",
+            ".assert missing == 1",
+            ".assert 1 +",
+            ".assert",
+            ".error \"Not supported yet\"",
+        ] {
+            if pass1(scanner(failing)).is_ok() {
+                panic!("Did not expect {} to work", failing);
+            }
+        }
+    }
+
+    #[test]
+    fn test_test_blocks() {
+        let source = scanner(
+            ".org $8000\n\
+             .test \"doubles\"\n\
+             .given a=$21, c=1, $10=$ff\n\
+             .call double\n\
+             .expect a=$42, $10=$ff\n\
+             .endtest\n\
+             double:\n\
+             asl\n\
+             rts\n",
+        );
+        if let Ok(program) = pass1(source) {
+            // Nothing in the test goes into the image
+            assert_eq!(program.code.len(), 2);
+            assert_eq!(program.code[0].address, 0x8000);
+            assert_eq!(program.tests.len(), 1);
+            assert_eq!(program.tests[0].name, "doubles");
+            assert_eq!(program.tests[0].steps.len(), 3);
+        } else {
+            panic!("Did not expect the test block to error");
+        }
+        for broken in &[
+            ".test \"unfinished\"\n.call main",
+            ".test \"code\"\nnop\n.endtest",
+            ".test \"given\"\n.given a\n.endtest",
+            ".test\n.endtest",
+            ".endtest",
+        ] {
+            if pass1(scanner(broken)).is_ok() {
+                panic!("Did not expect {} to work", broken);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cycles() {
+        // 2 + 3 + 2, and the branch may be taken across a page
+        let source = scanner(".cycles 7, 9\nlda #$01\nsta $10\nbne $1000\n.endcycles\n");
+        if let Ok(program) = pass1(source) {
+            assert_eq!(program.cycle_blocks.len(), 1);
+            assert_eq!(program.cycle_blocks[0].start, 1);
+            assert_eq!(program.cycle_blocks[0].end, 5);
+            assert_eq!(program.cycle_blocks[0].min, 7);
+            assert_eq!(program.cycle_blocks[0].max, 9);
+        } else {
+            panic!("Did not expect the cycle budget to fail");
+        }
+        for broken in &[
+            ".cycles 7, 8\nlda #$01\nsta $10\nbne $1000\n.endcycles",
+            ".cycles 8, 9\nlda #$01\nsta $10\nbne $1000\n.endcycles",
+            ".cycles 1, 2\nnop",
+            ".cycles 1, 2\n.cycles 1, 2\n.endcycles\n.endcycles",
+            ".endcycles",
+            ".cycles 2\nnop\n.endcycles",
+            ".cycles 3, 2\nnop\n.endcycles",
+        ] {
+            if pass1(scanner(broken)).is_ok() {
+                panic!("Did not expect {} to work", broken);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cpu() {
+        for (source, works) in &[
+            (".cpu 6502\nlda ($12),y\n", true),
+            (".cpu 6502\nlda ($12)\n", false),
+            (".cpu 6502\nstz $12\n", false),
+            (".cpu 6502\ninc\n", false),
+            (".cpu 65c02\nstz $12\nbra $1000\n", true),
+            (".cpu 65c02\nsmb0 $12\n", false),
+            (".cpu r65c02\nsmb0 $12\n", true),
+            (".cpu r65c02\nstp\n", false),
+            (".cpu w65c02\nstp\n", true),
+            ("stp\n", true),
+            (".cpu 6510\n", false),
+            (".cpu 6502x\nlax ($12),y\nsbx #$01\n", true),
+            (".cpu 6502x\nstz $12\n", false),
+            (".cpu 6502\nlax $12\n", false),
+            (".cpu 65816\nslo $12\n", false),
+            (".cpu w65c02\nxce\n", false),
+            (".cpu 65816\nxce\n", true),
+            (".cpu 65816\nsmb0 $12\n", false),
+            (".org $10000\nnop\n", false),
+            (".cpu 65816\n.org $10000\nnop\n", true),
+            (".a16\n", false),
+            (".cpu 65816\nlda #$1234\n", false),
+            (".cpu 65816\n.a16\nlda #$1234\n", true),
+        ] {
+            if pass1(scanner(source)).is_ok() != *works {
+                panic!("Expected {} to work: {}", source, works);
+            }
+        }
+        if let Err(err) = super::pass1_for_cpu(scanner("nop\nphx\n"), super::Cpu::Mos6502) {
+            assert_eq!(
+                err.1,
+                "phx at line 2 isn't available on the 6502, it needs the 65c02"
+            );
+        } else {
+            panic!("Did not expect phx to work on the 6502");
+        }
+    }
+
+    #[test]
+    fn test_65816() {
+        let source = scanner(
+            ".cpu 65816\n\
+             .org $018000\n\
+             start:\n\
+             rep #$30\n\
+             lda #$1234\n\
+             ldx #$05\n\
+             sep #$20\n\
+             lda #$12\n\
+             .i8\n\
+             ldy #size\n\
+             lda $123456,x\n\
+             lda [$12],y\n\
+             sta ($03,s),y\n\
+             mvn $01,$02\n\
+             jsl start\n\
+             jml [$1234]\n\
+             lda long:start\n\
+             brl start\n\
+             .equ size=$10\n",
+        );
+        let program = match pass1(source) {
+            Ok(program) => program,
+            Err(err) => panic!("Did not expect the 65816 program to error: {}", err.1),
+        };
+        let output = match generate(&generate_instruction_set(), &program) {
+            Ok(output) => output,
+            Err(err) => panic!("Did not expect pass2 to error: {}", err.1),
+        };
+        let bytes: Vec<Vec<u8>> = output.listing.into_iter().map(|l| l.bytes).collect();
+        assert_eq!(program.code[0].address, 0x018000);
+        assert_eq!(
+            bytes,
+            vec![
+                vec![0xc2, 0x30],
+                vec![0xa9, 0x34, 0x12],
+                vec![0xa2, 0x05, 0x00],
+                vec![0xe2, 0x20],
+                vec![0xa9, 0x12],
+                vec![0xa0, 0x10],
+                vec![0xbf, 0x56, 0x34, 0x12],
+                vec![0xb7, 0x12],
+                vec![0x93, 0x03],
+                vec![0x54, 0x02, 0x01],
+                vec![0x22, 0x00, 0x80, 0x01],
+                vec![0xdc, 0x34, 0x12],
+                vec![0xaf, 0x00, 0x80, 0x01],
+                vec![0x82, 0xd9, 0xff],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bad_vectors() {
+        for vectors in &[
+            ".vectors brk=start",
+            ".vectors reset=a, reset=b",
+            ".vectors reset",
+        ] {
+            if pass1(scanner(vectors)).is_ok() {
+                panic!("Did not expect {} to work", vectors);
+            }
+        }
+    }
 }
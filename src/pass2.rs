@@ -1,91 +1,354 @@
+use crate::checksum::checksum;
+use crate::checksum::checksum_width;
+use crate::checksum::Checksum;
 use crate::errors::error;
 use crate::errors::Error;
 use crate::errors::ErrorCode;
+use crate::errors::LocatedError;
+use crate::image::flatten;
+use crate::image::Image;
+use crate::image::Segment;
+use crate::instructions::address_mode_length;
+use crate::instructions::AddressMode;
 use crate::instructions::InstructionKey;
 use crate::instructions::InstructionMap;
-use crate::instructions::Value::{Null, String, U16, U8};
-use crate::pass1::Content::{Code, Data};
+use crate::instructions::Value;
+use crate::pass1::Address;
+use crate::pass1::CodeTableEntry;
+use crate::pass1::Content;
+use crate::pass1::Content::{Code, Data, Word};
 use crate::pass1::Program;
+use crate::scanner::FileId;
+use crate::scanner::Line;
 
-pub type MachineCode = Vec<u8>;
+pub use crate::image::MachineCode;
 
-pub fn pass2(instruction_set: InstructionMap, program: Program) -> Result<MachineCode, Error> {
-    let mut output = MachineCode::new();
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RelocationKind {
+    Byte, // The low byte of the symbol's value
+    Word, // The symbol's value, little endian
+    Long, // All 24 bits of the symbol's value, for the 65816
+}
+
+// A hole left in the image for an imported label, patched by the linker
+pub struct Relocation {
+    pub address: Address, // Where the hole starts
+    pub kind: RelocationKind,
+    pub symbol: String,
+}
+
+// The bytes produced for a single line of source
+pub struct ListingEntry {
+    pub file: FileId,
+    pub line: Line,
+    pub address: Address,
+    pub bytes: Vec<u8>,
+}
+
+pub struct Output {
+    pub image: Image,
+    pub fill: Option<u8>, // From .fillbyte, for the gaps in the image
+    pub relocations: Vec<Relocation>,
+    pub checksums: Vec<(Address, Checksum)>, // Still to be filled in
+    pub listing: Vec<ListingEntry>,
+}
+
+impl Output {
+    pub fn machine_code(mut self) -> Result<MachineCode, Error> {
+        let fill = self.fill.unwrap_or(0x00);
+        self.finish(fill)?;
+        Ok(flatten(self.segments()?, 0, fill))
+    }
 
-    let mut next_address = 0;
+    // Fill in the checksums, in source order, now every other byte is final.
+    // Gaps count as the fill byte, and a checksum counts itself as zeros.
+    pub fn finish(&mut self, fill: u8) -> Result<(), Error> {
+        if let Some(relocation) = self
+            .relocations
+            .first()
+            .filter(|_| !self.checksums.is_empty())
+        {
+            return Err(error(
+                ErrorCode::UnresolvedImport,
+                format!(
+                    "Checksums can't be computed until {} is resolved",
+                    relocation.symbol
+                ),
+            ));
+        }
 
-    for line in program.code {
-        let mut address = line.address;
+        for (address, entry) in std::mem::take(&mut self.checksums) {
+            let width = checksum_width(entry.kind) as Address;
+            let bytes: Vec<u8> = (entry.start..=entry.end)
+                .map(|at| match self.image.get(at) {
+                    Some(_) if at >= address && at - address < width => 0x00,
+                    Some(byte) => byte,
+                    None => fill,
+                })
+                .collect();
+            let sum = checksum(&entry, &bytes);
 
-        for _ in next_address..address {
-            output.push(0);
+            for (offset, byte) in sum.iter().enumerate() {
+                self.image.patch(address + offset as Address, *byte);
+            }
+            if let Some(listed) = self.listing.iter_mut().find(|l| l.address == address) {
+                listed.bytes = sum;
+            }
         }
-        verbose!("${:04x}: ", address);
-
-        match line.content {
-            Code(code) => {
-                let instruction_key = InstructionKey {
-                    mnemonic: code.mnemonic,
-                    address_mode: code.address_mode,
-                };
-
-                match instruction_set.get(&instruction_key) {
-                    Some(machine_code) => {
-                        verbose!("{:02x} ", machine_code);
-                        output.push(*machine_code);
-                        address += 1;
-                    }
-                    None => {
-                        return Err(error(
-                            ErrorCode::UnknownInstruction,
-                            "Invalid instruction found".to_string(),
-                        ))
-                    }
-                }
 
-                match code.value {
-                    U8(val) => {
-                        output.push(val);
-                        address += 1;
-                        verbose!("{:02x}", val);
-                    }
-                    U16(val) => {
-                        let bytes = val.to_be_bytes();
-                        output.append(&mut vec![bytes[1], bytes[0]]); // Note: little endian!
-                        address += 2;
-                        verbose!("{:02x} {:02x}", bytes[1], bytes[0]);
-                    }
-                    Null => {}
-                    String(label) => {
-                        match program.symbol_table.get(&label) {
-                            Some(val) => {
-                                let bytes = val.address.to_be_bytes();
-                                if bytes.len() == 1 {
-                                    output.append(&mut vec![bytes[0]]); // Note: little endian!
-                                    verbose!("{:02x}", bytes[0]);
-                                } else {
-                                    output.append(&mut vec![bytes[1], bytes[0]]); // Note: little endian!
-                                    verbose!("{:02x} {:02x}", bytes[1], bytes[0]);
-                                }
-                                address += bytes.len() as u16;
-                            }
-                            // This should never happen
-                            None => panic!("Found an unknown label {}", label),
-                        }
-                    }
-                };
+        Ok(())
+    }
+
+    pub fn segments(self) -> Result<Vec<Segment>, Error> {
+        self.resolved()?;
+        Ok(self.image.segments())
+    }
+
+    // Imports only make sense when we're producing an object file
+    pub fn resolved(&self) -> Result<(), Error> {
+        if let Some(relocation) = self.relocations.first() {
+            return Err(error(
+                ErrorCode::UnresolvedImport,
+                format!(
+                    "Imported label {} can only be resolved by the linker",
+                    relocation.symbol
+                ),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+pub fn pass2(instruction_set: InstructionMap, program: Program) -> Result<MachineCode, Error> {
+    generate(&instruction_set, &program)?.machine_code()
+}
+
+// Turn the program into an image of machine code, leaving holes for any
+// imported labels along with the relocations needed to fill them in
+pub fn generate(instruction_set: &InstructionMap, program: &Program) -> Result<Output, Error> {
+    generate_with_line(instruction_set, program).map_err(|(err, _)| err)
+}
+
+// The same, but also saying which line an error was found on
+pub fn generate_with_line(
+    instruction_set: &InstructionMap,
+    program: &Program,
+) -> Result<Output, LocatedError> {
+    let mut image = Image::new();
+    let mut relocations = Vec::new();
+    let mut checksums = Vec::new();
+    let mut listing = Vec::new();
+
+    for line in &program.code {
+        let output = encode(
+            instruction_set,
+            program,
+            line,
+            &mut relocations,
+            &mut checksums,
+        )
+        .map_err(|err| (err, Some((line.file, line.line))))?;
+
+        // Bytes may only be placed once, whatever order the source is in
+        for (offset, byte) in output.iter().enumerate() {
+            let address = line.address + offset as Address;
+            if let Err((file, other)) = image.insert(address, *byte, line.file, line.line) {
+                return Err((
+                    error(
+                        ErrorCode::Overlap,
+                        format!(
+                            "Line {}{} overlaps line {}{} at ${:04x}",
+                            line.line,
+                            program.of_file(line.file),
+                            other,
+                            program.of_file(file),
+                            address
+                        ),
+                    ),
+                    Some((line.file, line.line)),
+                ));
             }
-            Data(data) => {
-                for byte in data {
-                    output.push(byte);
-                    address += 1;
-                    verbose!("{:02x} ", byte);
+        }
+
+        listing.push(ListingEntry {
+            file: line.file,
+            line: line.line,
+            address: line.address,
+            bytes: output,
+        });
+    }
+
+    Ok(Output {
+        image,
+        fill: program.fill,
+        relocations,
+        checksums,
+        listing,
+    })
+}
+
+// The bytes for one entry in the code table
+fn encode(
+    instruction_set: &InstructionMap,
+    program: &Program,
+    line: &CodeTableEntry,
+    relocations: &mut Vec<Relocation>,
+    checksums: &mut Vec<(Address, Checksum)>,
+) -> Result<Vec<u8>, Error> {
+    let mut output = Vec::new();
+
+    match &line.content {
+        Code(code) => {
+            let instruction_key = InstructionKey {
+                mnemonic: code.mnemonic,
+                address_mode: code.address_mode,
+            };
+
+            match instruction_set.get(&instruction_key) {
+                Some(machine_code) => output.push(*machine_code),
+                None => {
+                    return Err(error(
+                        ErrorCode::UnknownInstruction,
+                        "Invalid instruction found".to_string(),
+                    ))
                 }
             }
+
+            match &code.value {
+                // Branches store the distance from the next instruction
+                _ if code.address_mode == AddressMode::Relative => {
+                    let offset =
+                        branch_offset(program, &code.value, line.address, code.address_mode)?;
+                    output.push(offset as u8);
+                }
+                _ if code.address_mode == AddressMode::RelativeLong => {
+                    let offset =
+                        branch_offset(program, &code.value, line.address, code.address_mode)?;
+                    output.extend_from_slice(&offset.to_le_bytes());
+                }
+                _ if code.address_mode == AddressMode::ZeropageRelative => {
+                    let offset =
+                        branch_offset(program, &code.value, line.address, code.address_mode)?;
+                    output.push(code.zeropage);
+                    output.push(offset as u8);
+                }
+                Value::U8(val) => output.push(*val),
+                Value::U16(val) => output.extend_from_slice(&val.to_le_bytes()),
+                Value::U24(val) => output.extend_from_slice(&val.to_le_bytes()[..3]),
+                Value::Null => {}
+                Value::String(label) => {
+                    // The addressing mode tells us how much room the label gets,
+                    // and a 16 bit register needs a 16 bit immediate
+                    let width =
+                        address_mode_length(code.address_mode) as usize - 1 + code.wide as usize;
+                    let address = line.address + output.len() as Address;
+                    let bytes = label_bytes(program, label, width, address, relocations)?;
+                    output.extend(bytes);
+                }
+            };
+        }
+        Data(data) => output.extend_from_slice(data),
+        Content::Checksum(entry) => {
+            // Zeros for now, finish() fills in the real thing
+            output.resize(checksum_width(entry.kind), 0x00);
+            checksums.push((line.address, *entry));
+        }
+        Word(value) => {
+            let bytes = match value {
+                Value::String(label) => label_bytes(program, label, 2, line.address, relocations)?,
+                Value::U16(val) => val.to_le_bytes().to_vec(),
+                Value::U24(val) => val.to_le_bytes()[..2].to_vec(),
+                Value::U8(val) => vec![*val, 0x00],
+                Value::Null => vec![0x00, 0x00],
+            };
+            output.extend(bytes);
         }
-        verboseln!("");
-        next_address = address;
     }
 
     Ok(output)
 }
+
+// The low width bytes of a label's address. Imported labels get a
+// placeholder and a relocation for the linker to fill in.
+fn label_bytes(
+    program: &Program,
+    label: &str,
+    width: usize,
+    address: Address,
+    relocations: &mut Vec<Relocation>,
+) -> Result<Vec<u8>, Error> {
+    let value = match program.symbol_table.get(label) {
+        Some(val) => val.address,
+        None if program.imports.iter().any(|import| import == label) => {
+            relocations.push(Relocation {
+                address,
+                kind: match width {
+                    1 => RelocationKind::Byte,
+                    2 => RelocationKind::Word,
+                    _ => RelocationKind::Long,
+                },
+                symbol: label.to_string(),
+            });
+            0
+        }
+        None => {
+            return Err(error(
+                ErrorCode::UnknownLabel,
+                format!("Unknown label: {}", label),
+            ))
+        }
+    };
+    Ok(value.to_le_bytes()[..width].to_vec())
+}
+
+// The distance from the end of a branch to its target. Branches can't
+// leave their bank, but wrap around inside it.
+fn branch_offset(
+    program: &Program,
+    target: &Value,
+    address: Address,
+    address_mode: AddressMode,
+) -> Result<i16, Error> {
+    let length = address_mode_length(address_mode);
+    let target = match target {
+        Value::U16(val) => *val as Address,
+        Value::U24(val) => *val,
+        Value::String(label) => match program.symbol_table.get(label) {
+            Some(val) => val.address,
+            None => {
+                return Err(error(
+                    ErrorCode::UnknownLabel,
+                    format!("Unknown label: {}", label),
+                ))
+            }
+        },
+        _ => {
+            return Err(error(
+                ErrorCode::UnknownSyntax,
+                format!("Expected a branch target at ${:04x}", address),
+            ))
+        }
+    };
+    if target >> 16 != address >> 16 {
+        return Err(error(
+            ErrorCode::BranchOutOfRange,
+            format!(
+                "Branch at ${:06x} can't reach ${:06x} in another bank",
+                address, target
+            ),
+        ));
+    }
+
+    let offset = (target as u16).wrapping_sub((address as u16).wrapping_add(length)) as i16;
+    if address_mode != AddressMode::RelativeLong && !(-128..=127).contains(&offset) {
+        return Err(error(
+            ErrorCode::BranchOutOfRange,
+            format!(
+                "Branch at ${:04x} can't reach ${:04x}, it's {} bytes away",
+                address, target, offset
+            ),
+        ));
+    }
+    Ok(offset)
+}
@@ -0,0 +1,323 @@
+// kasm run: assemble a program and trace it through the simulator
+use std::collections::HashMap;
+
+use crate::assembly::assemble_with;
+use crate::assembly::Diagnostics;
+use crate::assembly::Options;
+use crate::disassembler::format_instruction;
+use crate::disassembler::Address16;
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::image::Segment;
+use crate::parse_address;
+use crate::pass1::Program;
+use crate::simulator::flags_to_str;
+use crate::simulator::Cpu;
+use crate::simulator::Executed;
+use crate::simulator::Stop;
+use crate::simulator::RESET_VECTOR;
+use crate::sources::FileSystem;
+use crate::sources::SourceProvider;
+
+const CYCLE_LIMIT_DEFAULT: u64 = 1_000_000;
+
+pub struct RunConfig {
+    source_file: String,
+    start: Option<String>, // A label or an address, the reset vector if missing
+    limit: u64,            // Give up after this many cycles
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} run [--start <label|address>] [--cycles <limit>] <sourcefile>",
+        cmd
+    );
+}
+
+impl RunConfig {
+    // Expects the arguments after "run"
+    pub fn new(args: &mut Vec<String>) -> Result<RunConfig, (ErrorCode, &'static str)> {
+        let mut args = args.drain(2..); // Remove the command and "run"
+        let mut source_file = None;
+        let mut start = None;
+        let mut limit = CYCLE_LIMIT_DEFAULT;
+
+        while let Some(val) = args.next() {
+            if val == "--start" {
+                match args.next() {
+                    Some(label) => start = Some(label),
+                    None => return Err((ErrorCode::Usage, "No start label or address provided")),
+                }
+            } else if val == "--cycles" {
+                match args.next().and_then(|cycles| cycles.parse::<u64>().ok()) {
+                    Some(cycles) => limit = cycles,
+                    None => return Err((ErrorCode::Usage, "Invalid cycle limit")),
+                }
+            } else if source_file.is_none() {
+                source_file = Some(val);
+            } else {
+                return Err((ErrorCode::Usage, "Only one source file can be run"));
+            }
+        }
+
+        match source_file {
+            Some(source_file) => Ok(RunConfig {
+                source_file,
+                start,
+                limit,
+            }),
+            None => Err((ErrorCode::Usage, "Missing arguments")),
+        }
+    }
+}
+
+pub fn run(config: &RunConfig) -> Result<(), Error> {
+    let source = FileSystem.read_source(&config.source_file)?;
+    let (program, segments) = assemble(&source, Some(&config.source_file))?;
+    for message in &program.messages {
+        println!("{}", message);
+    }
+
+    let mut cpu = Cpu::new();
+    cpu.load(&segments);
+    cpu.pc = start_address(&program, &segments, config.start.as_deref())?;
+
+    let names = label_names(&program);
+    let stop = cpu.run(config.limit, |cpu, executed| {
+        println!("{}", trace_line(cpu, executed, &names))
+    });
+
+    println!("{} {}", stop_reason(stop, cpu.pc), registers(&cpu));
+    match stop {
+        Stop::CycleLimit => Err(error(
+            ErrorCode::CycleLimit,
+            format!("Still running after {} cycles", cpu.cycles),
+        )),
+        Stop::UnknownOpcode(opcode) => Err(error(
+            ErrorCode::NoValidOpcode,
+            format!("Unknown opcode ${:02x} at ${:04x}", opcode, cpu.pc),
+        )),
+        _ => Ok(()),
+    }
+}
+
+// The same steps as the assembler, stopping short of writing anything out.
+// Included files are found next to name, as they are when assembling.
+pub fn assemble(source: &str, name: Option<&str>) -> Result<(Program, Vec<Segment>), Error> {
+    let assembly = assemble_with(source, name, &Options::default(), &FileSystem)
+        .map_err(Diagnostics::into_error)?;
+    let segments = assembly.segments();
+    Ok((assembly.program, segments))
+}
+
+fn start_address(
+    program: &Program,
+    segments: &[Segment],
+    start: Option<&str>,
+) -> Result<Address16, Error> {
+    if let Some(start) = start {
+        let address = match program.symbol_table.get(start) {
+            Some(label) => Some(label.address),
+            None => parse_address(start),
+        };
+        return match address {
+            // The simulator only has the first 64K
+            Some(address) if address <= 0xffff => Ok(address as Address16),
+            Some(address) => Err(error(
+                ErrorCode::AddressExpected,
+                format!("${:x} is outside the simulator's 64K", address),
+            )),
+            None => Err(error(
+                ErrorCode::UnknownLabel,
+                format!("Unknown label: {}", start),
+            )),
+        };
+    }
+
+    let placed = |address: Address16| {
+        let address = address as u32;
+        segments.iter().any(|segment| {
+            address >= segment.address
+                && ((address - segment.address) as usize) < segment.data.len()
+        })
+    };
+    if !placed(RESET_VECTOR) || !placed(RESET_VECTOR + 1) {
+        return Err(error(
+            ErrorCode::AddressExpected,
+            "No reset vector, use --start to say where to begin".to_string(),
+        ));
+    }
+
+    let mut cpu = Cpu::new();
+    cpu.load(segments);
+    Ok(cpu.read_word(RESET_VECTOR))
+}
+
+// Several labels can share an address, pick the first alphabetically
+fn label_names(program: &Program) -> HashMap<Address16, String> {
+    let mut names: HashMap<Address16, String> = HashMap::new();
+    for (name, label) in &program.symbol_table {
+        if label.address > 0xffff {
+            continue;
+        }
+        let entry = names
+            .entry(label.address as Address16)
+            .or_insert_with(|| name.clone());
+        if name < entry {
+            *entry = name.clone();
+        }
+    }
+    names
+}
+
+fn registers(cpu: &Cpu) -> String {
+    format!(
+        "A={:02x} X={:02x} Y={:02x} SP={:02x} P={}",
+        cpu.a,
+        cpu.x,
+        cpu.y,
+        cpu.sp,
+        flags_to_str(cpu.p)
+    )
+}
+
+fn trace_line(cpu: &Cpu, executed: &Executed, names: &HashMap<Address16, String>) -> String {
+    let operand = |address: Address16| match names.get(&address) {
+        Some(name) => name.clone(),
+        None => format!("${:04x}", address),
+    };
+
+    let mut text = String::new();
+    if let Some(name) = names.get(&executed.address) {
+        text.push_str(&format!("{}:\n", name));
+    }
+
+    let bytes: Vec<String> = executed
+        .instruction
+        .bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    let instruction =
+        format_instruction(&executed.instruction, executed.address as usize, &operand);
+    text.push_str(&format!(
+        "{:04x}  {:<8}  {:<16}  {}  {:>2} {}",
+        executed.address,
+        bytes.join(" "),
+        instruction,
+        registers(cpu),
+        executed.cycles,
+        cpu.cycles
+    ));
+    text
+}
+
+pub fn stop_reason(stop: Stop, pc: Address16) -> String {
+    match stop {
+        Stop::Break => format!("brk at ${:04x}", pc),
+        Stop::Stopped => format!("stp at ${:04x}", pc),
+        Stop::Waiting => format!("wai at ${:04x}", pc),
+        Stop::Returned => format!("rts to the caller at ${:04x}", pc),
+        Stop::CycleLimit => format!("cycle limit at ${:04x}", pc),
+        Stop::UnknownOpcode(opcode) => format!("unknown opcode ${:02x} at ${:04x}", opcode, pc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::assemble;
+    use super::label_names;
+    use super::start_address;
+    use super::trace_line;
+    use super::RunConfig;
+    use crate::image::Segment;
+    use crate::pass1::Program;
+    use crate::simulator::Cpu;
+    use crate::simulator::Stop;
+
+    fn assembled(source: &str) -> (Program, Vec<Segment>) {
+        if let Ok(assembled) = assemble(source, None) {
+            assembled
+        } else {
+            panic!("Did not expect the program to fail to assemble");
+        }
+    }
+
+    const PROGRAM: &str = "
+        .org $8000
+        reset:
+            ldx #$03
+        loop:
+            dex
+            bne loop
+            brk
+        other:
+            lda #$01
+            stp
+        .vectors reset=reset
+    ";
+
+    #[test]
+    fn test_run_from_reset() {
+        let (program, segments) = assembled(PROGRAM);
+        let mut cpu = Cpu::new();
+        cpu.load(&segments);
+        if let Ok(address) = start_address(&program, &segments, None) {
+            cpu.pc = address;
+        } else {
+            panic!("Did not expect to be missing a reset vector");
+        }
+
+        let names = label_names(&program);
+        let mut trace = Vec::new();
+        let stop = cpu.run(100, |cpu, executed| {
+            trace.push(trace_line(cpu, executed, &names))
+        });
+        assert_eq!(stop, Stop::Break);
+        assert_eq!(trace.len(), 7);
+        assert!(trace[0].starts_with("reset:\n8000  a2 03     ldx #$03"));
+        assert!(trace[2].contains("bne loop"));
+        assert!(trace[0].contains("A=00 X=03 Y=00 SP=ff P=nv-bdIzc"));
+    }
+
+    #[test]
+    fn test_run_from_label() {
+        let (program, segments) = assembled(PROGRAM);
+        let mut cpu = Cpu::new();
+        cpu.load(&segments);
+        cpu.pc = start_address(&program, &segments, Some("other")).unwrap_or(0);
+        assert_eq!(cpu.run(100, |_, _| {}), Stop::Stopped);
+        assert_eq!(cpu.a, 0x01);
+
+        assert!(start_address(&program, &segments, Some("nowhere")).is_err());
+        assert_eq!(
+            start_address(&program, &segments, Some("$8000")).unwrap_or(0),
+            0x8000
+        );
+    }
+
+    #[test]
+    fn test_no_reset_vector() {
+        let (program, segments) = assembled(".org $1000\nnop\n");
+        assert!(start_address(&program, &segments, None).is_err());
+    }
+
+    #[test]
+    fn test_run_config() {
+        let mut args: Vec<String> = ["kasm", "run", "--start", "main", "--cycles", "500", "a.s"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Ok(config) = RunConfig::new(&mut args) {
+            assert_eq!(config.source_file, "a.s");
+            assert_eq!(config.start, Some("main".to_string()));
+            assert_eq!(config.limit, 500);
+        } else {
+            panic!("Did not expect to fail parsing the arguments");
+        }
+
+        let mut args: Vec<String> = vec!["kasm".to_string(), "run".to_string()];
+        assert!(RunConfig::new(&mut args).is_err());
+    }
+}
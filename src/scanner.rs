@@ -1,13 +1,19 @@
-type Line = u16;
+pub type Line = u16;
+pub type FileId = usize; // Which file a line came from, 0 for the source itself
 
 pub struct SourceLine {
     pub line: String,
     pub line_number: Line,
+    pub file: FileId,
 }
 
 impl SourceLine {
     fn new(line: String, line_number: Line) -> SourceLine {
-        SourceLine { line, line_number }
+        SourceLine {
+            line,
+            line_number,
+            file: 0,
+        }
     }
 }
 
@@ -0,0 +1,609 @@
+// A 65C02 interpreter, decoding with the same tables the assembler uses
+use crate::disassembler::Address16;
+use crate::disassembler::Instruction;
+use crate::image::Segment;
+use crate::instructions::address_mode_length;
+use crate::instructions::generate_instruction_set;
+use crate::instructions::generate_opcode_table;
+use crate::instructions::instruction_cycles;
+use crate::instructions::mnemonic_to_str;
+use crate::instructions::AddressMode;
+use crate::instructions::Mnemonic;
+use crate::instructions::OpcodeMap;
+use crate::pass1::VECTORS;
+
+pub const CARRY: u8 = 0x01;
+pub const ZERO: u8 = 0x02;
+pub const INTERRUPT: u8 = 0x04;
+pub const DECIMAL: u8 = 0x08;
+pub const BREAK: u8 = 0x10;
+pub const UNUSED: u8 = 0x20;
+pub const OVERFLOW: u8 = 0x40;
+pub const NEGATIVE: u8 = 0x80;
+
+const STACK: Address16 = 0x0100;
+pub const RESET_VECTOR: Address16 = VECTORS as Address16 + 2;
+
+// Why the simulator stopped
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Stop {
+    Break,             // brk
+    Stopped,           // stp
+    Waiting,           // wai, with nothing to wake us up
+    Returned,          // rts with nothing on the stack
+    CycleLimit,        // Ran for too long
+    UnknownOpcode(u8), // Not a 65C02 instruction
+}
+
+// What one step did
+pub struct Executed {
+    pub address: Address16,
+    pub instruction: Instruction,
+    pub cycles: u8,
+}
+
+pub struct Cpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: Address16,
+    pub p: u8,
+    pub cycles: u64,
+    pub memory: Vec<u8>,
+    opcodes: OpcodeMap,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+impl Cpu {
+    pub fn new() -> Cpu {
+        Cpu {
+            a: 0,
+            x: 0,
+            y: 0,
+            sp: 0xff,
+            pc: 0,
+            p: UNUSED | INTERRUPT,
+            cycles: 0,
+            memory: vec![0; 0x10000],
+            opcodes: generate_opcode_table(&generate_instruction_set()),
+        }
+    }
+
+    // Anything past the first 64K is out of sight
+    pub fn load(&mut self, segments: &[Segment]) {
+        for segment in segments {
+            for (offset, byte) in segment.data.iter().enumerate() {
+                if let Some(memory) = self.memory.get_mut(segment.address as usize + offset) {
+                    *memory = *byte;
+                }
+            }
+        }
+    }
+
+    pub fn read(&self, address: Address16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    pub fn write(&mut self, address: Address16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    pub fn read_word(&self, address: Address16) -> Address16 {
+        u16::from_le_bytes([self.read(address), self.read(address.wrapping_add(1))])
+    }
+
+    // Pointers in the zeropage wrap around within it
+    fn read_zeropage_word(&self, address: u8) -> Address16 {
+        u16::from_le_bytes([
+            self.read(address as Address16),
+            self.read(address.wrapping_add(1) as Address16),
+        ])
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(STACK + self.sp as Address16, value);
+        self.sp = self.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.sp = self.sp.wrapping_add(1);
+        self.read(STACK + self.sp as Address16)
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) -> u8 {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+        value
+    }
+
+    // Run until something stops us, calling trace after every instruction
+    pub fn run(&mut self, limit: u64, mut trace: impl FnMut(&Cpu, &Executed)) -> Stop {
+        loop {
+            if self.cycles >= limit {
+                return Stop::CycleLimit;
+            }
+            match self.step() {
+                Ok(executed) => trace(self, &executed),
+                Err(stop) => return stop,
+            }
+        }
+    }
+
+    // Execute the instruction at pc, or say why we can't
+    pub fn step(&mut self) -> Result<Executed, Stop> {
+        let address = self.pc;
+        let opcode = self.read(address);
+        let key = match self.opcodes.get(&opcode) {
+            Some(key) => *key,
+            None => return Err(Stop::UnknownOpcode(opcode)),
+        };
+
+        match key.mnemonic {
+            Mnemonic::BRK => return Err(Stop::Break),
+            Mnemonic::STP => return Err(Stop::Stopped),
+            Mnemonic::WAI => return Err(Stop::Waiting),
+            Mnemonic::RTS if self.sp == 0xff => return Err(Stop::Returned),
+            _ => {}
+        }
+
+        let length = address_mode_length(key.address_mode);
+        let bytes = (0..length)
+            .map(|offset| self.read(address.wrapping_add(offset)))
+            .collect();
+        let instruction = Instruction { key, bytes };
+        self.pc = address.wrapping_add(length);
+
+        let timing = instruction_cycles(key);
+        let mut cycles = timing.base;
+
+        // Work out where the operand lives
+        let operand = |offset: usize| instruction.bytes[offset];
+        let (target, crossed) = match key.address_mode {
+            AddressMode::Zeropage => (operand(1) as Address16, false),
+            AddressMode::ZeropageX => (operand(1).wrapping_add(self.x) as Address16, false),
+            AddressMode::ZeropageY => (operand(1).wrapping_add(self.y) as Address16, false),
+            AddressMode::Absolute => (instruction.word(), false),
+            AddressMode::AbsoluteX => indexed(instruction.word(), self.x),
+            AddressMode::AbsoluteY => indexed(instruction.word(), self.y),
+            AddressMode::Indirect => (self.read_word(instruction.word()), false),
+            AddressMode::IndirectX => (
+                self.read_zeropage_word(operand(1).wrapping_add(self.x)),
+                false,
+            ),
+            AddressMode::IndirectY => indexed(self.read_zeropage_word(operand(1)), self.y),
+            AddressMode::ZeropageIndirect => (self.read_zeropage_word(operand(1)), false),
+            AddressMode::AbsoluteIndexedIndirect => (
+                self.read_word(instruction.word().wrapping_add(self.x as Address16)),
+                false,
+            ),
+            AddressMode::Relative => (instruction.branch_target(address as usize), false),
+            AddressMode::ZeropageRelative => (operand(1) as Address16, false),
+            AddressMode::Implied | AddressMode::Immediate => (0, false),
+            // The 65816's modes, whose opcodes aren't in the table
+            AddressMode::AbsoluteLong
+            | AddressMode::AbsoluteLongX
+            | AddressMode::DirectIndirectLong
+            | AddressMode::DirectIndirectLongY
+            | AddressMode::StackRelative
+            | AddressMode::StackRelativeIndirectY
+            | AddressMode::BlockMove
+            | AddressMode::RelativeLong
+            | AddressMode::AbsoluteIndirectLong => return Err(Stop::UnknownOpcode(opcode)),
+        };
+        if crossed && timing.page_cross {
+            cycles += 1;
+        }
+
+        // Immediates and the accumulator aren't in memory
+        let value = match key.address_mode {
+            AddressMode::Immediate => operand(1),
+            AddressMode::Implied => self.a,
+            AddressMode::Relative => 0,
+            _ => self.read(target),
+        };
+
+        let bit = mnemonic_to_str(key.mnemonic)
+            .chars()
+            .last()
+            .and_then(|c| c.to_digit(10))
+            .unwrap_or(0);
+
+        let mut branch = None;
+        let mut result = None; // What read-modify-write instructions write back
+
+        match key.mnemonic {
+            Mnemonic::ADC => self.adc(value, &mut cycles),
+            Mnemonic::SBC => self.sbc(value, &mut cycles),
+            Mnemonic::AND => self.a = self.set_nz(self.a & value),
+            Mnemonic::ORA => self.a = self.set_nz(self.a | value),
+            Mnemonic::EOR => self.a = self.set_nz(self.a ^ value),
+            Mnemonic::BIT => {
+                self.set_flag(ZERO, self.a & value == 0);
+                if key.address_mode != AddressMode::Immediate {
+                    self.set_flag(NEGATIVE, value & 0x80 != 0);
+                    self.set_flag(OVERFLOW, value & 0x40 != 0);
+                }
+            }
+            Mnemonic::CMP => self.compare(self.a, value),
+            Mnemonic::CPX => self.compare(self.x, value),
+            Mnemonic::CPY => self.compare(self.y, value),
+            Mnemonic::LDA => self.a = self.set_nz(value),
+            Mnemonic::LDX => self.x = self.set_nz(value),
+            Mnemonic::LDY => self.y = self.set_nz(value),
+            Mnemonic::STA => self.write(target, self.a),
+            Mnemonic::STX => self.write(target, self.x),
+            Mnemonic::STY => self.write(target, self.y),
+            Mnemonic::STZ => self.write(target, 0),
+            Mnemonic::ASL => {
+                self.set_flag(CARRY, value & 0x80 != 0);
+                result = Some(self.set_nz(value << 1));
+            }
+            Mnemonic::LSR => {
+                self.set_flag(CARRY, value & 0x01 != 0);
+                result = Some(self.set_nz(value >> 1));
+            }
+            Mnemonic::ROL => {
+                let carry = self.p & CARRY;
+                self.set_flag(CARRY, value & 0x80 != 0);
+                result = Some(self.set_nz((value << 1) | carry));
+            }
+            Mnemonic::ROR => {
+                let carry = (self.p & CARRY) << 7;
+                self.set_flag(CARRY, value & 0x01 != 0);
+                result = Some(self.set_nz((value >> 1) | carry));
+            }
+            Mnemonic::INC => result = Some(self.set_nz(value.wrapping_add(1))),
+            Mnemonic::DEC => result = Some(self.set_nz(value.wrapping_sub(1))),
+            Mnemonic::TRB => {
+                self.set_flag(ZERO, self.a & value == 0);
+                result = Some(value & !self.a);
+            }
+            Mnemonic::TSB => {
+                self.set_flag(ZERO, self.a & value == 0);
+                result = Some(value | self.a);
+            }
+            Mnemonic::INX => self.x = self.set_nz(self.x.wrapping_add(1)),
+            Mnemonic::INY => self.y = self.set_nz(self.y.wrapping_add(1)),
+            Mnemonic::DEX => self.x = self.set_nz(self.x.wrapping_sub(1)),
+            Mnemonic::DEY => self.y = self.set_nz(self.y.wrapping_sub(1)),
+            Mnemonic::TAX => self.x = self.set_nz(self.a),
+            Mnemonic::TAY => self.y = self.set_nz(self.a),
+            Mnemonic::TXA => self.a = self.set_nz(self.x),
+            Mnemonic::TYA => self.a = self.set_nz(self.y),
+            Mnemonic::TSX => self.x = self.set_nz(self.sp),
+            Mnemonic::TXS => self.sp = self.x,
+            Mnemonic::PHA => self.push(self.a),
+            Mnemonic::PHX => self.push(self.x),
+            Mnemonic::PHY => self.push(self.y),
+            Mnemonic::PHP => self.push(self.p | BREAK | UNUSED),
+            Mnemonic::PLA => {
+                let value = self.pull();
+                self.a = self.set_nz(value);
+            }
+            Mnemonic::PLX => {
+                let value = self.pull();
+                self.x = self.set_nz(value);
+            }
+            Mnemonic::PLY => {
+                let value = self.pull();
+                self.y = self.set_nz(value);
+            }
+            Mnemonic::PLP => self.p = (self.pull() | UNUSED) & !BREAK,
+            Mnemonic::CLC => self.set_flag(CARRY, false),
+            Mnemonic::CLD => self.set_flag(DECIMAL, false),
+            Mnemonic::CLI => self.set_flag(INTERRUPT, false),
+            Mnemonic::CLV => self.set_flag(OVERFLOW, false),
+            Mnemonic::SEC => self.set_flag(CARRY, true),
+            Mnemonic::SED => self.set_flag(DECIMAL, true),
+            Mnemonic::SEI => self.set_flag(INTERRUPT, true),
+            Mnemonic::JMP => self.pc = target,
+            Mnemonic::JSR => {
+                let [lo, hi] = self.pc.wrapping_sub(1).to_le_bytes();
+                self.push(hi);
+                self.push(lo);
+                self.pc = target;
+            }
+            Mnemonic::RTS => {
+                let lo = self.pull();
+                let hi = self.pull();
+                self.pc = u16::from_le_bytes([lo, hi]).wrapping_add(1);
+            }
+            Mnemonic::RTI => {
+                self.p = (self.pull() | UNUSED) & !BREAK;
+                let lo = self.pull();
+                let hi = self.pull();
+                self.pc = u16::from_le_bytes([lo, hi]);
+            }
+            Mnemonic::BCC => branch = Some(self.p & CARRY == 0),
+            Mnemonic::BCS => branch = Some(self.p & CARRY != 0),
+            Mnemonic::BNE => branch = Some(self.p & ZERO == 0),
+            Mnemonic::BEQ => branch = Some(self.p & ZERO != 0),
+            Mnemonic::BPL => branch = Some(self.p & NEGATIVE == 0),
+            Mnemonic::BMI => branch = Some(self.p & NEGATIVE != 0),
+            Mnemonic::BVC => branch = Some(self.p & OVERFLOW == 0),
+            Mnemonic::BVS => branch = Some(self.p & OVERFLOW != 0),
+            Mnemonic::BRA => branch = Some(true),
+            Mnemonic::RMB0
+            | Mnemonic::RMB1
+            | Mnemonic::RMB2
+            | Mnemonic::RMB3
+            | Mnemonic::RMB4
+            | Mnemonic::RMB5
+            | Mnemonic::RMB6
+            | Mnemonic::RMB7 => result = Some(value & !(1 << bit)),
+            Mnemonic::SMB0
+            | Mnemonic::SMB1
+            | Mnemonic::SMB2
+            | Mnemonic::SMB3
+            | Mnemonic::SMB4
+            | Mnemonic::SMB5
+            | Mnemonic::SMB6
+            | Mnemonic::SMB7 => result = Some(value | (1 << bit)),
+            Mnemonic::BBR0
+            | Mnemonic::BBR1
+            | Mnemonic::BBR2
+            | Mnemonic::BBR3
+            | Mnemonic::BBR4
+            | Mnemonic::BBR5
+            | Mnemonic::BBR6
+            | Mnemonic::BBR7 => branch = Some(value & (1 << bit) == 0),
+            Mnemonic::BBS0
+            | Mnemonic::BBS1
+            | Mnemonic::BBS2
+            | Mnemonic::BBS3
+            | Mnemonic::BBS4
+            | Mnemonic::BBS5
+            | Mnemonic::BBS6
+            | Mnemonic::BBS7 => branch = Some(value & (1 << bit) != 0),
+            Mnemonic::NOP | Mnemonic::BRK | Mnemonic::STP | Mnemonic::WAI => {}
+            // The 65816's instructions, which never make it into the table either
+            Mnemonic::BRL
+            | Mnemonic::COP
+            | Mnemonic::JML
+            | Mnemonic::JSL
+            | Mnemonic::MVN
+            | Mnemonic::MVP
+            | Mnemonic::PEA
+            | Mnemonic::PEI
+            | Mnemonic::PER
+            | Mnemonic::PHB
+            | Mnemonic::PHD
+            | Mnemonic::PHK
+            | Mnemonic::PLB
+            | Mnemonic::PLD
+            | Mnemonic::REP
+            | Mnemonic::RTL
+            | Mnemonic::SEP
+            | Mnemonic::TCD
+            | Mnemonic::TCS
+            | Mnemonic::TDC
+            | Mnemonic::TSC
+            | Mnemonic::TXY
+            | Mnemonic::TYX
+            | Mnemonic::WDM
+            | Mnemonic::XBA
+            | Mnemonic::XCE => return Err(Stop::UnknownOpcode(opcode)),
+            // Nor do the NMOS undocumented ones, the 65C02 made them nops
+            Mnemonic::ALR
+            | Mnemonic::ANC
+            | Mnemonic::ARR
+            | Mnemonic::DCP
+            | Mnemonic::ISC
+            | Mnemonic::LAX
+            | Mnemonic::RLA
+            | Mnemonic::RRA
+            | Mnemonic::SAX
+            | Mnemonic::SBX
+            | Mnemonic::SLO
+            | Mnemonic::SRE => return Err(Stop::UnknownOpcode(opcode)),
+        }
+
+        if let Some(result) = result {
+            match key.address_mode {
+                AddressMode::Implied => self.a = result,
+                _ => self.write(target, result),
+            }
+        }
+
+        if branch == Some(true) {
+            let destination = instruction.branch_target(address as usize);
+            // bra's base already counts it being taken
+            if timing.branch {
+                cycles += 1;
+            }
+            if destination & 0xff00 != self.pc & 0xff00 {
+                cycles += 1;
+            }
+            self.pc = destination;
+        }
+
+        self.cycles += cycles as u64;
+        Ok(Executed {
+            address,
+            instruction,
+            cycles,
+        })
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CARRY, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn adc(&mut self, value: u8, cycles: &mut u8) {
+        let a = self.a as u16;
+        let v = value as u16;
+        let carry = (self.p & CARRY) as u16;
+        let binary = a + v + carry;
+        self.set_flag(OVERFLOW, (!(a ^ v) & (a ^ binary) & 0x80) != 0);
+
+        if self.p & DECIMAL == 0 {
+            self.set_flag(CARRY, binary > 0xff);
+            self.a = self.set_nz(binary as u8);
+            return;
+        }
+
+        // The 65C02 takes a cycle longer in decimal mode, but sets N and Z properly
+        *cycles += 1;
+        let mut lo = (a & 0x0f) + (v & 0x0f) + carry;
+        let mut hi = (a & 0xf0) + (v & 0xf0);
+        if lo > 0x09 {
+            lo += 0x06;
+        }
+        if lo > 0x0f {
+            hi += 0x10;
+        }
+        if hi > 0x90 {
+            hi += 0x60;
+        }
+        self.set_flag(CARRY, hi > 0xff);
+        self.a = self.set_nz(((hi & 0xf0) | (lo & 0x0f)) as u8);
+    }
+
+    fn sbc(&mut self, value: u8, cycles: &mut u8) {
+        let a = self.a as i16;
+        let v = value as i16;
+        let borrow = 1 - (self.p & CARRY) as i16;
+        let binary = a - v - borrow;
+        self.set_flag(CARRY, binary >= 0);
+        self.set_flag(OVERFLOW, ((a ^ v) & (a ^ binary) & 0x80) != 0);
+
+        if self.p & DECIMAL == 0 {
+            self.a = self.set_nz(binary as u8);
+            return;
+        }
+
+        *cycles += 1;
+        let mut lo = (a & 0x0f) - (v & 0x0f) - borrow;
+        let mut hi = (a & 0xf0) - (v & 0xf0);
+        if lo < 0 {
+            lo -= 0x06;
+            hi -= 0x10;
+        }
+        if hi < 0 {
+            hi -= 0x60;
+        }
+        self.a = self.set_nz(((hi & 0xf0) | (lo & 0x0f)) as u8);
+    }
+}
+
+// Indexing can carry into the next page, which costs a cycle
+fn indexed(base: Address16, index: u8) -> (Address16, bool) {
+    let address = base.wrapping_add(index as Address16);
+    (address, address & 0xff00 != base & 0xff00)
+}
+
+// NV-BDIZC, upper case when set
+pub fn flags_to_str(p: u8) -> String {
+    "nv-bdizc"
+        .chars()
+        .enumerate()
+        .map(|(index, flag)| {
+            if flag != '-' && p & (0x80 >> index) != 0 {
+                flag.to_ascii_uppercase()
+            } else {
+                flag
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::flags_to_str;
+    use super::Cpu;
+    use super::Stop;
+    use super::CARRY;
+    use super::DECIMAL;
+    use crate::image::Segment;
+
+    fn run(address: u16, code: &[u8]) -> (Cpu, Stop) {
+        let mut cpu = Cpu::new();
+        cpu.load(&[Segment {
+            address: address as u32,
+            data: code.to_vec(),
+        }]);
+        cpu.pc = address;
+        let stop = cpu.run(1000, |_, _| {});
+        (cpu, stop)
+    }
+
+    #[test]
+    fn test_loop() {
+        // ldx #$05, loop: dex, bne loop, brk
+        let (cpu, stop) = run(0x1000, &[0xa2, 0x05, 0xca, 0xd0, 0xfd, 0x00]);
+        assert_eq!(stop, Stop::Break);
+        assert_eq!(cpu.x, 0);
+        assert_eq!(cpu.pc, 0x1005);
+        // 2 for ldx, then 5 dex and 4 taken branches and 1 not taken
+        assert_eq!(cpu.cycles, 2 + 5 * 2 + 4 * 3 + 2);
+    }
+
+    #[test]
+    fn test_bra() {
+        // bra +1, brk, stp: always taken, so 3 and never the 2 of a branch that isn't
+        let (cpu, stop) = run(0x1000, &[0x80, 0x01, 0x00, 0xdb]);
+        assert_eq!(stop, Stop::Stopped);
+        assert_eq!(cpu.cycles, 3);
+
+        // The same, crossing into the next page
+        let (cpu, _) = run(0x10fd, &[0x80, 0x01, 0x00, 0xdb]);
+        assert_eq!(cpu.cycles, 4);
+    }
+
+    #[test]
+    fn test_subroutine() {
+        // jsr double, stp, double: asl, rts
+        let (cpu, stop) = run(0x1000, &[0xa9, 0x21, 0x20, 0x06, 0x10, 0xdb, 0x0a, 0x60]);
+        assert_eq!(stop, Stop::Stopped);
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(cpu.sp, 0xff);
+    }
+
+    #[test]
+    fn test_return_ends_the_run() {
+        // sta $10, stz $11, inc $11, rts
+        let (cpu, stop) = run(0x1000, &[0x85, 0x10, 0x64, 0x11, 0xe6, 0x11, 0x60]);
+        assert_eq!(stop, Stop::Returned);
+        assert_eq!(cpu.memory[0x11], 0x01);
+    }
+
+    #[test]
+    fn test_decimal() {
+        // sed, clc, lda #$19, adc #$28, brk
+        let (cpu, _) = run(0x1000, &[0xf8, 0x18, 0xa9, 0x19, 0x69, 0x28, 0x00]);
+        assert_eq!(cpu.a, 0x47);
+        assert_eq!(cpu.p & (CARRY | DECIMAL), DECIMAL);
+        // sed, sec, lda #$10, sbc #$01, brk
+        let (cpu, _) = run(0x1000, &[0xf8, 0x38, 0xa9, 0x10, 0xe9, 0x01, 0x00]);
+        assert_eq!(cpu.a, 0x09);
+        assert_eq!(cpu.p & CARRY, CARRY);
+    }
+
+    #[test]
+    fn test_cycle_limit() {
+        // loop: bra loop
+        let (_, stop) = run(0x1000, &[0x80, 0xfe]);
+        assert_eq!(stop, Stop::CycleLimit);
+    }
+
+    #[test]
+    fn test_flags_to_str() {
+        assert_eq!(flags_to_str(0x24), "nv-bdIzc");
+        assert_eq!(flags_to_str(0xc3), "NV-bdiZC");
+    }
+}
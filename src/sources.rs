@@ -0,0 +1,228 @@
+// Where source files come from. The assembler only asks for files by name,
+// so an editor can hand it unsaved buffers and tests can use a HashMap.
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::errors::LocatedError;
+use crate::files;
+use crate::scanner::scanner;
+use crate::scanner::FileId;
+use crate::scanner::Line;
+use crate::scanner::SourceLine;
+use crate::scanner::SourceTable;
+
+const INCLUDE_DEPTH: usize = 16; // Deep enough for anyone, and stops include loops
+
+pub trait SourceProvider {
+    fn read_source(&self, name: &str) -> Result<String, Error>;
+    fn read_binary(&self, name: &str) -> Result<Vec<u8>, Error>;
+}
+
+// The real filesystem, names are paths
+pub struct FileSystem;
+
+impl SourceProvider for FileSystem {
+    fn read_source(&self, name: &str) -> Result<String, Error> {
+        files::read_source(name)
+    }
+
+    fn read_binary(&self, name: &str) -> Result<Vec<u8>, Error> {
+        files::read_binary(name)
+    }
+}
+
+// Files held in memory, by name
+impl SourceProvider for HashMap<String, Vec<u8>> {
+    fn read_source(&self, name: &str) -> Result<String, Error> {
+        match String::from_utf8(self.read_binary(name)?) {
+            Ok(source) => Ok(source),
+            Err(_) => Err(error(
+                ErrorCode::FileRead,
+                format!("Couldn't read {}: not UTF-8", name),
+            )),
+        }
+    }
+
+    fn read_binary(&self, name: &str) -> Result<Vec<u8>, Error> {
+        match self.get(name) {
+            Some(bytes) => Ok(bytes.clone()),
+            None => Err(error(
+                ErrorCode::FileOpen,
+                format!("Couldn't open {}: not found", name),
+            )),
+        }
+    }
+}
+
+// A file the lines came from. A line's file id is where its file is in the
+// list, and the source itself is first, with an empty name if it has none.
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    pub included_at: Option<(FileId, Line)>, // The .include that brought it in
+}
+
+// Scan the source, replacing each .include with the lines of the file it
+// names and each .incbin with a .byte of the file's contents. Every file
+// read is added to files, and its lines keep their own line numbers. Names
+// are relative to the file doing the including, when it has a name.
+pub fn expand(
+    source: &str,
+    name: Option<&str>,
+    provider: &dyn SourceProvider,
+    files: &mut Vec<SourceFile>,
+) -> Result<SourceTable, LocatedError> {
+    files.clear();
+    files.push(SourceFile {
+        name: name.unwrap_or("").to_string(),
+        text: source.to_string(),
+        included_at: None,
+    });
+    expand_nested(scanner(source), provider, files, 0)
+}
+
+fn expand_nested(
+    source: SourceTable,
+    provider: &dyn SourceProvider,
+    files: &mut Vec<SourceFile>,
+    depth: usize,
+) -> Result<SourceTable, LocatedError> {
+    let mut expanded = SourceTable::new();
+
+    for line in source {
+        let (line_number, from) = (line.line_number, line.file);
+        let (directive, file) = match line.line.split_once(char::is_whitespace) {
+            Some((directive @ (".include" | ".incbin"), file)) => (directive, file),
+            _ => {
+                expanded.push(line);
+                continue;
+            }
+        };
+        let located = |err: Error| (err, Some((from, line_number)));
+
+        let file = match file
+            .trim()
+            .strip_prefix('"')
+            .and_then(|f| f.strip_suffix('"'))
+        {
+            Some(file) if !file.is_empty() => resolve(&files[from].name, file),
+            _ => {
+                return Err(located(error(
+                    ErrorCode::MalformedInclude,
+                    format!("Expected a quoted file name at line {}", line_number),
+                )))
+            }
+        };
+
+        if directive == ".incbin" {
+            let bytes = provider.read_binary(&file).map_err(located)?;
+            if !bytes.is_empty() {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+                expanded.push(SourceLine {
+                    line: format!(".byte {}", bytes.join(",")),
+                    line_number,
+                    file: from,
+                });
+            }
+            continue;
+        }
+
+        if depth == INCLUDE_DEPTH {
+            return Err(located(error(
+                ErrorCode::MalformedInclude,
+                format!(
+                    "Includes nested more than {} deep at line {}, is {} including itself?",
+                    INCLUDE_DEPTH, line_number, file
+                ),
+            )));
+        }
+        let text = provider.read_source(&file).map_err(located)?;
+        let mut included = scanner(&text);
+        for included in &mut included {
+            included.file = files.len();
+        }
+        files.push(SourceFile {
+            name: file,
+            text,
+            included_at: Some((from, line_number)),
+        });
+        expanded.append(&mut expand_nested(included, provider, files, depth + 1)?);
+    }
+
+    Ok(expanded)
+}
+
+// A name relative to the directory of the file it was found in
+fn resolve(from: &str, name: &str) -> String {
+    match Path::new(from).parent() {
+        Some(directory) if !directory.as_os_str().is_empty() && Path::new(name).is_relative() => {
+            directory.join(name).to_string_lossy().to_string()
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::expand;
+
+    fn provider() -> HashMap<String, Vec<u8>> {
+        [
+            ("lib/io.s", ".include \"macros.s\"\nputc:\n    rts\n"),
+            ("lib/macros.s", "nop\n"),
+            ("font.bin", "\x01\x02\x03"),
+            ("loop.s", ".include \"loop.s\"\n"),
+        ]
+        .iter()
+        .map(|(name, text)| (name.to_string(), text.as_bytes().to_vec()))
+        .collect()
+    }
+
+    #[test]
+    fn test_expand() {
+        let source = "start:\n.include \"lib/io.s\"\n.incbin \"font.bin\"\n";
+        let mut files = vec![];
+        let expanded = match expand(source, None, &provider(), &mut files) {
+            Ok(expanded) => expanded,
+            Err(err) => panic!("Did not expect the includes to fail: {}", (err.0).1),
+        };
+        let lines: Vec<(&str, usize, u16)> = expanded
+            .iter()
+            .map(|line| (line.line.as_str(), line.file, line.line_number))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("start:", 0, 1),
+                ("nop", 2, 1),
+                ("putc:", 1, 2),
+                ("rts", 1, 3),
+                (".byte $01,$02,$03", 0, 3),
+            ]
+        );
+
+        let names: Vec<&str> = files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, vec!["", "lib/io.s", "lib/macros.s"]);
+        assert_eq!(files[1].included_at, Some((0, 2)));
+        assert_eq!(files[2].included_at, Some((1, 1)));
+    }
+
+    #[test]
+    fn test_bad_includes() {
+        for (source, line) in &[
+            ("nop\n.include \"missing.s\"\n", (0, 2)),
+            (".incbin font.bin\n", (0, 1)),
+            (".include \"loop.s\"\n", (16, 1)),
+        ] {
+            match expand(source, None, &provider(), &mut vec![]) {
+                Ok(_) => panic!("Did not expect {} to work", source),
+                Err((_, at)) => assert_eq!(at, Some(*line)),
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Motorola S-records, as used by the KrisOS serial boot loader
+use crate::image::Segment;
+
+const BYTES_PER_RECORD: usize = 16;
+const RESET_VECTOR: u32 = 0xfffc;
+
+fn record(record_type: u8, address: u32, address_size: usize, data: &[u8]) -> String {
+    let mut bytes = vec![(address_size + data.len() + 1) as u8];
+    bytes.extend_from_slice(&address.to_be_bytes()[4 - address_size..]);
+    bytes.extend_from_slice(data);
+
+    // The checksum is the one's complement of the sum of every other byte
+    let sum = bytes.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    bytes.push(!sum);
+
+    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!("S{}{}\n", record_type, hex.concat())
+}
+
+// Use the reset vector when we have one, otherwise the lowest address
+fn entry_point(segments: &[Segment]) -> u32 {
+    let byte_at = |address: u32| {
+        segments.iter().find_map(|segment| {
+            let offset = address.checked_sub(segment.address)? as usize;
+            segment.data.get(offset).copied()
+        })
+    };
+
+    match (byte_at(RESET_VECTOR), byte_at(RESET_VECTOR + 1)) {
+        (Some(lo), Some(hi)) => u16::from_le_bytes([lo, hi]) as u32,
+        _ => segments
+            .iter()
+            .map(|segment| segment.address)
+            .min()
+            .unwrap_or(0),
+    }
+}
+
+// Only the segments are written, the gaps between them are left out
+pub fn to_srec(segments: &[Segment]) -> String {
+    let end = segments
+        .iter()
+        .map(|segment| segment.address + segment.data.len() as u32)
+        .max()
+        .unwrap_or(0);
+
+    // Pick the narrowest records that can hold every address
+    let (data_type, end_type, address_size) = if end <= 0x10000 {
+        (1, 9, 2)
+    } else if end <= 0x1000000 {
+        (2, 8, 3)
+    } else {
+        (3, 7, 4)
+    };
+
+    let mut srec = record(0, 0, 2, &[]);
+
+    for segment in segments {
+        let mut address = segment.address;
+        for chunk in segment.data.chunks(BYTES_PER_RECORD) {
+            srec.push_str(&record(data_type, address, address_size, chunk));
+            address += chunk.len() as u32;
+        }
+    }
+
+    srec.push_str(&record(end_type, entry_point(segments), address_size, &[]));
+    srec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::record;
+    use super::to_srec;
+    use crate::image::Segment;
+
+    #[test]
+    fn test_record_checksum() {
+        assert_eq!(
+            record(1, 0x0038, 2, &[0x48, 0x65, 0x6c, 0x6c, 0x6f]),
+            "S108003848656C6C6FCB\n"
+        );
+        assert_eq!(record(9, 0x0000, 2, &[]), "S9030000FC\n");
+    }
+
+    #[test]
+    fn test_to_srec() {
+        let segments = vec![
+            Segment {
+                address: 0x8000,
+                data: vec![0xa9, 0x00],
+            },
+            Segment {
+                address: 0xfffc,
+                data: vec![0x00, 0x80],
+            },
+        ];
+        assert_eq!(
+            to_srec(&segments),
+            "S0030000FC\n\
+             S1058000A900D1\n\
+             S105FFFC00807F\n\
+             S90380007C\n"
+        );
+    }
+
+    #[test]
+    fn test_entry_without_reset_vector() {
+        let segments = vec![Segment {
+            address: 0x1000,
+            data: vec![0xea],
+        }];
+        assert!(to_srec(&segments).ends_with("S9031000EC\n"));
+    }
+}
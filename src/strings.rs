@@ -5,6 +5,13 @@ macro_rules! str_to_u16 {
     };
 }
 
+// Wide enough for the 65816's 24 bit addresses
+macro_rules! str_to_u32 {
+    ($fmt:expr) => {
+        u32::from_str_radix($fmt, 16)
+    };
+}
+
 macro_rules! str_to_u8 {
     ($fmt:expr) => {
         u8::from_str_radix($fmt, 16)
@@ -0,0 +1,339 @@
+// kasm test: run the .test blocks in a program through the simulator
+use crate::disassembler::Address16;
+use crate::errors::error;
+use crate::errors::Error;
+use crate::errors::ErrorCode;
+use crate::expression::evaluate;
+use crate::expression::Number;
+use crate::image::Segment;
+use crate::pass1::Program;
+use crate::pass1::Test;
+use crate::pass1::TestStep;
+use crate::runner::assemble;
+use crate::runner::stop_reason;
+use crate::simulator::Cpu;
+use crate::simulator::Stop;
+use crate::simulator::CARRY;
+use crate::simulator::DECIMAL;
+use crate::simulator::INTERRUPT;
+use crate::simulator::NEGATIVE;
+use crate::simulator::OVERFLOW;
+use crate::simulator::ZERO;
+use crate::sources::FileSystem;
+use crate::sources::SourceProvider;
+
+const CYCLE_LIMIT_DEFAULT: u64 = 100_000;
+
+pub struct TestConfig {
+    source_file: String,
+    filter: Option<String>, // Only run tests with this in their name
+    limit: u64,             // Cycles each .call may take
+}
+
+pub fn usage(cmd: &str) {
+    println!(
+        "usage: {} test [--cycles <limit>] <sourcefile> [<filter>]",
+        cmd
+    );
+}
+
+impl TestConfig {
+    pub fn new(args: &mut Vec<String>) -> Result<TestConfig, (ErrorCode, &'static str)> {
+        let mut args = args.drain(2..); // Remove the command and "test"
+        let mut source_file = None;
+        let mut filter = None;
+        let mut limit = CYCLE_LIMIT_DEFAULT;
+
+        while let Some(val) = args.next() {
+            if val == "--cycles" {
+                match args.next().and_then(|cycles| cycles.parse::<u64>().ok()) {
+                    Some(cycles) => limit = cycles,
+                    None => return Err((ErrorCode::Usage, "Invalid cycle limit")),
+                }
+            } else if source_file.is_none() {
+                source_file = Some(val);
+            } else if filter.is_none() {
+                filter = Some(val);
+            } else {
+                return Err((ErrorCode::Usage, "Only one filter can be given"));
+            }
+        }
+
+        match source_file {
+            Some(source_file) => Ok(TestConfig {
+                source_file,
+                filter,
+                limit,
+            }),
+            None => Err((ErrorCode::Usage, "Missing arguments")),
+        }
+    }
+}
+
+pub fn run(config: &TestConfig) -> Result<(), Error> {
+    let source = FileSystem.read_source(&config.source_file)?;
+    let (program, segments) = assemble(&source, Some(&config.source_file))?;
+    for message in &program.messages {
+        println!("{}", message);
+    }
+
+    let tests: Vec<&Test> = program
+        .tests
+        .iter()
+        .filter(|test| match &config.filter {
+            Some(filter) => test.name.contains(filter.as_str()),
+            None => true,
+        })
+        .collect();
+    let filtered = program.tests.len() - tests.len();
+
+    println!("running {} tests", tests.len());
+    let mut failures = Vec::new();
+    for test in &tests {
+        match run_test(&program, &segments, test, config.limit) {
+            Ok(()) => println!("test {} ... ok", test.name),
+            Err(failure) => {
+                println!("test {} ... FAILED", test.name);
+                failures.push((test.name.as_str(), failure));
+            }
+        }
+    }
+
+    if !failures.is_empty() {
+        println!("\nfailures:");
+        for (name, failure) in &failures {
+            println!("\n---- {} ----\n{}", name, failure);
+        }
+        println!();
+    }
+
+    let summary = format!(
+        "{} passed; {} failed; {} filtered out",
+        tests.len() - failures.len(),
+        failures.len(),
+        filtered
+    );
+    if failures.is_empty() {
+        println!("\ntest result: ok. {}", summary);
+        Ok(())
+    } else {
+        Err(error(
+            ErrorCode::TestFailed,
+            format!("test result: FAILED. {}", summary),
+        ))
+    }
+}
+
+// Run the steps of a test on a freshly loaded image, saying what went wrong
+fn run_test(
+    program: &Program,
+    segments: &[Segment],
+    test: &Test,
+    limit: u64,
+) -> Result<(), String> {
+    let lookup = |label: &str| {
+        program
+            .symbol_table
+            .get(label)
+            .map(|label| label.address as Number)
+    };
+    let value = |expression: &str, max: Number| match evaluate(expression, 0, &lookup) {
+        Ok(value) if (0..=max).contains(&value) => Ok(value),
+        Ok(value) => Err(format!("{} is too big for ${:x}", value, max)),
+        Err(err) => Err(err.1),
+    };
+
+    let mut cpu = Cpu::new();
+    cpu.load(segments);
+
+    for (line, step) in &test.steps {
+        let at_line = |message: String| format!("Line {}: {}", line, message);
+        match step {
+            TestStep::Given(pairs) => {
+                for (name, expression) in pairs {
+                    let max = if flag(name).is_some() { 1 } else { 0xff };
+                    let byte = value(expression, max).map_err(at_line)? as u8;
+                    match register(&mut cpu, name) {
+                        Some(register) => *register = byte,
+                        None => match flag(name) {
+                            Some(flag) => cpu.p = cpu.p & !flag | if byte == 1 { flag } else { 0 },
+                            None => {
+                                let address = value(name, 0xffff).map_err(at_line)?;
+                                cpu.write(address as Address16, byte);
+                            }
+                        },
+                    }
+                }
+            }
+            TestStep::Call(routine) => {
+                let address = value(routine, 0xffff).map_err(at_line)? as Address16;
+                cpu.pc = address;
+                cpu.sp = 0xff;
+                cpu.cycles = 0;
+                // Only an rts back to the test counts, running off into
+                // empty memory hits a brk and shouldn't pass
+                match cpu.run(limit, |_, _| {}) {
+                    Stop::Returned => {}
+                    Stop::CycleLimit => {
+                        return Err(at_line(format!(
+                            "{} still running after {} cycles at ${:04x}",
+                            routine, cpu.cycles, cpu.pc
+                        )))
+                    }
+                    stop => {
+                        return Err(at_line(format!(
+                            "{} didn't return: {}",
+                            routine,
+                            stop_reason(stop, cpu.pc)
+                        )))
+                    }
+                }
+            }
+            TestStep::Expect(pairs) => {
+                let mut mismatches = Vec::new();
+                for (name, expression) in pairs {
+                    let max = if flag(name).is_some() { 1 } else { 0xff };
+                    let expected = value(expression, max).map_err(at_line)? as u8;
+                    let actual = match register(&mut cpu, name) {
+                        Some(register) => *register,
+                        None => match flag(name) {
+                            Some(flag) => (cpu.p & flag != 0) as u8,
+                            None => cpu.read(value(name, 0xffff).map_err(at_line)? as Address16),
+                        },
+                    };
+                    if actual != expected {
+                        mismatches.push(format!(
+                            "{} is ${:02x}, expected ${:02x}",
+                            name, actual, expected
+                        ));
+                    }
+                }
+                if !mismatches.is_empty() {
+                    return Err(at_line(mismatches.join(", ")));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn register<'a>(cpu: &'a mut Cpu, name: &str) -> Option<&'a mut u8> {
+    match name {
+        "a" => Some(&mut cpu.a),
+        "x" => Some(&mut cpu.x),
+        "y" => Some(&mut cpu.y),
+        "sp" => Some(&mut cpu.sp),
+        _ => None,
+    }
+}
+
+fn flag(name: &str) -> Option<u8> {
+    match name {
+        "n" => Some(NEGATIVE),
+        "v" => Some(OVERFLOW),
+        "d" => Some(DECIMAL),
+        "i" => Some(INTERRUPT),
+        "z" => Some(ZERO),
+        "c" => Some(CARRY),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::run_test;
+    use super::TestConfig;
+    use crate::runner::assemble;
+
+    const PROGRAM: &str = "
+        .org $8000
+        .test \"doubles\"
+            .given a=$21, c=1
+            .call double
+            .expect a=$42, c=0, z=0
+        .endtest
+        .test \"stores\"
+            .given a=$ff, $10=$00
+            .call store
+            .expect $10=$ff, result=$ff, x=$00
+        .endtest
+        .test \"wrong\"
+            .given a=$01
+            .call double
+            .expect a=$03
+        .endtest
+        .test \"runaway\"
+            .call forever
+        .endtest
+        .test \"lost\"
+            .call wander
+            .expect a=$01
+        .endtest
+        double:
+            asl
+            rts
+        store:
+            sta $10
+            sta result
+            rts
+        forever:
+            bra forever
+        wander:
+            lda #$01
+            jmp $2000
+        result:
+            .byte $00
+    ";
+
+    #[test]
+    fn test_run_test() {
+        let (program, segments) = if let Ok(assembled) = assemble(PROGRAM, None) {
+            assembled
+        } else {
+            panic!("Did not expect the program to fail to assemble");
+        };
+
+        // The tests don't end up in the image
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].data.len(), 16);
+
+        let results: Vec<Result<(), String>> = program
+            .tests
+            .iter()
+            .map(|test| run_test(&program, &segments, test, 1000))
+            .collect();
+        assert_eq!(results[0], Ok(()));
+        assert_eq!(results[1], Ok(()));
+        assert_eq!(
+            results[2],
+            Err("Line 16: a is $02, expected $03".to_string())
+        );
+        if let Err(failure) = &results[3] {
+            assert!(failure.contains("still running"));
+        } else {
+            panic!("Did not expect the runaway test to pass");
+        }
+
+        // Running into the zeroes at $2000 is a brk, not a return
+        assert_eq!(
+            results[4],
+            Err("Line 22: wander didn't return: brk at $2000".to_string())
+        );
+    }
+
+    #[test]
+    fn test_test_config() {
+        let mut args: Vec<String> = ["kasm", "test", "--cycles", "50", "a.s", "doubles"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        if let Ok(config) = TestConfig::new(&mut args) {
+            assert_eq!(config.source_file, "a.s");
+            assert_eq!(config.filter, Some("doubles".to_string()));
+            assert_eq!(config.limit, 50);
+        } else {
+            panic!("Did not expect to fail parsing the arguments");
+        }
+    }
+}
@@ -20,7 +20,11 @@ fn generate_simple_machine_code() {
                 mnemonic: kasm::instructions::Mnemonic::LDA,
                 address_mode: kasm::instructions::AddressMode::Absolute,
                 value: kasm::instructions::Value::U8(0xab),
+                wide: false,
+                zeropage: 0,
             }),
+            file: 0,
+            line: 1,
         }
     );
     let mc = match kasm::pass2::pass2(is, p) {
@@ -32,3 +36,100 @@ fn generate_simple_machine_code() {
     assert_eq!(mc[0x1234], 0xad);
     assert_eq!(mc[0x1235], 0xab);
 }
+
+#[test]
+fn generate_out_of_order_machine_code() {
+    let is = kasm::instructions::generate_instruction_set();
+    let mut p = kasm::pass1::Program::new();
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0003,
+        content: kasm::pass1::Content::Data(vec![0x03, 0x04]),
+        file: 0,
+        line: 2,
+    });
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0000,
+        content: kasm::pass1::Content::Data(vec![0x01, 0x02]),
+        file: 0,
+        line: 1,
+    });
+    let mc = match kasm::pass2::pass2(is, p) {
+        Ok(mc) => mc,
+        Err(err) => panic!("This should never error {}", err.1),
+    };
+    assert_eq!(mc, vec![0x01, 0x02, 0x00, 0x03, 0x04]);
+}
+
+#[test]
+fn generate_overlapping_machine_code() {
+    let is = kasm::instructions::generate_instruction_set();
+    let mut p = kasm::pass1::Program::new();
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x1000,
+        content: kasm::pass1::Content::Data(vec![0x01, 0x02]),
+        file: 0,
+        line: 4,
+    });
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x1001,
+        content: kasm::pass1::Content::Data(vec![0x03]),
+        file: 0,
+        line: 9,
+    });
+    match kasm::pass2::pass2(is, p) {
+        Ok(_) => panic!("Did not expect overlapping lines to work"),
+        Err(err) => assert_eq!(err.1, "Line 9 overlaps line 4 at $1001"),
+    };
+}
+
+#[test]
+fn generate_filled_machine_code() {
+    let is = kasm::instructions::generate_instruction_set();
+    let mut p = kasm::pass1::Program::new();
+    p.fill = Some(0xff);
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0001,
+        content: kasm::pass1::Content::Data(vec![0x01]),
+        file: 0,
+        line: 2,
+    });
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0003,
+        content: kasm::pass1::Content::Data(vec![0x03]),
+        file: 0,
+        line: 3,
+    });
+    let mc = match kasm::pass2::pass2(is, p) {
+        Ok(mc) => mc,
+        Err(err) => panic!("This should never error {}", err.1),
+    };
+    assert_eq!(mc, vec![0xff, 0x01, 0xff, 0x03]);
+}
+
+#[test]
+fn generate_zero_sum_machine_code() {
+    let is = kasm::instructions::generate_instruction_set();
+    let mut p = kasm::pass1::Program::new();
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0000,
+        content: kasm::pass1::Content::Data(vec![0x01, 0x02]),
+        file: 0,
+        line: 1,
+    });
+    p.code.push(kasm::pass1::CodeTableEntry {
+        address: 0x0002,
+        content: kasm::pass1::Content::Checksum(kasm::checksum::Checksum {
+            kind: kasm::checksum::ChecksumKind::Sum8,
+            start: 0x0000,
+            end: 0x0003,
+            adjust: true,
+        }),
+        file: 0,
+        line: 2,
+    });
+    let mc = match kasm::pass2::pass2(is, p) {
+        Ok(mc) => mc,
+        Err(err) => panic!("This should never error {}", err.1),
+    };
+    assert_eq!(mc, vec![0x01, 0x02, 0xfd]);
+}